@@ -0,0 +1,183 @@
+#![allow(clippy::needless_return)]
+
+use proc_macro::TokenStream;
+use proc_macro2::{Ident, Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, LitStr, Member};
+
+/// Implements `rustpm_orm::database::Id`.
+///
+/// A field marked `#[id]` becomes the record key, otherwise every insert gets a
+/// fresh UUID just like `database::gen_id`, or a ULID with `#[id(strategy = "ulid")]`
+/// on the struct.
+#[proc_macro_derive(Id, attributes(id))]
+pub fn derive_id(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_id(&input) {
+        Err(err) => return err.to_compile_error().into(),
+        Ok(tokens) => return tokens.into(),
+    }
+}
+
+fn expand_id(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let body = match id_field(input)? {
+        None => {
+            let strategy = id_strategy(input)?;
+            quote! { ::rustpm_orm::database::IdStrategy::#strategy.generate() }
+        }
+        Some(member) => quote! { ::std::string::ToString::to_string(&self.#member) },
+    };
+
+    return Ok(quote! {
+        impl #impl_generics ::rustpm_orm::database::Id for #name #ty_generics #where_clause {
+            fn gen_id(&self) -> ::std::string::String {
+                #body
+            }
+        }
+    });
+}
+
+fn id_strategy(input: &DeriveInput) -> syn::Result<Ident> {
+    let mut strategy = Ident::new("Uuid", Span::call_site());
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("id")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("strategy") {
+                let value: LitStr = meta.value()?.parse()?;
+                let variant = match value.value().as_str() {
+                    "uuid" => "Uuid",
+                    "ulid" => "Ulid",
+                    _ => return Err(syn::Error::new_spanned(value, "expected \"uuid\" or \"ulid\"")),
+                };
+                strategy = Ident::new(variant, value.span());
+                return Ok(());
+            }
+            return Err(meta.error("expected `strategy = \"...\"`"));
+        })?;
+    }
+    return Ok(strategy);
+}
+
+fn id_field(input: &DeriveInput) -> syn::Result<Option<Member>> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => return Ok(None),
+    };
+
+    let mut found: Option<Member> = None;
+    for (i, field) in fields.iter().enumerate() {
+        let attr = match field.attrs.iter().find(|attr| attr.path().is_ident("id")) {
+            None => continue,
+            Some(attr) => attr,
+        };
+        if found.is_some() {
+            return Err(syn::Error::new_spanned(attr, "only one field can be marked #[id]"));
+        }
+        found = Some(match &field.ident {
+            None => Member::from(i),
+            Some(ident) => Member::from(ident.clone()),
+        });
+    }
+    return Ok(found);
+}
+
+/// Implements `rustpm_orm::database::Model`.
+///
+/// `#[model(collection = "users")]` names the tree (snake_case type name by
+/// default) and `#[model(key)]` marks the key field, falling back to a field
+/// called `id`.
+#[proc_macro_derive(Model, attributes(model))]
+pub fn derive_model(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_model(&input) {
+        Err(err) => return err.to_compile_error().into(),
+        Ok(tokens) => return tokens.into(),
+    }
+}
+
+fn expand_model(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut collection = snake_case(&name.to_string());
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("model")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("collection") {
+                let value: LitStr = meta.value()?.parse()?;
+                collection = value.value();
+                return Ok(());
+            }
+            return Err(meta.error("expected `collection = \"...\"`"));
+        })?;
+    }
+
+    let key = model_key(input)?;
+    return Ok(quote! {
+        impl #impl_generics ::rustpm_orm::database::Model for #name #ty_generics #where_clause {
+            const COLLECTION: &'static str = #collection;
+
+            fn key(&self) -> ::std::string::String {
+                ::std::string::ToString::to_string(&self.#key)
+            }
+        }
+    });
+}
+
+fn model_key(input: &DeriveInput) -> syn::Result<Member> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => return Err(syn::Error::new_spanned(&input.ident, "Model can only be derived for structs")),
+    };
+
+    let mut marked: Option<Member> = None;
+    let mut named_id: Option<Member> = None;
+    for (i, field) in fields.iter().enumerate() {
+        let member = match &field.ident {
+            None => Member::from(i),
+            Some(ident) => Member::from(ident.clone()),
+        };
+        if field.ident.as_ref().is_some_and(|ident| ident == "id") {
+            named_id = Some(member.clone());
+        }
+
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("model")) {
+            attr.parse_nested_meta(|meta| {
+                if !meta.path.is_ident("key") {
+                    return Err(meta.error("expected `key`"));
+                }
+                if marked.is_some() {
+                    return Err(meta.error("only one field can be marked #[model(key)]"));
+                }
+                marked = Some(member.clone());
+                return Ok(());
+            })?;
+        }
+    }
+
+    match marked.or(named_id) {
+        None => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "mark the key field with #[model(key)] or name it `id`",
+            ))
+        }
+        Some(member) => return Ok(member),
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let mut out = String::with_capacity(name.len() + 4);
+    for (i, ch) in name.chars().enumerate() {
+        if ch.is_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+            out.extend(ch.to_lowercase());
+        } else {
+            out.push(ch);
+        }
+    }
+    return out;
+}
@@ -0,0 +1,14 @@
+#![allow(clippy::needless_return)]
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match rustpm_orm::database::cli::run(&args, &mut std::io::stdout().lock()) {
+        Err(err) => {
+            eprintln!("rustpm: {}", err);
+            return ExitCode::FAILURE;
+        }
+        Ok(()) => return ExitCode::SUCCESS,
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// How often one key was read and written while tracking was on, see `DBManager::hot_keys`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotKey {
+    pub collection: String,
+    pub key: String,
+    pub reads: u64,
+    pub writes: u64,
+}
+
+impl HotKey {
+    pub fn accesses(&self) -> u64 {
+        return self.reads + self.writes;
+    }
+}
+
+// reads and writes, per collection and key
+type Counts = HashMap<(String, Vec<u8>), (u64, u64)>;
+
+/// Per-key read and write counters, kept only while switched on with
+/// `DBManager::track_access`.
+#[derive(Clone, Default)]
+pub(super) struct AccessTracker {
+    enabled: Arc<AtomicBool>,
+    counts: Arc<Mutex<Counts>>,
+}
+
+impl fmt::Debug for AccessTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "AccessTracker({})", self.enabled.load(Ordering::Relaxed));
+    }
+}
+
+impl AccessTracker {
+    pub(super) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub(super) fn read(&self, collection: &str, key: &[u8]) {
+        self.count(collection, key, |counts| counts.0 += 1);
+    }
+
+    pub(super) fn write(&self, collection: &str, key: &[u8]) {
+        self.count(collection, key, |counts| counts.1 += 1);
+    }
+
+    fn count(&self, collection: &str, key: &[u8], f: impl FnOnce(&mut (u64, u64))) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut counts = self.counts.lock().unwrap();
+        f(counts.entry((collection.to_string(), key.to_vec())).or_default());
+    }
+
+    /// The `n` most accessed keys, of one collection or of all of them, busiest first.
+    pub(super) fn hottest(&self, collection: Option<&str>, n: usize) -> Vec<HotKey> {
+        let counts = self.counts.lock().unwrap();
+        let mut hot: Vec<HotKey> = counts
+            .iter()
+            .filter(|((name, _), _)| collection.is_none_or(|collection| name == collection))
+            .map(|((name, key), (reads, writes))| HotKey {
+                collection: name.clone(),
+                key: String::from_utf8_lossy(key).into_owned(),
+                reads: *reads,
+                writes: *writes,
+            })
+            .collect();
+        hot.sort_by(|a, b| b.accesses().cmp(&a.accesses()).then_with(|| a.key.cmp(&b.key)));
+        hot.truncate(n);
+        return hot;
+    }
+
+    pub(super) fn reset(&self) {
+        self.counts.lock().unwrap().clear();
+    }
+
+    pub(super) fn forget(&self, prefix: &str) {
+        self.counts.lock().unwrap().retain(|(collection, _), _| !collection.starts_with(prefix));
+    }
+}
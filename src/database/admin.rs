@@ -0,0 +1,288 @@
+//! A small read-only HTTP server for looking into a running database from a browser,
+//! see `DBManager::serve_admin`. Built with the `admin` feature.
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::json::Value;
+
+use super::format::{self, render};
+use super::registry::existing_tree;
+use super::{DBError, DBErrorKind, DBManager};
+
+// how often the listener checks whether it should stop
+const POLL: Duration = Duration::from_millis(50);
+// how long a client gets to send its whole request, and how long that may be
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST: u64 = 16 * 1024;
+// records per page when the request does not say
+const PAGE: usize = 100;
+
+fn failed(err: std::io::Error) -> DBError {
+    return DBError::with_source(DBErrorKind::Other("admin server".to_string()), err);
+}
+
+/// Serves read-only endpoints over a database until dropped:
+///
+/// - `GET /health` answers `{"status":"ok"}` while the database can be read
+/// - `GET /stats` the size on disk and records per collection
+/// - `GET /collections/<name>?limit=<n>&after=<id>` a page of records, in key order,
+///   with the id to pass as `after` for the next one
+/// - `GET /collections/<name>/<id>` one record
+///
+/// Records are shown the way the `rustpm` tool shows them. Requests are answered one
+/// at a time, which is plenty for a person with a browser but nothing more; a client
+/// gets 5 seconds and 16 KiB for its request before it is cut off.
+///
+/// There is no authentication: anyone who can reach the address can read every
+/// record, so bind it to localhost or put it behind something that checks who asks.
+pub struct AdminServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    error: Arc<Mutex<Option<String>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl AdminServer {
+    pub(super) fn start(db: DBManager, addr: impl ToSocketAddrs) -> Result<Self, DBError> {
+        let listener = TcpListener::bind(addr).map_err(failed)?;
+        listener.set_nonblocking(true).map_err(failed)?;
+        let addr = listener.local_addr().map_err(failed)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let error = Arc::new(Mutex::new(None));
+
+        let (running, failure) = (stop.clone(), error.clone());
+        let handle = thread::Builder::new()
+            .name("rustpm-admin".to_string())
+            .spawn(move || {
+                while !running.load(Ordering::SeqCst) {
+                    match listener.accept() {
+                        Err(err) if err.kind() == ErrorKind::WouldBlock => thread::sleep(POLL),
+                        // such as running out of file descriptors, which does not clear at once
+                        Err(err) => {
+                            *failure.lock().unwrap() = Some(err.to_string());
+                            thread::sleep(POLL);
+                        }
+                        Ok((stream, _)) => {
+                            if let Err(err) = answer(&db, stream) {
+                                *failure.lock().unwrap() = Some(err.to_string());
+                            }
+                        }
+                    }
+                }
+            })
+            .expect("failed to spawn admin server");
+        return Ok(AdminServer {
+            addr,
+            stop,
+            error,
+            handle: Some(handle),
+        });
+    }
+
+    /// Where the server listens, useful after binding port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        return self.addr;
+    }
+
+    /// Why the last connection failed, if one did.
+    pub fn error(&self) -> Option<String> {
+        return self.error.lock().unwrap().clone();
+    }
+}
+
+impl Drop for AdminServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+struct Response {
+    status: &'static str,
+    body: Value,
+}
+
+fn respond(status: &'static str, body: Value) -> Response {
+    return Response { status, body };
+}
+
+fn message(status: &'static str, text: impl Into<String>) -> Response {
+    let mut body = BTreeMap::new();
+    body.insert("error".to_string(), Value::String(text.into()));
+    return respond(status, Value::Object(body));
+}
+
+// the client's side of a connection, failing reads once its time is up
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    at: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.at.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(ErrorKind::TimedOut, "client took too long to send its request"));
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        return self.stream.read(buf);
+    }
+}
+
+fn answer(db: &DBManager, stream: TcpStream) -> Result<(), DBError> {
+    stream.set_nonblocking(false).map_err(failed)?;
+    let deadline = Deadline {
+        stream: &stream,
+        at: Instant::now() + READ_TIMEOUT,
+    };
+    let mut reader = BufReader::new(deadline.take(MAX_REQUEST));
+    let mut request = String::new();
+    reader.read_line(&mut request).map_err(failed)?;
+    // the headers are read and ignored, the request line says all we need
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).map_err(failed)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let too_long = reader.get_ref().limit() == 0;
+    let response = match request.split_whitespace().collect::<Vec<_>>()[..] {
+        _ if too_long => message("431 Request Header Fields Too Large", "the request is too long"),
+        ["GET", target, _] => route(db, target).unwrap_or_else(|err| match err.status_code() {
+            404 => message("404 Not Found", err.to_string()),
+            _ => message("500 Internal Server Error", err.to_string()),
+        }),
+        [_, _, _] => message("405 Method Not Allowed", "the admin server is read-only"),
+        _ => message("400 Bad Request", "malformed request line"),
+    };
+    let body = response.body.to_string();
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        body.len()
+    );
+    let mut stream = &stream;
+    stream.write_all(head.as_bytes()).map_err(failed)?;
+    stream.write_all(body.as_bytes()).map_err(failed)?;
+    stream.flush().map_err(failed)?;
+    if too_long {
+        // closing on what is left unread would reset the connection before the client
+        // gets to read the response, so the rest is read and dropped, within limits
+        stream.shutdown(Shutdown::Write).map_err(failed)?;
+        let mut rest = reader.into_inner().into_inner();
+        rest.at = Instant::now() + READ_TIMEOUT;
+        let _ = io::copy(&mut rest.take(MAX_REQUEST), &mut io::sink());
+    }
+    return Ok(());
+}
+
+fn route(db: &DBManager, target: &str) -> Result<Response, DBError> {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let segments: Vec<String> = path.split('/').filter(|part| !part.is_empty()).map(decode).collect();
+    let params: BTreeMap<String, String> = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (decode(key), decode(value)))
+        .collect();
+
+    match segments.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["health"] => {
+            db.stats()?;
+            let mut body = BTreeMap::new();
+            body.insert("status".to_string(), Value::String("ok".to_string()));
+            return Ok(respond("200 OK", Value::Object(body)));
+        }
+        ["stats"] => {
+            let stats = db.stats()?;
+            let mut body = BTreeMap::new();
+            body.insert("size_on_disk".to_string(), Value::UInt(stats.size_on_disk));
+            body.insert("default_records".to_string(), Value::UInt(stats.default_records as u64));
+            let collections = stats.collections.into_iter().map(|(name, records)| (name, Value::UInt(records as u64)));
+            body.insert("collections".to_string(), Value::Object(collections.collect()));
+            body.insert("was_recovered".to_string(), Value::Bool(stats.was_recovered));
+            return Ok(respond("200 OK", Value::Object(body)));
+        }
+        ["collections", name] => return list(db, name, &params),
+        ["collections", name, id] => {
+            let (tree, format) = open(db, name)?;
+            match tree.get(id)? {
+                None => return Err(DBError::new(DBErrorKind::NotFound(format!("{} in {}", id, name)))),
+                Some(bytes) => return Ok(respond("200 OK", render(id.as_bytes(), &bytes, format, db.shared.schemas.get(name).as_ref()))),
+            }
+        }
+        _ => return Err(DBError::new(DBErrorKind::NotFound(format!("no endpoint at {}", path)))),
+    }
+}
+
+// only collections that exist, so a mistyped name in the address bar creates nothing,
+// and without resolving their format, which could record one
+fn open(db: &DBManager, name: &str) -> Result<(sled::Tree, super::Format), DBError> {
+    let tree = match existing_tree(&db.conn, name)? {
+        Some(tree) if !name.starts_with("__") => tree,
+        _ => return Err(DBError::new(DBErrorKind::NotFound(format!("collection {}", name)))),
+    };
+    let format = format::recorded(&db.conn, &tree)?;
+    return Ok((tree, format));
+}
+
+fn list(db: &DBManager, name: &str, params: &BTreeMap<String, String>) -> Result<Response, DBError> {
+    let (tree, format) = open(db, name)?;
+    let schema = db.shared.schemas.get(name);
+    let limit = match params.get("limit").map(|limit| limit.parse::<usize>()) {
+        None => PAGE,
+        // an empty page could not say where the next one starts
+        Some(Err(_)) | Some(Ok(0)) => return Ok(message("400 Bad Request", "limit must be a number above 0")),
+        Some(Ok(limit)) => limit,
+    };
+    let entries = match params.get("after") {
+        None => tree.iter(),
+        Some(after) => tree.range::<&[u8], _>((std::ops::Bound::Excluded(after.as_bytes()), std::ops::Bound::Unbounded)),
+    };
+
+    let mut records = Vec::new();
+    let mut next = Value::Null;
+    for entry in entries {
+        let (id, bytes) = entry?;
+        if records.len() == limit {
+            if let Some(Value::Object(last)) = records.last() {
+                next = last.get("id").cloned().unwrap_or(Value::Null);
+            }
+            break;
+        }
+        records.push(render(&id, &bytes, format, schema.as_ref()));
+    }
+    let mut body = BTreeMap::new();
+    body.insert("records".to_string(), Value::Array(records));
+    body.insert("next".to_string(), next);
+    return Ok(respond("200 OK", Value::Object(body)));
+}
+
+// undoes the percent-encoding browsers apply to paths and query strings
+fn decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match (bytes[i], hex.and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    return String::from_utf8_lossy(&out).into_owned();
+}
@@ -0,0 +1,61 @@
+use std::ops::Add;
+
+/// A numeric field type `Collection::aggregate` can total.
+pub trait Number: Copy + PartialOrd + Add<Output = Self> + Default {
+    fn to_f64(self) -> f64;
+}
+
+macro_rules! number {
+    ($($ty:ty),*) => {
+        $(impl Number for $ty {
+            fn to_f64(self) -> f64 {
+                return self as f64;
+            }
+        })*
+    };
+}
+
+number!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64);
+
+/// Sum, minimum and maximum of a field over a set of records, see `Collection::aggregate`.
+/// `min` and `max` are `None` when there were no records.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aggregate<N> {
+    pub count: usize,
+    pub sum: N,
+    pub min: Option<N>,
+    pub max: Option<N>,
+}
+
+impl<N: Number> Default for Aggregate<N> {
+    fn default() -> Self {
+        return Aggregate {
+            count: 0,
+            sum: N::default(),
+            min: None,
+            max: None,
+        };
+    }
+}
+
+impl<N: Number> Aggregate<N> {
+    /// The average, `None` when there were no records.
+    pub fn mean(&self) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        return Some(self.sum.to_f64() / self.count as f64);
+    }
+
+    pub(super) fn add(&mut self, value: N) {
+        self.count += 1;
+        self.sum = self.sum + value;
+        // comparisons against NaN are false, so a NaN never becomes the minimum or maximum
+        if self.min.is_none_or(|min| value < min) {
+            self.min = Some(value);
+        }
+        if self.max.is_none_or(|max| value > max) {
+            self.max = Some(value);
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::ops::Bound;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sled::IVec;
+
+use super::{DBError, DBManager, Id, InsertOutcome, UpsertOutcome};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+const WORKERS: usize = 4;
+
+// records a `RecordStream` reads per trip to the blocking pool, which bounds how
+// many it holds at once
+const STREAM_BATCH: usize = 256;
+
+// a handful of threads dedicated to blocking sled calls, the same role
+// tokio's spawn_blocking pool plays, but usable from any executor
+fn pool() -> &'static Sender<Job> {
+    static POOL: OnceLock<Sender<Job>> = OnceLock::new();
+    return POOL.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..WORKERS {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("rustpm-blocking-{}", i))
+                .spawn(move || loop {
+                    let job = match receiver.lock().unwrap().recv() {
+                        Err(_) => return,
+                        Ok(job) => job,
+                    };
+                    job();
+                })
+                .expect("failed to spawn blocking worker");
+        }
+        return sender;
+    });
+}
+
+struct Shared<R> {
+    result: Option<thread::Result<R>>,
+    waker: Option<Waker>,
+}
+
+/// Resolves with the result of a closure run on the blocking pool, the pool
+/// `AsyncDBManager` uses rather than tokio's.
+pub struct Blocking<R> {
+    shared: Arc<Mutex<Shared<R>>>,
+}
+
+pub fn spawn_blocking<R, F>(f: F) -> Blocking<R>
+where
+    R: Send + 'static,
+    F: FnOnce() -> R + Send + 'static,
+{
+    let shared = Arc::new(Mutex::new(Shared {
+        result: None,
+        waker: None,
+    }));
+
+    let task = shared.clone();
+    let job: Job = Box::new(move || {
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        let mut state = task.lock().unwrap();
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    });
+    pool().send(job).expect("blocking pool has shut down");
+
+    return Blocking { shared };
+}
+
+impl<R> Future for Blocking<R> {
+    type Output = R;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<R> {
+        let mut state = self.shared.lock().unwrap();
+        match state.result.take() {
+            Some(Ok(result)) => return Poll::Ready(result),
+            // surface a panic from the closure on the awaiting task
+            Some(Err(payload)) => panic::resume_unwind(payload),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+/// `DBManager` with every operation moved off the calling task.
+///
+/// This is not tied to tokio: tokio is not a dependency, so instead of its
+/// `spawn_blocking` the calls run on a pool of 4 threads of our own, and the futures
+/// work under any executor, tokio included. At most 4 calls run at once, whatever the
+/// runtime; the rest wait their turn.
+#[derive(Debug, Clone)]
+pub struct AsyncDBManager {
+    db: DBManager,
+}
+
+impl From<DBManager> for AsyncDBManager {
+    fn from(db: DBManager) -> Self {
+        return AsyncDBManager { db };
+    }
+}
+
+impl AsyncDBManager {
+    pub async fn new(database_name: String) -> Result<AsyncDBManager, DBError> {
+        let db = spawn_blocking(move || DBManager::new(database_name)).await?;
+        return Ok(AsyncDBManager { db });
+    }
+
+    pub fn inner(&self) -> &DBManager {
+        return &self.db;
+    }
+
+    /// Runs arbitrary blocking work against the database, e.g. on a collection.
+    pub async fn run<R, F>(&self, f: F) -> R
+    where
+        R: Send + 'static,
+        F: FnOnce(&DBManager) -> R + Send + 'static,
+    {
+        let db = self.db.clone();
+        return spawn_blocking(move || f(&db)).await;
+    }
+
+    pub async fn insert_data<T>(&self, data: T) -> Result<String, DBError>
+    where
+        T: DeserializeOwned + Serialize + Id + Send + 'static,
+    {
+        return self.run(move |db| db.insert_data(data)).await;
+    }
+
+    pub async fn insert_data_with_outcome<T>(&self, data: T) -> Result<InsertOutcome<T>, DBError>
+    where
+        T: DeserializeOwned + Serialize + Id + Send + 'static,
+    {
+        return self.run(move |db| db.insert_data_with_outcome(data)).await;
+    }
+
+    pub async fn insert_many<T>(&self, records: Vec<T>) -> Result<Vec<String>, DBError>
+    where
+        T: Serialize + Id + Send + 'static,
+    {
+        return self.run(move |db| db.insert_many(records)).await;
+    }
+
+    pub async fn get_by_id<T>(&self, id: String) -> Result<T, DBError>
+    where
+        T: DeserializeOwned + Serialize + Id + Send + 'static,
+    {
+        return self.run(move |db| db.get_by_id(id)).await;
+    }
+
+    pub async fn get_all<T>(&self) -> Result<Vec<T>, DBError>
+    where
+        T: DeserializeOwned + Serialize + Id + Send + 'static,
+    {
+        return self.run(|db| db.get_all()).await;
+    }
+
+    pub async fn exists(&self, id: String) -> Result<bool, DBError> {
+        return self.run(move |db| db.exists(id)).await;
+    }
+
+    pub async fn update_by_id<T>(&self, id: String, data: T) -> Result<(), DBError>
+    where
+        T: Serialize + Id + Send + 'static,
+    {
+        return self.run(move |db| db.update_by_id(id, data)).await;
+    }
+
+    pub async fn upsert<T>(&self, id: String, data: T) -> Result<UpsertOutcome, DBError>
+    where
+        T: Serialize + Id + Send + 'static,
+    {
+        return self.run(move |db| db.upsert(id, data)).await;
+    }
+
+    pub async fn delete_by_id<T>(&self, id: String) -> Result<Option<T>, DBError>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        return self.run(move |db| db.delete_by_id(id)).await;
+    }
+
+    /// Waits until everything written so far is durable on disk.
+    pub async fn flush(&self) -> Result<usize, DBError> {
+        return self.db.flush_async().await;
+    }
+
+    /// Streams the default tree's records with their ids, in key order, see `RecordStream`.
+    pub fn stream<T>(&self) -> RecordStream<T> {
+        return RecordStream::new(self.db.clone(), None);
+    }
+
+    /// Streams the records of collection `name`, see `RecordStream`.
+    pub fn stream_collection<T>(&self, name: impl Into<String>) -> RecordStream<T> {
+        return RecordStream::new(self.db.clone(), Some(name.into()));
+    }
+}
+
+type Filter<T> = Arc<dyn Fn(&T) -> bool + Send + Sync>;
+type Item<T> = Result<(IVec, T), DBError>;
+
+// what one trip to the pool brought back: the records, the last key read, and
+// whether the collection ran out
+type Batch<T> = (Vec<Item<T>>, Option<IVec>, bool);
+
+/// Records read in batches on the blocking pool as they are consumed, so a large
+/// collection can be forwarded, e.g. as a chunked response, without holding it all.
+/// Items are like those of `Collection::iter`: a record that fails to decode is an
+/// `Err` and the stream goes on.
+///
+/// `poll_next` has the shape of `futures::Stream::poll_next`, so
+/// `futures::stream::poll_fn(move |cx| records.poll_next(cx))` makes it one.
+pub struct RecordStream<T> {
+    db: DBManager,
+    collection: Option<String>,
+    filter: Option<Filter<T>>,
+    after: Option<IVec>,
+    buffered: VecDeque<Item<T>>,
+    pending: Option<Blocking<Batch<T>>>,
+    done: bool,
+}
+
+impl<T> RecordStream<T> {
+    fn new(db: DBManager, collection: Option<String>) -> Self {
+        return RecordStream {
+            db,
+            collection,
+            filter: None,
+            after: None,
+            buffered: VecDeque::new(),
+            pending: None,
+            done: false,
+        };
+    }
+
+    /// Only yields records `predicate` accepts, tested on the pool as they are read.
+    pub fn matching<P>(mut self, predicate: P) -> Self
+    where
+        P: Fn(&T) -> bool + Send + Sync + 'static,
+    {
+        self.filter = Some(Arc::new(predicate));
+        return self;
+    }
+}
+
+impl<T: DeserializeOwned + Send + 'static> RecordStream<T> {
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Item<T>>> {
+        loop {
+            if let Some(item) = self.buffered.pop_front() {
+                return Poll::Ready(Some(item));
+            }
+            if self.done {
+                return Poll::Ready(None);
+            }
+            let pending = match &mut self.pending {
+                Some(pending) => pending,
+                None => self.pending.insert(self.fetch()),
+            };
+            match Pin::new(pending).poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready((items, last, exhausted)) => {
+                    self.pending = None;
+                    self.buffered.extend(items);
+                    self.after = last.or(self.after.take());
+                    self.done = exhausted;
+                }
+            }
+        }
+    }
+
+    /// The next record, or `None` once the collection has been read to the end.
+    pub async fn next(&mut self) -> Option<Item<T>> {
+        return std::future::poll_fn(|cx| self.poll_next(cx)).await;
+    }
+
+    fn fetch(&self) -> Blocking<Batch<T>> {
+        let (db, collection, filter, after) = (self.db.clone(), self.collection.clone(), self.filter.clone(), self.after.clone());
+        return spawn_blocking(move || {
+            let records = match &collection {
+                None => db.default_collection::<T>(),
+                Some(name) => match db.collection::<T>(name) {
+                    Err(err) => return (vec![Err(err)], None, true),
+                    Ok(records) => records,
+                },
+            };
+            let entries = match &after {
+                None => {
+                    if let Err(err) = records.expire() {
+                        return (vec![Err(err)], None, true);
+                    }
+                    records.tree.range::<IVec, _>(..)
+                }
+                Some(after) => records.tree.range((Bound::Excluded(after.clone()), Bound::Unbounded)),
+            };
+
+            let mut items = Vec::new();
+            let mut last = None;
+            let mut read = 0;
+            for entry in entries.take(STREAM_BATCH) {
+                read += 1;
+                match &entry {
+                    // a storage error ends the stream, a record that does not decode does not
+                    Err(_) => {
+                        items.push(records.decode_entry(entry));
+                        return (items, last, true);
+                    }
+                    Ok((key, _)) => last = Some(key.clone()),
+                }
+                let item = records.decode_entry(entry);
+                match (&item, &filter) {
+                    (Ok((_, data)), Some(filter)) if !filter(data) => continue,
+                    _ => items.push(item),
+                }
+            }
+            return (items, last, read < STREAM_BATCH);
+        });
+    }
+}
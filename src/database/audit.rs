@@ -0,0 +1,98 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sled::{Db, Tree};
+
+use super::key::push_escaped;
+use super::registry::Registries;
+use super::ttl;
+use super::{DBError, DBErrorKind};
+
+/// What happened to a record, see `AuditEntry`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditOp {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl AuditOp {
+    fn tag(self) -> u8 {
+        match self {
+            AuditOp::Insert => return b'i',
+            AuditOp::Update => return b'u',
+            AuditOp::Delete => return b'd',
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            b'i' => return Some(AuditOp::Insert),
+            b'u' => return Some(AuditOp::Update),
+            b'd' => return Some(AuditOp::Delete),
+            _ => return None,
+        }
+    }
+}
+
+/// One change to a record of a collection with `Collection::enable_audit` on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub id: String,
+    pub op: AuditOp,
+    pub at: SystemTime,
+    /// Who made the change, as set with `Collection::with_actor`.
+    pub actor: Option<String>,
+}
+
+// entries are keyed by the escaped record id and then a number sled hands out in
+// increasing order, so one record's trail is a prefix scan in the order it happened
+fn entry_key(conn: &Db, id: &[u8]) -> Result<Vec<u8>, DBError> {
+    let mut key = Vec::with_capacity(id.len() + 10);
+    push_escaped(&mut key, id);
+    key.extend_from_slice(&conn.generate_id()?.to_be_bytes());
+    return Ok(key);
+}
+
+/// Notes `op` on `id` when `collection` is audited. Entries are written after the
+/// change itself, not in the same transaction.
+pub(super) fn record(
+    conn: &Db,
+    shared: &Registries,
+    collection: &str,
+    id: &[u8],
+    op: AuditOp,
+    actor: Option<&str>,
+) -> Result<(), DBError> {
+    let tree = match shared.audits.get(collection) {
+        None => return Ok(()),
+        Some(tree) => tree,
+    };
+    let mut value = vec![op.tag()];
+    value.extend_from_slice(&ttl::now_millis().to_be_bytes());
+    value.extend_from_slice(actor.unwrap_or("").as_bytes());
+    tree.insert(entry_key(conn, id)?, value)?;
+    return Ok(());
+}
+
+/// Every entry for `id`, oldest first.
+pub(super) fn trail(tree: &Tree, id: &[u8]) -> Result<Vec<AuditEntry>, DBError> {
+    let mut prefix = Vec::with_capacity(id.len() + 2);
+    push_escaped(&mut prefix, id);
+    let mut entries = Vec::new();
+    for value in tree.scan_prefix(prefix).values() {
+        let value = value?;
+        let op = match value.first().copied().and_then(AuditOp::from_tag) {
+            Some(op) if value.len() >= 9 => op,
+            _ => return Err(DBError::new(DBErrorKind::ReadFailed("malformed audit entry".to_string()))),
+        };
+        let millis = u64::from_be_bytes(value[1..9].try_into().unwrap());
+        let actor = String::from_utf8_lossy(&value[9..]).into_owned();
+        entries.push(AuditEntry {
+            id: String::from_utf8_lossy(id).into_owned(),
+            op,
+            at: UNIX_EPOCH + Duration::from_millis(millis),
+            actor: (!actor.is_empty()).then_some(actor),
+        });
+    }
+    return Ok(entries);
+}
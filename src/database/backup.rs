@@ -0,0 +1,226 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use sled::Db;
+
+use super::{DBError, DBErrorKind};
+
+/// What `DBManager::restore_backup` does with the data already in the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestoreMode {
+    /// Adds the archived records, replacing stored ones with the same id.
+    Merge,
+    /// Empties every tree first, so the database ends up exactly as archived.
+    Replace,
+}
+
+enum Item {
+    Tree(Vec<u8>),
+    Entry(Vec<Vec<u8>>),
+}
+
+// the archive is a header followed by every tree sled exports; each tree is its
+// type and name, then its entries (a field count and the fields), then an end marker.
+// every byte string is written as a u64 BE length and the bytes
+const MAGIC: &[u8] = b"rustpm-backup\x01";
+const TREE: u8 = b'T';
+const ENTRY: u8 = b'E';
+const END_TREE: u8 = b'.';
+const END: u8 = b'Z';
+
+fn write_bytes(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    out.write_all(&(bytes.len() as u64).to_be_bytes())?;
+    return out.write_all(bytes);
+}
+
+fn write_archive(db: &Db, out: &mut impl Write) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    for (kind, name, entries) in db.export() {
+        out.write_all(&[TREE])?;
+        write_bytes(out, &kind)?;
+        write_bytes(out, &name)?;
+        for fields in entries {
+            out.write_all(&[ENTRY])?;
+            out.write_all(&(fields.len() as u32).to_be_bytes())?;
+            for field in &fields {
+                write_bytes(out, field)?;
+            }
+        }
+        out.write_all(&[END_TREE])?;
+    }
+    return out.write_all(&[END]);
+}
+
+// the archive is written next to its destination and moved into place once complete,
+// so a failed backup never replaces a good one
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".partial");
+    return PathBuf::from(name);
+}
+
+/// Writes every tree of `db` to a single archive file at `path`.
+pub(super) fn write(db: &Db, path: &Path) -> Result<(), DBError> {
+    let failed = |err: io::Error| DBError::with_source(DBErrorKind::WriteFailed(format!("backup to {}", path.display())), err);
+
+    db.flush()?;
+    let partial = partial_path(path);
+    let written = File::create(&partial).and_then(|file| {
+        let mut out = BufWriter::new(file);
+        write_archive(db, &mut out)?;
+        return out.into_inner().map_err(|err| err.into_error())?.sync_all();
+    });
+    if let Err(err) = written.and_then(|_| fs::rename(&partial, path)) {
+        let _ = fs::remove_file(&partial);
+        return Err(failed(err));
+    }
+    return Ok(());
+}
+
+// like `Db::import`, which panics where this returns the error; every exported
+// entry is its key followed by its value
+fn copy_trees(db: &Db, copy: &Db) -> sled::Result<()> {
+    for (_, name, entries) in db.export() {
+        let tree = copy.open_tree(name)?;
+        for mut fields in entries {
+            let value = match fields.pop() {
+                None => return Err(sled::Error::Unsupported("export entry without a value".to_string())),
+                Some(value) => value,
+            };
+            tree.insert(fields.concat(), value)?;
+        }
+    }
+    return Ok(());
+}
+
+/// Copies every tree of `db` into a new database at `path`, which must not exist yet.
+/// Like a backup, the copy is made next to `path` and only moved there once complete.
+pub(super) fn copy(db: &Db, path: &Path) -> Result<(), DBError> {
+    if path.exists() {
+        return Err(DBError::new(DBErrorKind::Conflict(format!("{} already exists", path.display()))));
+    }
+
+    db.flush()?;
+    let partial = partial_path(path);
+    let _ = fs::remove_dir_all(&partial);
+    // the copy is dropped, and so closed, before it is moved into place
+    let copied = sled::open(&partial).and_then(|copy| {
+        copy_trees(db, &copy)?;
+        return copy.flush();
+    });
+    if let Err(err) = copied {
+        let _ = fs::remove_dir_all(&partial);
+        return Err(DBError::from(err));
+    }
+    if let Err(err) = fs::rename(&partial, path) {
+        let _ = fs::remove_dir_all(&partial);
+        return Err(DBError::with_source(DBErrorKind::WriteFailed(format!("copy to {}", path.display())), err));
+    }
+    return Ok(());
+}
+
+fn damaged(reason: &str) -> DBError {
+    return DBError::new(DBErrorKind::ReadFailed(format!("backup archive is damaged: {}", reason)));
+}
+
+fn read_exact<const N: usize>(input: &mut impl Read) -> Result<[u8; N], DBError> {
+    let mut buf = [0; N];
+    if let Err(err) = input.read_exact(&mut buf) {
+        return Err(DBError::with_source(DBErrorKind::ReadFailed("backup archive ended early".to_string()), err));
+    }
+    return Ok(buf);
+}
+
+fn read_bytes(input: &mut impl Read) -> Result<Vec<u8>, DBError> {
+    let len = u64::from_be_bytes(read_exact(input)?);
+    // read through `take` so a damaged length cannot allocate more than the file holds
+    let mut bytes = Vec::new();
+    let read = input.by_ref().take(len).read_to_end(&mut bytes);
+    if read.is_err() || bytes.len() as u64 != len {
+        return Err(damaged("truncated value"));
+    }
+    return Ok(bytes);
+}
+
+// walks the archive, handing every tree and entry to `visit` in order
+fn read_archive<F>(path: &Path, mut visit: F) -> Result<(), DBError>
+where
+    F: FnMut(Item) -> Result<(), DBError>,
+{
+    let file = match File::open(path) {
+        Err(err) => {
+            return Err(DBError::with_source(
+                DBErrorKind::NotFound(format!("backup {}", path.display())),
+                err,
+            ))
+        }
+        Ok(file) => file,
+    };
+    let mut input = BufReader::new(file);
+    if read_exact::<{ MAGIC.len() }>(&mut input)? != MAGIC {
+        return Err(damaged("not a backup archive"));
+    }
+
+    loop {
+        match read_exact::<1>(&mut input)?[0] {
+            END => return Ok(()),
+            TREE => {}
+            _ => return Err(damaged("expected a tree")),
+        }
+        if read_bytes(&mut input)? != b"tree" {
+            return Err(damaged("unknown collection type"));
+        }
+        visit(Item::Tree(read_bytes(&mut input)?))?;
+
+        loop {
+            match read_exact::<1>(&mut input)?[0] {
+                END_TREE => break,
+                ENTRY => {}
+                _ => return Err(damaged("expected an entry")),
+            }
+            let count = u32::from_be_bytes(read_exact(&mut input)?);
+            let mut fields = Vec::new();
+            for _ in 0..count {
+                fields.push(read_bytes(&mut input)?);
+            }
+            if fields.len() != 2 {
+                return Err(damaged("entry is not a key and a value"));
+            }
+            visit(Item::Entry(fields))?;
+        }
+    }
+}
+
+/// Loads an archive written by `write` into `db`, returning how many entries it held.
+///
+/// The whole archive is checked before anything is written, so a damaged one leaves
+/// the database as it was.
+pub(super) fn restore(db: &Db, path: &Path, mode: RestoreMode) -> Result<usize, DBError> {
+    read_archive(path, |_| Ok(()))?;
+
+    if mode == RestoreMode::Replace {
+        // trees are emptied rather than dropped so handles already handed out stay valid
+        for name in db.tree_names() {
+            db.open_tree(name)?.clear()?;
+        }
+    }
+
+    let mut tree = None;
+    let mut restored = 0;
+    read_archive(path, |item| {
+        match item {
+            Item::Tree(name) => tree = Some(db.open_tree(name)?),
+            Item::Entry(mut fields) => {
+                let value = fields.pop().unwrap();
+                let key = fields.pop().unwrap();
+                tree.as_ref().unwrap().insert(key, value)?;
+                restored += 1;
+            }
+        }
+        return Ok(());
+    })?;
+    db.flush()?;
+    return Ok(restored);
+}
@@ -0,0 +1,80 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use super::transaction::{Transaction, TxResult};
+use super::Model;
+
+type Op = Box<dyn Fn(&Transaction<'_>) -> TxResult<()> + Send + Sync>;
+
+/// Writes to any number of `Model` collections that `DBManager::apply` lands together
+/// or not at all, e.g. `batch.insert(user); batch.delete::<Order>(id); db.apply(batch)`.
+///
+/// It is applied as one `DBManager::transaction`, so it keeps and skips the same
+/// bookkeeping and refuses the same collections.
+#[derive(Default)]
+pub struct WriteBatch {
+    collections: Vec<&'static str>,
+    ops: Vec<Op>,
+}
+
+impl fmt::Debug for WriteBatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.debug_struct("WriteBatch").field("collections", &self.collections).field("ops", &self.ops.len()).finish();
+    }
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        return WriteBatch::default();
+    }
+
+    /// Stores `data` under its key, replacing what is there.
+    pub fn insert<T>(&mut self, data: T) -> &mut Self
+    where
+        T: Model + Serialize + Clone + Send + Sync + 'static,
+    {
+        // the transaction may run more than once, so each run writes its own copy
+        return self.push(T::COLLECTION, move |tx| tx.save(data.clone()));
+    }
+
+    /// Deletes the `T` record under `key`, if there is one.
+    pub fn delete<T: Model + 'static>(&mut self, key: impl Into<String>) -> &mut Self {
+        let key = key.into();
+        return self.push(T::COLLECTION, move |tx| {
+            tx.remove::<T>(&key)?;
+            return Ok(());
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        return self.ops.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.ops.is_empty();
+    }
+
+    fn push<F>(&mut self, collection: &'static str, op: F) -> &mut Self
+    where
+        F: Fn(&Transaction<'_>) -> TxResult<()> + Send + Sync + 'static,
+    {
+        if !self.collections.contains(&collection) {
+            self.collections.push(collection);
+        }
+        self.ops.push(Box::new(op));
+        return self;
+    }
+
+    pub(super) fn collections(&self) -> &[&'static str] {
+        return &self.collections;
+    }
+
+    /// Runs every write, in the order they were added.
+    pub(super) fn run(&self, tx: &Transaction<'_>) -> TxResult<()> {
+        for op in &self.ops {
+            op(tx)?;
+        }
+        return Ok(());
+    }
+}
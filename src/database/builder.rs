@@ -0,0 +1,106 @@
+use std::fmt;
+
+use sled::{Config, Mode};
+
+use super::{DBError, DBManager, Format, IdStrategy};
+
+/// Opens a `DBManager` with sled's storage settings tuned, e.g.
+/// `DBManager::builder("app_db").cache_capacity(64 << 20).flush_every_ms(None).open()`.
+/// Anything left unset keeps sled's default.
+pub struct DBManagerBuilder {
+    database_name: String,
+    config: Config,
+    format: Option<Format>,
+    ids: IdStrategy,
+    max_record_size: Option<usize>,
+}
+
+impl fmt::Debug for DBManagerBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("DBManagerBuilder")
+            .field("database_name", &self.database_name)
+            .field("format", &self.format)
+            .field("ids", &self.ids)
+            .field("max_record_size", &self.max_record_size)
+            .finish();
+    }
+}
+
+impl DBManagerBuilder {
+    pub fn new(database_name: impl Into<String>) -> Self {
+        let database_name = database_name.into();
+        return DBManagerBuilder {
+            config: Config::new().path(&database_name),
+            database_name,
+            format: None,
+            ids: IdStrategy::default(),
+            max_record_size: None,
+        };
+    }
+
+    /// Bytes of page cache to keep in memory.
+    pub fn cache_capacity(mut self, bytes: u64) -> Self {
+        self.config = self.config.cache_capacity(bytes);
+        return self;
+    }
+
+    /// zstd compression of stored pages. sled has to be built with its `compression`
+    /// feature for this, opening fails otherwise.
+    pub fn compression(mut self, enabled: bool) -> Self {
+        self.config = self.config.use_compression(enabled);
+        return self;
+    }
+
+    /// How often dirty data is flushed to disk in the background, `None` to only flush
+    /// on `close` or `DBManager::flush`.
+    pub fn flush_every_ms(mut self, every: Option<u64>) -> Self {
+        self.config = self.config.flush_every_ms(every);
+        return self;
+    }
+
+    /// Size of each on-disk segment in bytes; a power of two. Fixed once the database exists.
+    pub fn segment_size(mut self, bytes: usize) -> Self {
+        self.config = self.config.segment_size(bytes);
+        return self;
+    }
+
+    /// Trade disk space against write throughput.
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.config = self.config.mode(mode);
+        return self;
+    }
+
+    /// Removes the database once the last handle is dropped.
+    pub fn temporary(mut self, temporary: bool) -> Self {
+        self.config = self.config.temporary(temporary);
+        return self;
+    }
+
+    /// The format new records are stored in, as with `DBManager::with_format`.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        return self;
+    }
+
+    /// How `DBManager::gen_id` makes ids, as with `DBManager::with_id_strategy`.
+    pub fn id_strategy(mut self, strategy: IdStrategy) -> Self {
+        self.ids = strategy;
+        return self;
+    }
+
+    /// The largest encoded record any collection accepts, as with `DBManager::with_max_record_size`.
+    pub fn max_record_size(mut self, bytes: usize) -> Self {
+        self.max_record_size = Some(bytes);
+        return self;
+    }
+
+    pub fn open(self) -> Result<DBManager, DBError> {
+        let conn = self.config.open()?;
+        let mut db = DBManager::from_conn(conn, self.database_name, self.format, false)?.with_id_strategy(self.ids);
+        if let Some(bytes) = self.max_record_size {
+            db = db.with_max_record_size(bytes);
+        }
+        return Ok(db);
+    }
+}
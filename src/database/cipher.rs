@@ -0,0 +1,449 @@
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::format::Codec;
+use super::{DBError, DBErrorKind};
+
+/// Encrypts stored values; see `Encrypted`.
+///
+/// `ChaCha20Poly1305` is the built-in implementation. Wrap another one (AES-GCM from
+/// a crate of your choice, a hardware key store, ...) by implementing this trait.
+/// `aad` is authenticated but not encrypted: decrypting has to be given the same bytes,
+/// or it fails.
+pub trait Cipher: Clone {
+    fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, DBError>;
+
+    fn decrypt(&self, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>, DBError>;
+}
+
+/// A codec that encrypts whatever `codec` produces, e.g.
+/// `db.collection_with_codec("secrets", Encrypted::new(Format::Bincode, ChaCha20Poly1305::new(key)))`.
+///
+/// Only record values are encrypted: ids, and the values indexes are built from, are
+/// stored as they are. Each value is bound to its collection and id, so one copied
+/// under another id or into another collection fails to decrypt, and only `encode_for`
+/// and `decode_for`, which know both, work.
+#[derive(Debug, Clone)]
+pub struct Encrypted<C, K> {
+    codec: C,
+    cipher: K,
+}
+
+impl<C: Codec, K: Cipher> Encrypted<C, K> {
+    pub fn new(codec: C, cipher: K) -> Self {
+        return Encrypted { codec, cipher };
+    }
+}
+
+fn unbound() -> DBError {
+    return DBError::new(DBErrorKind::Other("encrypted values are bound to where they are stored, use encode_for and decode_for".to_string()));
+}
+
+impl<C: Codec, K: Cipher> Codec for Encrypted<C, K> {
+    fn encode<T: Serialize>(&self, _data: &T) -> Result<Vec<u8>, DBError> {
+        return Err(unbound());
+    }
+
+    fn decode<T: DeserializeOwned>(&self, _bytes: &[u8]) -> Result<T, DBError> {
+        return Err(unbound());
+    }
+
+    fn encode_for<T: Serialize>(&self, collection: &str, id: &[u8], data: &T) -> Result<Vec<u8>, DBError> {
+        return self.cipher.encrypt(&self.codec.encode_for(collection, id, data)?, &aad(collection, id));
+    }
+
+    fn decode_for<T: DeserializeOwned>(&self, collection: &str, id: &[u8], bytes: &[u8]) -> Result<T, DBError> {
+        return self.codec.decode_for(collection, id, &self.cipher.decrypt(bytes, &aad(collection, id))?);
+    }
+}
+
+// a value is authenticated together with where it is stored
+fn aad(collection: &str, id: &[u8]) -> Vec<u8> {
+    return [collection.as_bytes(), b"\0", id].concat();
+}
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// PBKDF2 rounds `ChaCha20Poly1305::from_secret` runs, following OWASP's advice for
+/// PBKDF2-HMAC-SHA256.
+pub const KDF_ROUNDS: u32 = 600_000;
+
+/// ChaCha20-Poly1305 (RFC 8439) with a random nonce per value, stored as
+/// nonce, ciphertext, tag. Key it from a user's secret with `from_secret`, or pass 32
+/// bytes of key material of your own to `new`.
+#[derive(Clone)]
+pub struct ChaCha20Poly1305 {
+    key: [u8; 32],
+}
+
+impl fmt::Debug for ChaCha20Poly1305 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str("ChaCha20Poly1305");
+    }
+}
+
+impl ChaCha20Poly1305 {
+    pub fn new(key: [u8; 32]) -> Self {
+        return ChaCha20Poly1305 { key };
+    }
+
+    /// A cipher keyed from a passphrase or other secret through `derive_key`, with
+    /// `KDF_ROUNDS` rounds. The salt need not be secret, but the same one has to be
+    /// given every time, so generate it once, randomly, and keep it with the data.
+    pub fn from_secret(secret: &[u8], salt: &[u8]) -> Self {
+        return ChaCha20Poly1305::new(derive_key(secret, salt, KDF_ROUNDS));
+    }
+
+    fn seal(&self, nonce: &[u8; NONCE_LEN], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let mut sealed = plaintext.to_vec();
+        chacha20_xor(&self.key, nonce, 1, &mut sealed);
+        let tag = self.tag(nonce, aad, &sealed);
+        sealed.extend_from_slice(&tag);
+        return sealed;
+    }
+
+    fn tag(&self, nonce: &[u8; NONCE_LEN], aad: &[u8], ciphertext: &[u8]) -> [u8; TAG_LEN] {
+        let mut one_time_key = [0; 32];
+        one_time_key.copy_from_slice(&chacha20_block(&self.key, nonce, 0)[..32]);
+
+        let pad = |len: usize| vec![0; (16 - len % 16) % 16];
+        let mut mac_data = Vec::with_capacity(aad.len() + ciphertext.len() + 48);
+        mac_data.extend_from_slice(aad);
+        mac_data.extend_from_slice(&pad(aad.len()));
+        mac_data.extend_from_slice(ciphertext);
+        mac_data.extend_from_slice(&pad(ciphertext.len()));
+        mac_data.extend_from_slice(&(aad.len() as u64).to_le_bytes());
+        mac_data.extend_from_slice(&(ciphertext.len() as u64).to_le_bytes());
+        return poly1305(&one_time_key, &mac_data);
+    }
+}
+
+impl Cipher for ChaCha20Poly1305 {
+    fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, DBError> {
+        // the random bytes of a v4 uuid, skipping the ones holding its version and variant
+        let random = Uuid::new_v4().into_bytes();
+        let mut nonce = [0; NONCE_LEN];
+        nonce[..6].copy_from_slice(&random[..6]);
+        nonce[6..].copy_from_slice(&random[9..15]);
+
+        let mut stored = nonce.to_vec();
+        stored.extend_from_slice(&self.seal(&nonce, aad, plaintext));
+        return Ok(stored);
+    }
+
+    fn decrypt(&self, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>, DBError> {
+        let failed = || DBError::new(DBErrorKind::ReadFailed("failed to decrypt record".to_string()));
+        if ciphertext.len() < NONCE_LEN + TAG_LEN {
+            return Err(failed());
+        }
+        let (nonce, rest) = ciphertext.split_at(NONCE_LEN);
+        let (body, tag) = rest.split_at(rest.len() - TAG_LEN);
+        let nonce: [u8; NONCE_LEN] = nonce.try_into().unwrap();
+
+        // compared without an early exit so timing says nothing about the expected tag
+        let expected = self.tag(&nonce, aad, body);
+        if expected.iter().zip(tag).fold(0, |diff, (a, b)| diff | (a ^ b)) != 0 {
+            return Err(failed());
+        }
+        let mut plaintext = body.to_vec();
+        chacha20_xor(&self.key, &nonce, 1, &mut plaintext);
+        return Ok(plaintext);
+    }
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    return u32::from_le_bytes(bytes[..4].try_into().unwrap());
+}
+
+fn chacha20_block(key: &[u8; 32], nonce: &[u8; NONCE_LEN], counter: u32) -> [u8; 64] {
+    let mut initial = [0u32; 16];
+    initial[..4].copy_from_slice(&[0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+    for i in 0..8 {
+        initial[4 + i] = le32(&key[i * 4..]);
+    }
+    initial[12] = counter;
+    for i in 0..3 {
+        initial[13 + i] = le32(&nonce[i * 4..]);
+    }
+
+    let mut state = initial;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    let mut block = [0; 64];
+    for i in 0..16 {
+        block[i * 4..i * 4 + 4].copy_from_slice(&state[i].wrapping_add(initial[i]).to_le_bytes());
+    }
+    return block;
+}
+
+fn chacha20_xor(key: &[u8; 32], nonce: &[u8; NONCE_LEN], counter: u32, data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(64).enumerate() {
+        let block = chacha20_block(key, nonce, counter.wrapping_add(i as u32));
+        for (byte, key_byte) in chunk.iter_mut().zip(block.iter()) {
+            *byte ^= key_byte;
+        }
+    }
+}
+
+// poly1305 over 26-bit limbs, following poly1305-donna
+fn poly1305(key: &[u8; 32], message: &[u8]) -> [u8; TAG_LEN] {
+    const MASK: u32 = 0x3ffffff;
+    let r0 = le32(&key[0..]) & 0x3ffffff;
+    let r1 = (le32(&key[3..]) >> 2) & 0x3ffff03;
+    let r2 = (le32(&key[6..]) >> 4) & 0x3ffc0ff;
+    let r3 = (le32(&key[9..]) >> 6) & 0x3f03fff;
+    let r4 = (le32(&key[12..]) >> 8) & 0x00fffff;
+    let (s1, s2, s3, s4) = (r1 * 5, r2 * 5, r3 * 5, r4 * 5);
+    let (mut h0, mut h1, mut h2, mut h3, mut h4) = (0u32, 0u32, 0u32, 0u32, 0u32);
+
+    for chunk in message.chunks(16) {
+        let mut block = [0u8; 17];
+        block[..chunk.len()].copy_from_slice(chunk);
+        // a full block gets its high bit set, a short one is padded with a single 1 byte
+        let hibit = match chunk.len() {
+            16 => 1 << 24,
+            len => {
+                block[len] = 1;
+                0
+            }
+        };
+
+        h0 += le32(&block[0..]) & MASK;
+        h1 += (le32(&block[3..]) >> 2) & MASK;
+        h2 += (le32(&block[6..]) >> 4) & MASK;
+        h3 += (le32(&block[9..]) >> 6) & MASK;
+        h4 += (le32(&block[12..]) >> 8) | hibit;
+
+        let mul = |a: u32, b: u32| a as u64 * b as u64;
+        let d0 = mul(h0, r0) + mul(h1, s4) + mul(h2, s3) + mul(h3, s2) + mul(h4, s1);
+        let mut d1 = mul(h0, r1) + mul(h1, r0) + mul(h2, s4) + mul(h3, s3) + mul(h4, s2);
+        let mut d2 = mul(h0, r2) + mul(h1, r1) + mul(h2, r0) + mul(h3, s4) + mul(h4, s3);
+        let mut d3 = mul(h0, r3) + mul(h1, r2) + mul(h2, r1) + mul(h3, r0) + mul(h4, s4);
+        let mut d4 = mul(h0, r4) + mul(h1, r3) + mul(h2, r2) + mul(h3, r1) + mul(h4, r0);
+
+        h0 = d0 as u32 & MASK;
+        d1 += d0 >> 26;
+        h1 = d1 as u32 & MASK;
+        d2 += d1 >> 26;
+        h2 = d2 as u32 & MASK;
+        d3 += d2 >> 26;
+        h3 = d3 as u32 & MASK;
+        d4 += d3 >> 26;
+        h4 = d4 as u32 & MASK;
+        h0 += (d4 >> 26) as u32 * 5;
+        h1 += h0 >> 26;
+        h0 &= MASK;
+    }
+
+    // fully carry h, then compute h - p and keep it if that did not go negative
+    h2 += h1 >> 26;
+    h1 &= MASK;
+    h3 += h2 >> 26;
+    h2 &= MASK;
+    h4 += h3 >> 26;
+    h3 &= MASK;
+    h0 += (h4 >> 26) * 5;
+    h4 &= MASK;
+    h1 += h0 >> 26;
+    h0 &= MASK;
+
+    let mut g0 = h0.wrapping_add(5);
+    let mut g1 = h1.wrapping_add(g0 >> 26);
+    g0 &= MASK;
+    let mut g2 = h2.wrapping_add(g1 >> 26);
+    g1 &= MASK;
+    let mut g3 = h3.wrapping_add(g2 >> 26);
+    g2 &= MASK;
+    let g4 = h4.wrapping_add(g3 >> 26).wrapping_sub(1 << 26);
+    g3 &= MASK;
+
+    let keep_g = (g4 >> 31).wrapping_sub(1);
+    h0 = (h0 & !keep_g) | (g0 & keep_g);
+    h1 = (h1 & !keep_g) | (g1 & keep_g);
+    h2 = (h2 & !keep_g) | (g2 & keep_g);
+    h3 = (h3 & !keep_g) | (g3 & keep_g);
+    h4 = (h4 & !keep_g) | (g4 & keep_g);
+
+    let words = [
+        h0 | (h1 << 26),
+        (h1 >> 6) | (h2 << 20),
+        (h2 >> 12) | (h3 << 14),
+        (h3 >> 18) | (h4 << 8),
+    ];
+    let mut tag = [0; TAG_LEN];
+    let mut carry = 0u64;
+    for (i, word) in words.iter().enumerate() {
+        let sum = *word as u64 + le32(&key[16 + i * 4..]) as u64 + carry;
+        tag[i * 4..i * 4 + 4].copy_from_slice(&(sum as u32).to_le_bytes());
+        carry = sum >> 32;
+    }
+    return tag;
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5, 0xd807aa98, 0x12835b01,
+    0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc,
+    0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147,
+    0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116, 0x1e376c08,
+    0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208,
+    0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+const SHA256_INITIAL: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+
+fn sha256_compress(state: &mut [u32; 8], block: &[u8]) {
+    let mut w = [0u32; 64];
+    for i in 0..16 {
+        w[i] = u32::from_be_bytes(block[i * 4..i * 4 + 4].try_into().unwrap());
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+        let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for i in 0..64 {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let choice = (e & f) ^ (!e & g);
+        let t1 = h.wrapping_add(s1).wrapping_add(choice).wrapping_add(SHA256_K[i]).wrapping_add(w[i]);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let majority = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(majority);
+        (h, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(t1), c, b, a, t1.wrapping_add(t2));
+    }
+    for (word, add) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *word = word.wrapping_add(add);
+    }
+}
+
+// sha-256 of `message`, carrying on from `state` after `hashed` bytes of whole blocks
+fn sha256_finish(mut state: [u32; 8], hashed: u64, message: &[u8]) -> [u8; 32] {
+    let bits = (hashed + message.len() as u64) * 8;
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&bits.to_be_bytes());
+    for block in padded.chunks(64) {
+        sha256_compress(&mut state, block);
+    }
+
+    let mut digest = [0; 32];
+    for (i, word) in state.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    return digest;
+}
+
+// hmac-sha256 under one key, kept as the states after hashing its inner and outer pads
+struct Hmac {
+    inner: [u32; 8],
+    outer: [u32; 8],
+}
+
+impl Hmac {
+    fn new(key: &[u8]) -> Self {
+        let mut block = [0u8; 64];
+        match key.len() > 64 {
+            true => block[..32].copy_from_slice(&sha256_finish(SHA256_INITIAL, 0, key)),
+            false => block[..key.len()].copy_from_slice(key),
+        }
+        let pad = |byte: u8| {
+            let mut state = SHA256_INITIAL;
+            sha256_compress(&mut state, &block.map(|key_byte| key_byte ^ byte));
+            return state;
+        };
+        return Hmac {
+            inner: pad(0x36),
+            outer: pad(0x5c),
+        };
+    }
+
+    fn mac(&self, message: &[u8]) -> [u8; 32] {
+        let inner = sha256_finish(self.inner, 64, message);
+        return sha256_finish(self.outer, 64, &inner);
+    }
+}
+
+/// PBKDF2-HMAC-SHA256 (RFC 8018): a 32 byte key from `secret` and `salt`, made slow to
+/// guess by running `rounds` rounds, at least one.
+pub fn derive_key(secret: &[u8], salt: &[u8], rounds: u32) -> [u8; 32] {
+    let hmac = Hmac::new(secret);
+    // a 32 byte key is the first and only block of output
+    let mut round = hmac.mac(&[salt, &1u32.to_be_bytes()[..]].concat());
+    let mut key = round;
+    for _ in 1..rounds {
+        round = hmac.mac(&round);
+        for (key_byte, byte) in key.iter_mut().zip(round) {
+            *key_byte ^= byte;
+        }
+    }
+    return key;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        return bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    }
+
+    // test vectors from RFC 8439, sections 2.3.2, 2.5.2 and 2.8.2
+    #[test]
+    fn rfc8439_vectors() {
+        let key: [u8; 32] = std::array::from_fn(|i| i as u8);
+        let nonce = [0, 0, 0, 0x09, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+        assert_eq!(hex(&chacha20_block(&key, &nonce, 1)[..16]), "10f1e7e4d13b5915500fdd1fa32071c4");
+
+        let mac_key = [
+            0x85, 0xd6, 0xbe, 0x78, 0x57, 0x55, 0x6d, 0x33, 0x7f, 0x44, 0x52, 0xfe, 0x42, 0xd5, 0x06, 0xa8, 0x01, 0x03,
+            0x80, 0x8a, 0xfb, 0x0d, 0xb2, 0xfd, 0x4a, 0xbf, 0xf6, 0xaf, 0x41, 0x49, 0xf5, 0x1b,
+        ];
+        assert_eq!(hex(&poly1305(&mac_key, b"Cryptographic Forum Research Group")), "a8061dc1305136c6c22b8baf0c0127a9");
+
+        let cipher = ChaCha20Poly1305::new(std::array::from_fn(|i| 0x80 + i as u8));
+        let nonce = [0x07, 0, 0, 0, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47];
+        let aad = [0x50, 0x51, 0x52, 0x53, 0xc0, 0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7];
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let sealed = cipher.seal(&nonce, &aad, plaintext);
+        assert_eq!(hex(&sealed[..16]), "d31a8d34648e60db7b86afbc53ef7ec2");
+        assert_eq!(hex(&sealed[sealed.len() - TAG_LEN..]), "1ae10b594f09e26a7e902ecbd0600691");
+    }
+
+    // the widely published answers for "password" and "salt", plus a secret longer
+    // than a block, which is hashed first
+    #[test]
+    fn pbkdf2_vectors() {
+        assert_eq!(hex(&derive_key(b"password", b"salt", 1)), "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b");
+        assert_eq!(hex(&derive_key(b"password", b"salt", 2)), "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43");
+        assert_eq!(hex(&derive_key(b"password", b"salt", 4096)), "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a");
+        assert_eq!(hex(&derive_key(&[b'x'; 100], b"pepper", 3)), "8a936c3eeed271d196543d0387f6bc76eb235366174f4306fa4324f80d394b11");
+    }
+}
@@ -0,0 +1,104 @@
+//! The `rustpm` command line tool, for looking into a database file without writing
+//! a program for it. Built with the `cli` feature.
+
+use std::io::Write;
+
+use super::format::render;
+use super::{Collection, DBError, DBErrorKind, DBManager};
+
+pub const USAGE: &str = "usage: rustpm <database> <command> [arguments]
+
+commands:
+  collections              every collection and how many records it holds
+  dump <collection>        every record, one json object per line
+  get <collection> <id>    one record as json
+  delete <collection> <id> deletes one record
+  migrations               the migrations applied so far, oldest first
+  compact                  purges expired records and flushes, reporting the size on disk
+  backup <path>            writes a backup archive to <path>
+  shell                    an interactive shell, with the repl feature
+
+Records stored as bincode cannot be decoded without their type and are shown as hex.";
+
+fn usage() -> DBError {
+    return DBError::new(DBErrorKind::Other(USAGE.to_string()));
+}
+
+fn collection(db: &DBManager, name: Option<&String>) -> Result<Collection<()>, DBError> {
+    match name {
+        None => return Err(usage()),
+        Some(name) => return db.collection(name),
+    }
+}
+
+fn write_line(out: &mut impl Write, line: impl std::fmt::Display) -> Result<(), DBError> {
+    if let Err(err) = writeln!(out, "{}", line) {
+        return Err(DBError::with_source(DBErrorKind::WriteFailed("output".to_string()), err));
+    }
+    return Ok(());
+}
+
+/// Runs one command, `args` being what follows the program name, writing its output
+/// to `out`. Commands that only look are run on a read-only handle.
+pub fn run(args: &[String], out: &mut impl Write) -> Result<(), DBError> {
+    let (path, command, rest) = match args {
+        [path, command, rest @ ..] => (path.clone(), command.as_str(), rest),
+        _ => return Err(usage()),
+    };
+    let db = match command {
+        "delete" | "compact" => DBManager::new(path)?,
+        _ => DBManager::open_read_only(path)?,
+    };
+
+    match command {
+        "collections" => {
+            for (name, records) in db.stats()?.collections {
+                write_line(out, format!("{}\t{}", name, records))?;
+            }
+        }
+        "dump" => {
+            let collection = collection(&db, rest.first())?;
+            for entry in collection.tree.iter() {
+                let (id, bytes) = entry?;
+                write_line(out, render(&id, &bytes, *collection.codec(), collection.schema().as_ref()))?;
+            }
+        }
+        "get" => {
+            let collection = collection(&db, rest.first())?;
+            let id = rest.get(1).ok_or_else(usage)?;
+            match collection.tree.get(id)? {
+                None => return Err(DBError::new(DBErrorKind::NotFound(format!("{} in {}", id, collection.name())))),
+                Some(bytes) => write_line(out, render(id.as_bytes(), &bytes, *collection.codec(), collection.schema().as_ref()))?,
+            }
+        }
+        "delete" => {
+            let collection = collection(&db, rest.first())?;
+            let id = rest.get(1).ok_or_else(usage)?;
+            if collection.delete_raw(id.as_bytes())?.is_none() {
+                return Err(DBError::new(DBErrorKind::NotFound(format!("{} in {}", id, collection.name()))));
+            }
+            write_line(out, format!("deleted {}", id))?;
+        }
+        "migrations" => {
+            for name in db.applied_migrations()? {
+                write_line(out, name)?;
+            }
+        }
+        "compact" => {
+            let (before, after) = db.maintain()?;
+            write_line(out, format!("{} -> {} bytes", before, after))?;
+        }
+        #[cfg(feature = "repl")]
+        "shell" => {
+            let stdin = std::io::stdin();
+            super::Shell::new(db).run(stdin.lock(), out)?;
+        }
+        "backup" => {
+            let path = rest.first().ok_or_else(usage)?;
+            db.backup(path)?;
+            write_line(out, format!("backed up to {}", path))?;
+        }
+        _ => return Err(usage()),
+    }
+    return Ok(());
+}
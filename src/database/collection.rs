@@ -0,0 +1,2267 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sled::transaction::{abort, ConflictableTransactionError, TransactionalTree};
+use sled::{Batch, Db, IVec, Transactional, Tree};
+
+use super::access::HotKey;
+use super::aggregate::{Aggregate, Number};
+use super::audit::{self, AuditEntry, AuditOp};
+use super::csv::{self, ImportReport, Records, RowError};
+use super::durability::{self, Durability};
+use super::format::{Codec, Format, META_TREE};
+use super::hooks::Hooks;
+use super::index::{index_tree_name, write_entry, IndexEntry};
+use super::key::Key;
+use super::lease::{self, Lease};
+use super::limit::{self, Quota, Usage};
+use super::lock::KeyGuard;
+use super::registry::Registries;
+use super::relation::{self, Dependents};
+use super::schema::{self, Schema, Versioned};
+use super::search;
+use super::sync::{self, ApplyReport, Change, Changeset, Conflict, ConflictStrategy, SyncToken, DELETED, SAVED};
+use super::trace::traced;
+use super::ttl;
+use super::validate::Validate;
+use crate::json;
+use super::version;
+use super::{DBError, DBErrorKind, Id, Namespace, Query, Subscription};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpsertOutcome {
+    Created,
+    Replaced,
+}
+
+/// What `Collection::insert_with_outcome` did with the id it generated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InsertOutcome<T> {
+    Created(String),
+    /// The id was already taken and its record overwritten. `previous` is that record,
+    /// or `None` if it no longer decodes as `T`.
+    Replaced { id: String, previous: Option<T> },
+}
+
+impl<T> InsertOutcome<T> {
+    pub fn id(&self) -> &str {
+        match self {
+            InsertOutcome::Created(id) => return id,
+            InsertOutcome::Replaced { id, .. } => return id,
+        }
+    }
+
+    pub fn replaced(&self) -> bool {
+        return matches!(self, InsertOutcome::Replaced { .. });
+    }
+}
+
+/// One page of records; pass `next_cursor` back to `get_page` to continue. The cursor
+/// is the raw key of the last item, so it works for binary keys too.
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<IVec>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortDirection {
+    Ascending,
+    Descending,
+}
+
+/// How many rows `import_csv` inserts per batch.
+pub const CSV_BATCH: usize = 1000;
+
+/// How many records `delete_where` removes per atomic batch.
+pub const DELETE_BATCH: usize = 1000;
+
+/// How many records `update_where` rewrites per atomic batch.
+pub const UPDATE_BATCH: usize = 1000;
+
+// records picked out by a predicate: id, the bytes stored when matched, and the record
+type Matches<T> = Vec<(IVec, IVec, T)>;
+
+/// A soft-deleted record, see `Collection::set_soft_delete`.
+#[derive(Debug, Clone)]
+pub struct Tombstone<T> {
+    pub id: String,
+    pub data: T,
+    pub deleted_at: SystemTime,
+}
+
+// what the stored value has to look like for a write to go ahead
+enum Expect {
+    Any,
+    Exists,
+    Current(IVec),
+    Absent,
+    Version(u64),
+}
+
+/// A typed handle over a single sled `Tree`, so each model gets its own keyspace.
+pub struct Collection<T, C = Format> {
+    conn: Db,
+    pub(super) tree: Tree,
+    name: String,
+    shared: Registries,
+    pub(super) codec: C,
+    actor: Option<String>,
+    durability: Durability,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, C: Clone> Clone for Collection<T, C> {
+    fn clone(&self) -> Self {
+        return Collection {
+            conn: self.conn.clone(),
+            tree: self.tree.clone(),
+            name: self.name.clone(),
+            shared: self.shared.clone(),
+            codec: self.codec.clone(),
+            actor: self.actor.clone(),
+            durability: self.durability,
+            _marker: PhantomData,
+        };
+    }
+}
+
+impl<T, C: Codec> Collection<T, C> {
+    pub(super) fn new(conn: Db, tree: Tree, shared: Registries, codec: C) -> Self {
+        let name = String::from_utf8_lossy(&tree.name()).into_owned();
+        return Collection {
+            conn,
+            tree,
+            name,
+            shared,
+            codec,
+            actor: None,
+            durability: Durability::default(),
+            _marker: PhantomData,
+        };
+    }
+
+    /// This handle, attributing the changes it makes to `actor` in the audit log.
+    pub fn with_actor(mut self, actor: impl Into<String>) -> Self {
+        self.actor = Some(actor.into());
+        return self;
+    }
+
+    pub fn actor(&self) -> Option<&str> {
+        return self.actor.as_deref();
+    }
+
+    pub(super) fn acting_as(mut self, actor: Option<String>) -> Self {
+        self.actor = actor;
+        return self;
+    }
+
+    /// This handle, making each write it returns from as durable as `durability`
+    /// says, e.g. `payments.with_durability(Durability::Fsync).insert(payment)`.
+    /// Batch writes such as `insert_many` and `update_where` settle once, at the end.
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        return self;
+    }
+
+    pub fn durability(&self) -> Durability {
+        return self.durability;
+    }
+
+    fn make_durable(&self) -> Result<(), DBError> {
+        return durability::settle(&self.conn, self.durability, &self.shared.flusher);
+    }
+
+    /// Starts keeping an audit log of every insert, update and delete made here from
+    /// now on, read back with `audit_trail`. Writes inside transactions and merges are
+    /// not logged.
+    pub fn enable_audit(&self) -> Result<(), DBError> {
+        self.shared.writable()?;
+        self.shared.audits.get_or_open(&self.conn, &self.name)?;
+        return Ok(());
+    }
+
+    /// The changes logged for `id`, oldest first.
+    pub fn audit_trail(&self, id: impl Key) -> Result<Vec<AuditEntry>, DBError> {
+        match self.shared.audits.get(&self.name) {
+            None => return Ok(Vec::new()),
+            Some(tree) => return audit::trail(&tree, id.to_key().as_ref()),
+        }
+    }
+
+    /// Starts logging which records change, for `changes_since`. Records already
+    /// stored are logged as changed now, so the first changeset holds everything.
+    pub fn track_changes(&self) -> Result<(), DBError> {
+        self.shared.writable()?;
+        if self.shared.changes.get(&self.name).is_some() {
+            return Ok(());
+        }
+        self.shared.changes.get_or_open(&self.conn, &self.name)?;
+        for id in self.tree.iter().keys() {
+            sync::stamp(&self.shared, &self.conn, &self.name, &id?, SAVED, None)?;
+        }
+        return Ok(());
+    }
+
+    /// The latest state of every record changed from `token` on, deletions included,
+    /// along with the token to ask from next time.
+    pub fn changes_since(&self, token: SyncToken) -> Result<Changeset<T>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        let log = self.change_log()?;
+        let mut changeset = Changeset {
+            changes: Vec::new(),
+            token,
+        };
+        for entry in sync::since(&log, token)? {
+            changeset.token = SyncToken(entry.sequence + 1);
+            let data = match self.tree.get(&entry.id)? {
+                Some(bytes) if !entry.deleted => Some(self.decode(&entry.id, &bytes)?),
+                _ => None,
+            };
+            changeset.changes.push(Change {
+                id: String::from_utf8_lossy(&entry.id).into_owned(),
+                data,
+                modified: entry.modified,
+            });
+        }
+        return Ok(changeset);
+    }
+
+    /// Writes changes that came from another database, keeping their modification
+    /// times so they are not mistaken for local ones. Changes already applied are
+    /// skipped. A record also changed here from `since`, the token of the last
+    /// changeset sent out, is a conflict settled by `strategy`; anything else replaces
+    /// the local record. Hooks and validation do not run, the writing side has done that.
+    pub fn apply_changes(
+        &self,
+        changes: Vec<Change<T>>,
+        since: SyncToken,
+        strategy: &ConflictStrategy<T>,
+    ) -> Result<ApplyReport, DBError>
+    where
+        T: Serialize + DeserializeOwned + 'static,
+    {
+        let log = self.change_log()?;
+        let mut report = ApplyReport::default();
+        for change in changes {
+            let id = change.id.as_bytes();
+            if sync::modified(&log, id)? == Some(change.modified) {
+                continue;
+            }
+            let local = match sync::pending(&log, id, since)? {
+                None => None,
+                Some(modified) => {
+                    report.conflicts += 1;
+                    let data = match self.tree.get(id)? {
+                        None => None,
+                        Some(bytes) => Some(self.decode(id, &bytes)?),
+                    };
+                    Some(Change {
+                        id: change.id.clone(),
+                        data,
+                        modified,
+                    })
+                }
+            };
+            let applied = match local {
+                None => self.apply_change(id, change.data.as_ref(), Some(change.modified))?,
+                Some(local) => self.settle(local, change, strategy)?,
+            };
+            if applied {
+                report.applied += 1;
+            }
+        }
+        return Ok(report);
+    }
+
+    // writes whichever side of a conflict wins, returning whether anything was written;
+    // a resolver's record goes in as a new local change
+    fn settle(&self, local: Change<T>, remote: Change<T>, strategy: &ConflictStrategy<T>) -> Result<bool, DBError>
+    where
+        T: Serialize + 'static,
+    {
+        let id = remote.id.as_bytes();
+        match strategy {
+            ConflictStrategy::PreferLocal => return Ok(false),
+            ConflictStrategy::PreferRemote => return self.apply_change(id, remote.data.as_ref(), Some(remote.modified)),
+            ConflictStrategy::LastWriterWins => {
+                // ties are broken on the plain bincode bytes, which are the same on every
+                // replica even when the codec encrypts
+                let encoded = |change: &Change<T>| change.data.as_ref().map(|data| Format::Bincode.encode(data)).transpose();
+                if (remote.modified, encoded(&remote)?) <= (local.modified, encoded(&local)?) {
+                    return Ok(false);
+                }
+                return self.apply_change(id, remote.data.as_ref(), Some(remote.modified));
+            }
+            ConflictStrategy::Resolve(resolve) => {
+                let id = remote.id.clone();
+                let resolved = resolve(&Conflict { local, remote });
+                return self.apply_change(id.as_bytes(), resolved.as_ref(), None);
+            }
+        }
+    }
+
+    // `modified` is that of a change from elsewhere, `None` for one made here
+    fn apply_change(&self, id: &[u8], data: Option<&T>, modified: Option<u64>) -> Result<bool, DBError>
+    where
+        T: Serialize + 'static,
+    {
+        let tag = match data {
+            None => {
+                self.remove(id)?;
+                DELETED
+            }
+            Some(data) => {
+                self.commit(id, Some(data), Expect::Any)?;
+                SAVED
+            }
+        };
+        if modified.is_some() {
+            sync::stamp(&self.shared, &self.conn, &self.name, id, tag, modified)?;
+        }
+        return Ok(true);
+    }
+
+    fn change_log(&self) -> Result<Tree, DBError> {
+        match self.shared.changes.get(&self.name) {
+            None => return Err(DBError::new(DBErrorKind::Other(format!("{} does not track changes", self.name)))),
+            Some(log) => return Ok(log),
+        }
+    }
+
+    /// Calls `f` with the id and record after each insert made here, in the writing thread.
+    pub fn on_insert<F>(&self, f: F)
+    where
+        T: 'static,
+        F: Fn(&str, &T) + Send + Sync + 'static,
+    {
+        self.shared.events.on_insert::<T>(&self.name, Arc::new(f));
+    }
+
+    /// Calls `f` with the id and new record after each write that replaces one.
+    pub fn on_update<F>(&self, f: F)
+    where
+        T: 'static,
+        F: Fn(&str, &T) + Send + Sync + 'static,
+    {
+        self.shared.events.on_update::<T>(&self.name, Arc::new(f));
+    }
+
+    /// Calls `f` with the id of every record deleted here, including by a cascade or expiry.
+    pub fn on_delete<F>(&self, f: F)
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        self.shared.events.on_delete(&self.name, Arc::new(f));
+    }
+
+    fn audit(&self, id: &[u8], op: AuditOp) -> Result<(), DBError> {
+        return audit::record(&self.conn, &self.shared, &self.name, id, op, self.actor.as_deref());
+    }
+
+    // logs and announces a write that has gone through
+    fn written(&self, id: &[u8], op: AuditOp, data: &T) -> Result<(), DBError>
+    where
+        T: 'static,
+    {
+        self.audit(id, op)?;
+        sync::stamp(&self.shared, &self.conn, &self.name, id, SAVED, None)?;
+        match op {
+            AuditOp::Insert => self.shared.events.inserted(&self.name, id, data),
+            _ => self.shared.events.updated(&self.name, id, data),
+        }
+        return Ok(());
+    }
+
+    fn record_delete(&self, id: &[u8]) -> Result<(), DBError> {
+        self.audit(id, AuditOp::Delete)?;
+        sync::stamp(&self.shared, &self.conn, &self.name, id, DELETED, None)?;
+        self.shared.events.deleted(&self.name, id);
+        return Ok(());
+    }
+
+    pub fn name(&self) -> &str {
+        return &self.name;
+    }
+
+    pub fn codec(&self) -> &C {
+        return &self.codec;
+    }
+
+    /// Stamps writes with `T::VERSION` and upgrades older records on read.
+    pub fn register_versioned(&self)
+    where
+        T: Versioned + Serialize + 'static,
+        C: Send + Sync + 'static,
+    {
+        let codec = self.codec.clone();
+        let name = self.name.clone();
+        let schema = Schema::new(T::VERSION, move |id, version, bytes| codec.encode_for(&name, id, &T::upgrade(version, bytes)?));
+        self.shared.schemas.register(&self.name, schema);
+    }
+
+    /// Lets `DBManager::verify` check that every record here still decodes as `T`.
+    pub fn register_type(&self)
+    where
+        T: DeserializeOwned + 'static,
+        C: Send + Sync + 'static,
+    {
+        let codec = self.codec.clone();
+        let schemas = self.shared.schemas.clone();
+        let name = self.name.clone();
+        self.shared.checks.register(&self.name, move |id, bytes| {
+            let bytes = schema::current(schemas.get(&name).as_ref(), id, bytes)?;
+            return codec.decode_for::<T>(&name, id, &bytes).map(|_| ());
+        });
+    }
+
+    /// Runs `T`'s `Hooks` around writes to and deletes from this collection.
+    pub fn register_hooks(&self)
+    where
+        T: Hooks + DeserializeOwned + 'static,
+        C: Send + Sync + 'static,
+    {
+        let codec = self.codec.clone();
+        let schemas = self.shared.schemas.clone();
+        let name = self.name.clone();
+        self.shared.hooks.register::<T, _>(&self.name, move |id, bytes| {
+            let bytes = schema::current(schemas.get(&name).as_ref(), id, bytes)?;
+            return codec.decode_for::<T>(&name, id, &bytes)?.before_delete();
+        });
+    }
+
+    /// Refuses writes of records that fail `T`'s `Validate` rules.
+    pub fn register_validation(&self)
+    where
+        T: Validate + 'static,
+    {
+        self.shared.validators.register::<T>(&self.name);
+    }
+
+    pub fn namespace(&self, prefix: &str) -> Namespace<T, C> {
+        return Namespace::new(self.clone(), prefix);
+    }
+
+    pub fn subscribe(&self) -> Result<Subscription<T, C>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        return Subscription::new(self, b"");
+    }
+
+    pub fn insert(&self, data: T) -> Result<String, DBError>
+    where
+        T: Serialize + Id + 'static,
+    {
+        return Ok(self.insert_record(data)?.0);
+    }
+
+    /// Like `insert`, but says whether the generated id was already taken, handing
+    /// back the record that was overwritten, so key collisions do not go unnoticed.
+    pub fn insert_with_outcome(&self, data: T) -> Result<InsertOutcome<T>, DBError>
+    where
+        T: Serialize + DeserializeOwned + Id + 'static,
+    {
+        match self.insert_record(data)? {
+            (id, None) => return Ok(InsertOutcome::Created(id)),
+            (id, Some(bytes)) => {
+                let previous = self.decode(id.as_bytes(), &bytes).ok();
+                return Ok(InsertOutcome::Replaced { id, previous });
+            }
+        }
+    }
+
+    /// Like `insert`, but fails with `DBErrorKind::DuplicateKey` instead of overwriting
+    /// a record already stored under the generated id. The check and the write are one
+    /// compare-and-swap, so of two racing inserts of the same key exactly one wins.
+    pub fn insert_unique(&self, mut data: T) -> Result<String, DBError>
+    where
+        T: Serialize + Id + 'static,
+    {
+        self.before_save(&mut data)?;
+        let id = data.gen_id();
+
+        let written = traced("insert", &self.name, id.as_bytes(), || self.commit(id.as_bytes(), Some(&data), Expect::Absent));
+        if let Err(err) = written {
+            match err.kind() {
+                DBErrorKind::Conflict(_) => return Err(DBError::new(DBErrorKind::DuplicateKey(format!("{} in {}", id, self.name)))),
+                _ => return Err(err),
+            }
+        }
+        self.shared.hooks.after_save(&self.name, &data);
+        return Ok(id);
+    }
+
+    fn insert_record(&self, mut data: T) -> Result<(String, Option<IVec>), DBError>
+    where
+        T: Serialize + Id + 'static,
+    {
+        // the hook may change the fields the id is made from
+        self.before_save(&mut data)?;
+        let id = data.gen_id();
+
+        let previous = traced("insert", &self.name, id.as_bytes(), || self.commit(id.as_bytes(), Some(&data), Expect::Any))?;
+        self.shared.hooks.after_save(&self.name, &data);
+        return Ok((id, previous));
+    }
+
+    /// The next number in this collection's sequence, starting at 1. The counter is
+    /// kept on disk and bumped with a compare-and-swap, so numbers are never handed out
+    /// twice, across threads or reopens.
+    pub fn next_id(&self) -> Result<u64, DBError> {
+        self.shared.writable()?;
+        let meta = self.conn.open_tree(META_TREE)?;
+        let next = meta.update_and_fetch(format!("sequence/{}", self.name), |current| {
+            let current = current.and_then(|bytes| bytes.try_into().ok()).map_or(0, u64::from_be_bytes);
+            return Some((current + 1).to_be_bytes().to_vec());
+        })?;
+        // the closure above always leaves eight bytes behind
+        return Ok(u64::from_be_bytes(next.unwrap().as_ref().try_into().unwrap()));
+    }
+
+    /// Stores `data` under the next number from `next_id`, returning it. The key is
+    /// the number as a `Key`, so `get(id)` reads the record back and iteration follows
+    /// insertion order.
+    pub fn insert_auto(&self, mut data: T) -> Result<u64, DBError>
+    where
+        T: Serialize + 'static,
+    {
+        self.before_save(&mut data)?;
+        let id = self.next_id()?;
+        let key = id.to_key();
+        traced("insert", &self.name, key.as_ref(), || self.commit(key.as_ref(), Some(&data), Expect::Any))?;
+        self.shared.hooks.after_save(&self.name, &data);
+        return Ok(id);
+    }
+
+    pub fn insert_many(&self, records: Vec<T>) -> Result<Vec<String>, DBError>
+    where
+        T: Serialize + Id + 'static,
+    {
+        self.shared.writable()?;
+        let indexes = self.shared.indexes.for_collection(&self.name);
+        let mut ids = Vec::with_capacity(records.len());
+        let mut values = Vec::with_capacity(records.len());
+        let mut keys = Vec::with_capacity(records.len());
+        let mut records = records;
+        let limit = self.max_record_size()?;
+        for data in &mut records {
+            self.before_save(data)?;
+            let id = data.gen_id();
+            let value = self.encode(id.as_bytes(), data)?;
+            ids.push(id);
+            limit::check_size(&self.name, &value, limit)?;
+            values.push(value);
+            keys.push(indexes.iter().map(|index| index.keys(data)).collect::<Vec<_>>());
+        }
+        for id in &ids {
+            self.shared.access.write(&self.name, id.as_bytes());
+        }
+
+        let versions = self.shared.versions.get(&self.name);
+        let quota = self.quota()?;
+        if indexes.is_empty() && versions.is_none() && quota.is_none() {
+            let mut batch = Batch::default();
+            for (id, value) in ids.iter().zip(values) {
+                batch.insert(id.as_str(), value);
+            }
+            self.tree.apply_batch(batch)?;
+            self.saved_all(&ids, &records)?;
+            self.make_durable()?;
+            return Ok(ids);
+        }
+
+        let trees = self.trees(&indexes, &versions, quota)?;
+        trees[..].transaction(|views| {
+            let index_views = pair_views(&indexes, &views[1..]);
+            let version_view = version_view(views, &indexes, &versions);
+            let usage = self.usage(views, quota);
+            for ((id, value), keys) in ids.iter().zip(&values).zip(&keys) {
+                let previous = write_entry(&views[0], &index_views, version_view, id.as_bytes(), Some(value.clone()), keys)?;
+                limit::track(usage.as_ref(), id.as_bytes(), previous.as_deref(), Some(value))?;
+            }
+            return Ok::<(), ConflictableTransactionError<DBError>>(());
+        })?;
+        self.saved_all(&ids, &records)?;
+        self.make_durable()?;
+        return Ok(ids);
+    }
+
+    /// Loads records from CSV whose first row names the fields, inserting them in batches
+    /// of `CSV_BATCH`. Rows that do not deserialize into `T`, or that a validator, unique
+    /// index, size limit or quota refuses, are skipped and reported; a batch holding such
+    /// a row is inserted one row at a time to find it.
+    pub fn import_csv<R: Read>(&self, reader: R) -> Result<ImportReport, DBError>
+    where
+        T: Serialize + DeserializeOwned + Id + 'static,
+    {
+        let mut report = ImportReport::default();
+        let mut records = Records::new(BufReader::new(reader));
+        let headers = match records.next_record()? {
+            None => return Ok(report),
+            Some(Err(err)) => return Err(DBError::new(DBErrorKind::ReadFailed(format!("csv header: {}", err.message)))),
+            Some(Ok((_, headers))) => headers,
+        };
+
+        // the rows are kept next to their records, to parse them again for a retry
+        let mut rows = Vec::with_capacity(CSV_BATCH);
+        let mut batch = Vec::with_capacity(CSV_BATCH);
+        while let Some(record) = records.next_record()? {
+            match record.and_then(|(line, fields)| match csv::from_row(&headers, &fields) {
+                Err(message) => Err(RowError { line, message }),
+                Ok(data) => Ok((line, fields, data)),
+            }) {
+                Err(err) => report.errors.push(err),
+                Ok((line, fields, data)) => {
+                    rows.push((line, fields));
+                    batch.push(data);
+                }
+            }
+            if batch.len() == CSV_BATCH {
+                self.import_batch(&headers, std::mem::take(&mut rows), std::mem::take(&mut batch), &mut report)?;
+            }
+        }
+        self.import_batch(&headers, rows, batch, &mut report)?;
+        report.errors.sort_by_key(|err| err.line);
+        return Ok(report);
+    }
+
+    fn import_batch(&self, headers: &[String], rows: Vec<csv::Record>, batch: Vec<T>, report: &mut ImportReport) -> Result<(), DBError>
+    where
+        T: Serialize + DeserializeOwned + Id + 'static,
+    {
+        match self.insert_many(batch) {
+            Ok(ids) => {
+                report.ids.extend(ids);
+                return Ok(());
+            }
+            Err(err) if !refuses_record(&err) => return Err(err),
+            Err(_) => {}
+        }
+        for (line, fields) in rows {
+            let data = match csv::from_row(headers, &fields) {
+                Err(message) => {
+                    report.errors.push(RowError { line, message });
+                    continue;
+                }
+                Ok(data) => data,
+            };
+            match self.insert(data) {
+                Err(err) if refuses_record(&err) => report.errors.push(RowError { line, message: err.to_string() }),
+                Err(err) => return Err(err),
+                Ok(id) => report.ids.push(id),
+            }
+        }
+        return Ok(());
+    }
+
+    /// Inserts a record that is removed once `ttl` has passed. Expired records are
+    /// never returned by reads; they are deleted on the next read of the collection
+    /// or by a `Sweeper`. Plain writes to the record later leave its expiry in place.
+    pub fn insert_with_ttl(&self, data: T, ttl: Duration) -> Result<String, DBError>
+    where
+        T: Serialize + Id + 'static,
+    {
+        let id = data.gen_id();
+        self.upsert_with_ttl(id.clone(), data, ttl)?;
+        return Ok(id);
+    }
+
+    pub fn upsert_with_ttl(&self, id: impl Key, data: T, ttl: Duration) -> Result<UpsertOutcome, DBError>
+    where
+        T: Serialize + 'static,
+    {
+        self.shared.writable()?;
+        // the expiry goes in first, a crash in between leaves a harmless dangling entry
+        let expiries = self.shared.ttls.get_or_open(&self.conn, &self.name)?;
+        ttl::set(&expiries, id.to_key().as_ref(), ttl)?;
+        return self.upsert(id, data);
+    }
+
+    /// Time left before `id` expires, `None` when it never does.
+    pub fn ttl(&self, id: impl Key) -> Result<Option<Duration>, DBError> {
+        match self.shared.ttls.get(&self.name) {
+            None => return Ok(None),
+            Some(expiries) => return ttl::remaining(&expiries, id.to_key().as_ref()),
+        }
+    }
+
+    /// Deletes every expired record now, returning how many were removed.
+    pub fn purge_expired(&self) -> Result<usize, DBError> {
+        self.shared.writable()?;
+        return self.expire();
+    }
+
+    pub fn get(&self, id: impl Key) -> Result<T, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        let id = id.to_key();
+        self.shared.access.read(&self.name, id.as_ref());
+        return traced("get", &self.name, id.as_ref(), || {
+            self.expire()?;
+            match self.tree.get(id.as_ref())? {
+                None => return Err(DBError::new(DBErrorKind::NotFound(format!("{} in {}", String::from_utf8_lossy(id.as_ref()), self.name)))),
+                Some(bytes) => return self.decode(id.as_ref(), &bytes),
+            }
+        });
+    }
+
+    /// The record under `id`, or if there is none the one `f` makes, stored with a
+    /// compare-and-swap so that of two racing callers one creates it and the other gets
+    /// that record back. `f` runs at most once, and not at all if the record exists.
+    pub fn get_or_insert_with<F>(&self, id: impl Key, f: F) -> Result<T, DBError>
+    where
+        T: Serialize + DeserializeOwned + 'static,
+        F: FnOnce() -> T,
+    {
+        let id = id.to_key();
+        let mut make = Some(f);
+        let mut made = None;
+        self.shared.access.read(&self.name, id.as_ref());
+        return traced("get", &self.name, id.as_ref(), || loop {
+            self.expire()?;
+            if let Some(bytes) = self.tree.get(id.as_ref())? {
+                return self.decode(id.as_ref(), &bytes);
+            }
+            // a record made on an earlier round is reused if the winner was deleted since
+            let data = match made.take() {
+                Some(data) => data,
+                None => {
+                    let mut data = make.take().expect("made only once, then kept in made")();
+                    self.before_save(&mut data)?;
+                    data
+                }
+            };
+            match self.commit(id.as_ref(), Some(&data), Expect::Absent) {
+                Err(err) if matches!(err.kind(), DBErrorKind::Conflict(_)) => made = Some(data),
+                Err(err) => return Err(err),
+                Ok(_) => {
+                    self.shared.hooks.after_save(&self.name, &data);
+                    return Ok(data);
+                }
+            }
+        });
+    }
+
+    /// The record under `id` decoded as the projection `P` instead of `T`, so only the
+    /// fields `P` names are built. With json, `P` can pick any fields by name; bincode
+    /// is read in order, so there `P` has to be the first fields of `T`, in `T`'s order.
+    pub fn get_as<P: DeserializeOwned>(&self, id: impl Key) -> Result<P, DBError> {
+        let id = id.to_key();
+        self.shared.access.read(&self.name, id.as_ref());
+        return traced("get", &self.name, id.as_ref(), || {
+            self.expire()?;
+            match self.tree.get(id.as_ref())? {
+                None => return Err(DBError::new(DBErrorKind::NotFound(format!("{} in {}", String::from_utf8_lossy(id.as_ref()), self.name)))),
+                Some(bytes) => return self.decode_as(id.as_ref(), &bytes),
+            }
+        });
+    }
+
+    /// Every record decoded as the projection `P`, in key order, see `get_as`.
+    pub fn get_all_as<P: DeserializeOwned>(&self) -> Result<Vec<P>, DBError> {
+        self.expire()?;
+        let mut records = Vec::new();
+        for entry in self.tree.iter() {
+            let (id, value) = entry?;
+            records.push(self.decode_as(&id, &value)?);
+        }
+        return Ok(records);
+    }
+
+    pub fn exists(&self, id: impl Key) -> Result<bool, DBError> {
+        self.expire()?;
+        return Ok(self.tree.contains_key(id.to_key())?);
+    }
+
+    pub fn get_many<K: Key>(&self, ids: &[K]) -> Result<Vec<Option<T>>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        self.expire()?;
+        let mut records = Vec::with_capacity(ids.len());
+        for id in ids {
+            let id = id.to_key();
+            match self.tree.get(id.as_ref())? {
+                None => records.push(None),
+                Some(bytes) => records.push(Some(self.decode(id.as_ref(), &bytes)?)),
+            }
+        }
+        return Ok(records);
+    }
+
+    pub fn get_all(&self) -> Result<Vec<T>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        self.expire()?;
+        let mut records = Vec::new();
+        for entry in self.tree.iter() {
+            let (id, value) = entry?;
+            records.push(self.decode(&id, &value)?);
+        }
+        return Ok(records);
+    }
+
+    /// Every record with its id, in key order, read and decoded lazily one at a time.
+    /// Ids are the raw keys, which `get` takes back as they are. A record that fails to
+    /// decode is an `Err` item of its own, so the caller can skip or report it and carry
+    /// on with the rest.
+    pub fn iter(&self) -> Result<impl Iterator<Item = Result<(IVec, T), DBError>> + '_, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        self.expire()?;
+        return Ok(self.tree.iter().map(move |entry| self.decode_entry(entry)));
+    }
+
+    /// Like `iter`, from the last key to the first.
+    pub fn iter_rev(&self) -> Result<impl Iterator<Item = Result<(IVec, T), DBError>> + '_, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        self.expire()?;
+        return Ok(self.tree.iter().rev().map(move |entry| self.decode_entry(entry)));
+    }
+
+    /// The `n` records with the highest keys, highest first, e.g. the most recent ones
+    /// when keys are timestamps. Only those `n` are read.
+    pub fn last_n(&self, n: usize) -> Result<Vec<T>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        self.expire()?;
+        let mut records = Vec::with_capacity(n);
+        for entry in self.tree.iter().rev().take(n) {
+            let (id, value) = entry?;
+            records.push(self.decode(&id, &value)?);
+        }
+        return Ok(records);
+    }
+
+    /// The record with the lowest key and its id, `None` when the collection is empty.
+    pub fn first(&self) -> Result<Option<(IVec, T)>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        self.expire()?;
+        match self.tree.first()? {
+            None => return Ok(None),
+            Some(entry) => return Ok(Some(self.decode_entry(Ok(entry))?)),
+        }
+    }
+
+    /// The record with the highest key and its id, e.g. the newest with time-ordered keys.
+    pub fn last(&self) -> Result<Option<(IVec, T)>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        self.expire()?;
+        match self.tree.last()? {
+            None => return Ok(None),
+            Some(entry) => return Ok(Some(self.decode_entry(Ok(entry))?)),
+        }
+    }
+
+    pub(super) fn decode_entry(&self, entry: sled::Result<(IVec, IVec)>) -> Result<(IVec, T), DBError>
+    where
+        T: DeserializeOwned,
+    {
+        let (id, value) = entry?;
+        let data = self.decode(&id, &value)?;
+        return Ok((id, data));
+    }
+
+    /// Writes every record to `writer` as one JSON document per line, in key order,
+    /// decoding them one at a time. Returns how many were written.
+    pub fn export_jsonl<W: Write>(&self, writer: W) -> Result<usize, DBError>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let failed = |err: io::Error| DBError::with_source(DBErrorKind::WriteFailed("jsonl export".to_string()), err);
+
+        self.expire()?;
+        let mut out = BufWriter::new(writer);
+        let mut exported = 0;
+        for entry in self.tree.iter() {
+            let (id, value) = entry?;
+            let line = match json::to_vec(&self.decode(&id, &value)?) {
+                Err(err) => return Err(DBError::with_source(DBErrorKind::SerializeFailed("record as json".to_string()), err)),
+                Ok(line) => line,
+            };
+            out.write_all(&line).and_then(|_| out.write_all(b"\n")).map_err(failed)?;
+            exported += 1;
+        }
+        out.flush().map_err(failed)?;
+        return Ok(exported);
+    }
+
+    /// Records whose keys fall inside `range`, in key order.
+    pub fn get_range<K, R>(&self, range: R) -> Result<Vec<T>, DBError>
+    where
+        T: DeserializeOwned,
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        self.expire()?;
+        let mut records = Vec::new();
+        for entry in self.tree.range(range) {
+            let (id, value) = entry?;
+            records.push(self.decode(&id, &value)?);
+        }
+        return Ok(records);
+    }
+
+    /// Records whose keys start with `prefix`, in key order, e.g.
+    /// `orders.find_by_key_prefix("order:2024-")` with date-stamped keys. Only the
+    /// matching part of the collection is read.
+    pub fn find_by_key_prefix(&self, prefix: impl AsRef<[u8]>) -> Result<Vec<T>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        self.expire()?;
+        let mut records = Vec::new();
+        for entry in self.tree.scan_prefix(prefix) {
+            let (id, value) = entry?;
+            records.push(self.decode(&id, &value)?);
+        }
+        return Ok(records);
+    }
+
+    /// Like `get_range`, with the bounds given as any `Key`, e.g.
+    /// `events.get_between(Bound::Included(from), Bound::Excluded(to))` over `u64`
+    /// timestamps, which `get_range` cannot take as they are not bytes.
+    pub fn get_between<K: Key>(&self, start: Bound<K>, end: Bound<K>) -> Result<Vec<T>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        let bound = |bound: Bound<K>| bound.map(|key| key.to_key().as_ref().to_vec());
+        return self.get_range((bound(start), bound(end)));
+    }
+
+    /// Counts records without deserializing them.
+    pub fn count(&self) -> Result<usize, DBError> {
+        self.expire()?;
+        let mut count = 0;
+        for key in self.tree.iter().keys() {
+            key?;
+            count += 1;
+        }
+        return Ok(count);
+    }
+
+    pub fn count_where<P>(&self, predicate: P) -> Result<usize, DBError>
+    where
+        T: DeserializeOwned,
+        P: Fn(&T) -> bool,
+    {
+        self.expire()?;
+        let mut count = 0;
+        for entry in self.tree.iter() {
+            let (id, value) = entry?;
+            if predicate(&self.decode(&id, &value)?) {
+                count += 1;
+            }
+        }
+        return Ok(count);
+    }
+
+    /// Count, sum, minimum and maximum of the value `f` extracts from every record,
+    /// computed in one pass without collecting the records.
+    pub fn aggregate<N, F>(&self, f: F) -> Result<Aggregate<N>, DBError>
+    where
+        T: DeserializeOwned,
+        N: Number,
+        F: Fn(&T) -> N,
+    {
+        self.expire()?;
+        let mut aggregate = Aggregate::default();
+        for entry in self.tree.iter() {
+            let (id, value) = entry?;
+            aggregate.add(f(&self.decode(&id, &value)?));
+        }
+        return Ok(aggregate);
+    }
+
+    /// How many records fall in each group `key_fn` puts them in, in one pass.
+    pub fn group_by<K, F>(&self, key_fn: F) -> Result<BTreeMap<K, usize>, DBError>
+    where
+        T: DeserializeOwned,
+        K: Ord,
+        F: Fn(&T) -> K,
+    {
+        self.expire()?;
+        let mut groups = BTreeMap::new();
+        for entry in self.tree.iter() {
+            let (id, value) = entry?;
+            *groups.entry(key_fn(&self.decode(&id, &value)?)).or_insert(0) += 1;
+        }
+        return Ok(groups);
+    }
+
+    /// Like `group_by`, aggregating the value `f` extracts within each group.
+    pub fn group_aggregate<K, N, G, F>(&self, key_fn: G, f: F) -> Result<BTreeMap<K, Aggregate<N>>, DBError>
+    where
+        T: DeserializeOwned,
+        K: Ord,
+        N: Number,
+        G: Fn(&T) -> K,
+        F: Fn(&T) -> N,
+    {
+        self.expire()?;
+        let mut groups: BTreeMap<K, Aggregate<N>> = BTreeMap::new();
+        for entry in self.tree.iter() {
+            let (id, value) = entry?;
+            let data = self.decode(&id, &value)?;
+            groups.entry(key_fn(&data)).or_default().add(f(&data));
+        }
+        return Ok(groups);
+    }
+
+    pub fn get_page(&self, cursor: Option<IVec>, page_size: usize) -> Result<Page<T>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        return self.page(cursor, page_size, |id, value| self.decode(id, value));
+    }
+
+    /// Every id in key order, as raw keys, without reading the records.
+    pub fn keys(&self) -> Result<Vec<IVec>, DBError> {
+        self.expire()?;
+        let mut keys = Vec::new();
+        for key in self.tree.iter().keys() {
+            keys.push(key?);
+        }
+        return Ok(keys);
+    }
+
+    /// Like `get_page`, handing out ids instead of records.
+    pub fn keys_page(&self, cursor: Option<IVec>, page_size: usize) -> Result<Page<IVec>, DBError> {
+        return self.page(cursor, page_size, |key, _| Ok(IVec::from(key)));
+    }
+
+    // one page of whatever `item` makes of each entry after `cursor`
+    fn page<I, F>(&self, cursor: Option<IVec>, page_size: usize, item: F) -> Result<Page<I>, DBError>
+    where
+        F: Fn(&[u8], &[u8]) -> Result<I, DBError>,
+    {
+        self.expire()?;
+        let start = match cursor {
+            None => Bound::Unbounded,
+            Some(cursor) => Bound::Excluded(cursor),
+        };
+
+        let mut items = Vec::with_capacity(page_size);
+        let mut last_key = None;
+        let mut entries = self.tree.range::<IVec, _>((start, Bound::Unbounded));
+        while items.len() < page_size {
+            match entries.next() {
+                None => return Ok(Page { items, next_cursor: None }),
+                Some(entry) => {
+                    let (key, value) = entry?;
+                    items.push(item(&key, &value)?);
+                    last_key = Some(key);
+                }
+            }
+        }
+
+        // only hand out a cursor when there really is something after this page
+        let next_cursor = match entries.next() {
+            None => None,
+            Some(entry) => {
+                entry?;
+                last_key
+            }
+        };
+        return Ok(Page { items, next_cursor });
+    }
+
+    pub fn find_where<P>(&self, predicate: P) -> Result<Vec<T>, DBError>
+    where
+        T: DeserializeOwned,
+        P: Fn(&T) -> bool,
+    {
+        self.expire()?;
+        let mut records = Vec::new();
+        for entry in self.tree.iter() {
+            let (id, value) = entry?;
+            let data = self.decode(&id, &value)?;
+            if predicate(&data) {
+                records.push(data);
+            }
+        }
+        return Ok(records);
+    }
+
+    /// Starts a `Query` over this collection, see also the `query!` macro.
+    pub fn query(&self) -> Query<'_, T, C> {
+        return Query::new(self);
+    }
+
+    /// Whether an index named `name` has been declared on this collection.
+    pub fn has_index(&self, name: &str) -> bool {
+        return self.shared.indexes.find(&self.name, name).is_some();
+    }
+
+    pub fn find_sorted<K, F>(&self, key_fn: F, direction: SortDirection) -> Result<Vec<T>, DBError>
+    where
+        T: DeserializeOwned,
+        K: Ord,
+        F: Fn(&T) -> K,
+    {
+        let mut keyed: Vec<(K, T)> = self.get_all()?.into_iter().map(|data| (key_fn(&data), data)).collect();
+        match direction {
+            SortDirection::Ascending => keyed.sort_by(|a, b| a.0.cmp(&b.0)),
+            SortDirection::Descending => keyed.sort_by(|a, b| b.0.cmp(&a.0)),
+        }
+        return Ok(keyed.into_iter().map(|(_, data)| data).collect());
+    }
+
+    /// Records ordered by the raw bytes of an index, read straight off the index
+    /// tree instead of sorting in memory. Records the index skips are not returned.
+    pub fn find_sorted_by_index(&self, name: &str, direction: SortDirection) -> Result<Vec<T>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        self.expire()?;
+        let index = self.index(name)?;
+        let mut records = Vec::new();
+        for id in index.ordered_ids(direction)? {
+            if let Some(bytes) = self.tree.get(&id)? {
+                records.push(self.decode(&id, &bytes)?);
+            }
+        }
+        return Ok(records);
+    }
+
+    pub fn update(&self, id: impl Key, mut data: T) -> Result<(), DBError>
+    where
+        T: Serialize + 'static,
+    {
+        self.before_save(&mut data)?;
+        self.commit(id.to_key().as_ref(), Some(&data), Expect::Exists)?;
+        self.shared.hooks.after_save(&self.name, &data);
+        return Ok(());
+    }
+
+    pub fn upsert(&self, id: impl Key, mut data: T) -> Result<UpsertOutcome, DBError>
+    where
+        T: Serialize + 'static,
+    {
+        self.before_save(&mut data)?;
+        let previous = self.commit(id.to_key().as_ref(), Some(&data), Expect::Any)?;
+        self.shared.hooks.after_save(&self.name, &data);
+        match previous {
+            None => return Ok(UpsertOutcome::Created),
+            Some(_) => return Ok(UpsertOutcome::Replaced),
+        }
+    }
+
+    pub fn modify<F>(&self, id: impl Key, f: F) -> Result<T, DBError>
+    where
+        T: Serialize + DeserializeOwned + 'static,
+        F: FnOnce(T) -> T,
+    {
+        self.expire()?;
+        let id = id.to_key();
+        let current = match self.tree.get(id.as_ref())? {
+            None => return Err(DBError::new(DBErrorKind::NotFound("modify operation failed".to_string()))),
+            Some(bytes) => bytes,
+        };
+
+        let mut updated = f(self.decode(id.as_ref(), &current)?);
+        self.before_save(&mut updated)?;
+        self.commit(id.as_ref(), Some(&updated), Expect::Current(current))?;
+        self.shared.hooks.after_save(&self.name, &updated);
+        return Ok(updated);
+    }
+
+    /// Merges `patch` into the record under `id` as a JSON merge patch, e.g.
+    /// `{"name": "Ann"}` sets one field and leaves the others alone, then stores and
+    /// returns the result. Fails if the merged value no longer decodes as `T`.
+    pub fn patch(&self, id: impl Key, patch: json::Value) -> Result<T, DBError>
+    where
+        T: Serialize + DeserializeOwned + 'static,
+    {
+        self.expire()?;
+        let id = id.to_key();
+        let current = match self.tree.get(id.as_ref())? {
+            None => return Err(DBError::new(DBErrorKind::NotFound("patch operation failed".to_string()))),
+            Some(bytes) => bytes,
+        };
+
+        let mut value = match json::to_value(&self.decode(id.as_ref(), &current)?) {
+            Err(err) => return Err(DBError::with_source(DBErrorKind::SerializeFailed("record as json".to_string()), err)),
+            Ok(value) => value,
+        };
+        value.merge(patch);
+        let mut updated: T = match json::from_value(value) {
+            Err(err) => return Err(DBError::with_source(DBErrorKind::DeserializeFailed("patched record".to_string()), err)),
+            Ok(updated) => updated,
+        };
+        self.before_save(&mut updated)?;
+        self.commit(id.as_ref(), Some(&updated), Expect::Current(current))?;
+        self.shared.hooks.after_save(&self.name, &updated);
+        return Ok(updated);
+    }
+
+    /// Rewrites every record stored as `Old` into `T`, keeping indexes in step.
+    /// Meant for migrations after a struct change; returns how many were converted.
+    pub fn convert<Old, F>(&self, f: F) -> Result<usize, DBError>
+    where
+        T: Serialize + 'static,
+        Old: DeserializeOwned,
+        F: Fn(Old) -> T,
+    {
+        let mut converted = 0;
+        for entry in self.tree.iter() {
+            let (key, bytes) = entry?;
+            let updated = f(self.codec.decode_for(&self.name, &key, schema::payload(self.schema().as_ref(), &bytes))?);
+            self.commit(&key, Some(&updated), Expect::Current(bytes))?;
+            converted += 1;
+        }
+        return Ok(converted);
+    }
+
+    /// Swaps `id` from `expected` to `new` only if it still holds `expected`, where
+    /// `None` means no record. Values are compared decoded, so codecs that encode the
+    /// same value differently each time, like `Encrypted`, work too. On a mismatch
+    /// nothing is written and the inner `Err` holds what is stored instead.
+    pub fn cas(&self, id: impl Key, expected: Option<T>, mut new: Option<T>) -> Result<Result<(), Option<T>>, DBError>
+    where
+        T: Serialize + DeserializeOwned + PartialEq + 'static,
+    {
+        if let Some(new) = &mut new {
+            self.before_save(new)?;
+        }
+
+        let id = id.to_key();
+        loop {
+            let stored = self.tree.get(id.as_ref())?;
+            let current = match &stored {
+                None => None,
+                Some(bytes) => Some(self.decode(id.as_ref(), bytes)?),
+            };
+            if current != expected {
+                return Ok(Err(current));
+            }
+
+            // the bytes just read are what has to be there still when the swap lands
+            let expect = match stored {
+                None => Expect::Absent,
+                Some(bytes) => Expect::Current(bytes),
+            };
+            match self.commit(id.as_ref(), new.as_ref(), expect) {
+                // changed in between, compare again
+                Err(err) if matches!(err.kind(), DBErrorKind::Conflict(_)) => continue,
+                Err(err) => return Err(err),
+                Ok(_) => {
+                    if let Some(new) = &new {
+                        self.shared.hooks.after_save(&self.name, new);
+                    }
+                    return Ok(Ok(()));
+                }
+            }
+        }
+    }
+
+    /// Takes the advisory lock on `id`, waiting for whoever holds it to drop their
+    /// guard, so read-modify-write cycles on one record can take turns. Only callers of
+    /// `lock` and `try_lock` in this process are held back, writes go through as usual.
+    pub fn lock(&self, id: impl Key) -> KeyGuard {
+        return self.shared.locks.lock(&self.name, id.to_key().as_ref());
+    }
+
+    /// Like `lock`, giving up with `None` instead of waiting when the lock is held.
+    pub fn try_lock(&self, id: impl Key) -> Option<KeyGuard> {
+        return self.shared.locks.try_lock(&self.name, id.to_key().as_ref());
+    }
+
+    /// Takes a lease on `id` for `ttl`, `None` while someone else holds one that has not
+    /// expired. Leases are stored in the database, unlike `lock`, so a worker that
+    /// crashes holds the record only until its lease runs out. Like `lock`, they are
+    /// advisory and writes go through as usual.
+    pub fn acquire_lease(&self, id: impl Key, ttl: Duration) -> Result<Option<Lease>, DBError> {
+        self.shared.writable()?;
+        let leases = self.conn.open_tree(format!("__lease/{}", self.name))?;
+        return lease::acquire(&leases, id.to_key().as_ref(), ttl);
+    }
+
+    /// Removes and returns the record with the lowest key, so that of several callers
+    /// racing for it exactly one gets it, e.g. to take the next job from a queue keyed
+    /// by priority or time. Like `cas`, this skips delete hooks, soft delete and relations.
+    pub fn pop_min(&self) -> Result<Option<(IVec, T)>, DBError>
+    where
+        T: Serialize + DeserializeOwned + 'static,
+    {
+        return self.pop(|tree| tree.first());
+    }
+
+    /// Like `pop_min`, taking the record with the highest key.
+    pub fn pop_max(&self) -> Result<Option<(IVec, T)>, DBError>
+    where
+        T: Serialize + DeserializeOwned + 'static,
+    {
+        return self.pop(|tree| tree.last());
+    }
+
+    fn pop<F>(&self, end: F) -> Result<Option<(IVec, T)>, DBError>
+    where
+        T: Serialize + DeserializeOwned + 'static,
+        F: Fn(&Tree) -> sled::Result<Option<(IVec, IVec)>>,
+    {
+        self.expire()?;
+        loop {
+            let (id, bytes) = match end(&self.tree)? {
+                None => return Ok(None),
+                Some(entry) => entry,
+            };
+            // decoded before removing, so a record that does not decode stays put
+            let data = self.decode(&id, &bytes)?;
+            match self.commit(&id, None, Expect::Current(bytes)) {
+                // someone else took or changed it first, look again
+                Err(err) if matches!(err.kind(), DBErrorKind::Conflict(_)) => continue,
+                Err(err) => return Err(err),
+                Ok(_) => return Ok(Some((id, data))),
+            }
+        }
+    }
+
+    /// Sets how `merge` folds an operand into a stored record, so counters, lists and
+    /// sets can be updated without reading them first. `f` gets the current record, if
+    /// any, and returns the new one; `None` removes it. sled keeps merge operators in
+    /// memory only, so this has to be called again every time the database is opened.
+    pub fn set_merge_operator<M, F>(&self, f: F)
+    where
+        T: Serialize + DeserializeOwned + 'static,
+        M: DeserializeOwned,
+        F: Fn(Option<T>, M) -> Option<T> + Send + Sync + 'static,
+        C: Send + Sync + 'static,
+    {
+        // only the codec and schemas are captured, the tree must not keep its own database alive
+        let codec = self.codec.clone();
+        let schemas = self.shared.schemas.clone();
+        let name = self.name.clone();
+        self.tree.set_merge_operator(move |id: &[u8], old: Option<&[u8]>, operand: &[u8]| {
+            let schema = schemas.get(&name);
+            match apply_merge(&codec, &name, schema.as_ref(), id, old, operand, &f) {
+                // sled cannot fail a merge, an operand that does not decode leaves the record alone
+                Err(_) => return old.map(|bytes| bytes.to_vec()),
+                Ok(merged) => return merged,
+            }
+        });
+    }
+
+    /// Folds `operand` into `id` with the operator from `set_merge_operator`, returning
+    /// the merged record. Merges skip indexes, version counters and quotas, so they are
+    /// refused on collections that have any of them.
+    pub fn merge<M>(&self, id: impl Key, operand: M) -> Result<Option<T>, DBError>
+    where
+        T: DeserializeOwned,
+        M: Serialize,
+    {
+        if !self.shared.indexes.for_collection(&self.name).is_empty() || self.shared.versions.get(&self.name).is_some() {
+            return Err(DBError::new(DBErrorKind::Other(format!(
+                "{} has indexes or version counters and cannot be merged into",
+                self.name
+            ))));
+        }
+        // sled merges on its own, so the usage count of a quota could not follow along
+        if self.quota()?.is_some() {
+            return Err(DBError::new(DBErrorKind::Other(format!("{} has a quota and cannot be merged into", self.name))));
+        }
+
+        self.shared.writable()?;
+        self.expire()?;
+        let id = id.to_key();
+        let operand = self.codec.encode_for(&self.name, id.as_ref(), &operand)?;
+        limit::check_size(&self.name, &operand, self.max_record_size()?)?;
+        let merged = self.tree.merge(id.as_ref(), operand)?;
+        self.make_durable()?;
+        match merged {
+            None => return Ok(None),
+            Some(bytes) => return Ok(Some(self.decode(id.as_ref(), &bytes)?)),
+        }
+    }
+
+    /// Deletes the record under `id`, returning it, or `None` if there was none. The
+    /// record is decoded before anything is deleted, so one that no longer decodes as
+    /// `T` stays where it is.
+    pub fn delete(&self, id: impl Key) -> Result<Option<T>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        let id = id.to_key();
+        return traced("delete", &self.name, id.as_ref(), || {
+            self.expire()?;
+            let previous = match self.tree.get(id.as_ref())? {
+                None => return Ok(None),
+                Some(bytes) => self.decode(id.as_ref(), &bytes)?,
+            };
+            match self.delete_record(id.as_ref())? {
+                None => return Ok(None),
+                Some(_) => return Ok(Some(previous)),
+            }
+        });
+    }
+
+    /// Like `delete`, without decoding what was deleted.
+    #[cfg(feature = "cli")]
+    pub(super) fn delete_raw(&self, id: &[u8]) -> Result<Option<IVec>, DBError> {
+        return traced("delete", &self.name, id, || {
+            self.expire()?;
+            return self.delete_record(id);
+        });
+    }
+
+    fn delete_record(&self, id: &[u8]) -> Result<Option<IVec>, DBError> {
+        self.shared.relations.check_delete(&self.shared.indexes, &self.name, id)?;
+        if let Some(bytes) = self.hooked(id)? {
+            self.shared.hooks.before_delete(&self.name, id, &bytes)?;
+        }
+        let previous = match self.tree.get(id)? {
+            None => return Ok(None),
+            Some(previous) => previous,
+        };
+        let dependents = self.shared.relations.dependents(&self.shared.indexes, &self.name, id)?;
+        let removed = match (dependents.is_empty(), self.soft_delete_enabled()?) {
+            (true, true) => self.move_to_trash(id)?,
+            (true, false) => self.remove(id)?.is_some(),
+            (false, true) => self.remove_cascading(id, Some(self.trash()?), &dependents)?,
+            (false, false) => self.remove_cascading(id, None, &dependents)?,
+        };
+        if !removed {
+            return Ok(None);
+        }
+        self.make_durable()?;
+        return Ok(Some(previous));
+    }
+
+    /// Deletes every record `predicate` accepts, returning how many. Records are matched
+    /// and removed `DELETE_BATCH` at a time, each batch atomically; one written again
+    /// between being matched and its batch being removed is left alone.
+    ///
+    /// Collections with delete hooks, soft delete or relations to children go through
+    /// `delete` one record at a time, as each record may be refused or cascade.
+    pub fn delete_where<P>(&self, predicate: P) -> Result<usize, DBError>
+    where
+        T: DeserializeOwned,
+        P: Fn(&T) -> bool,
+    {
+        self.shared.writable()?;
+        self.expire()?;
+        let one_by_one = self.shared.hooks.on_delete(&self.name) || self.soft_delete_enabled()? || self.shared.relations.is_parent(&self.name);
+
+        let mut deleted = 0;
+        let mut after = None;
+        loop {
+            let (matched, exhausted) = self.next_matches(&mut after, DELETE_BATCH, &predicate)?;
+            if one_by_one {
+                for (id, _, _) in &matched {
+                    if traced("delete", &self.name, id, || self.delete_record(id))?.is_some() {
+                        deleted += 1;
+                    }
+                }
+            } else {
+                deleted += self.remove_batch(&matched)?;
+            }
+            if exhausted {
+                self.make_durable()?;
+                return Ok(deleted);
+            }
+        }
+    }
+
+    /// Replaces every record `predicate` accepts with what `f` makes of it, returning
+    /// how many were rewritten. Records are matched and written `UPDATE_BATCH` at a
+    /// time, each batch atomically together with its index entries; one written again
+    /// between being matched and its batch being written is left alone. A batch that
+    /// fails, e.g. on a unique index, writes nothing, but earlier batches stay written.
+    pub fn update_where<P, F>(&self, predicate: P, f: F) -> Result<usize, DBError>
+    where
+        T: Serialize + DeserializeOwned + 'static,
+        P: Fn(&T) -> bool,
+        F: Fn(T) -> T,
+    {
+        self.shared.writable()?;
+        self.expire()?;
+        let indexes = self.shared.indexes.for_collection(&self.name);
+        let versions = self.shared.versions.get(&self.name);
+        let quota = self.quota()?;
+        let trees = self.trees(&indexes, &versions, quota)?;
+
+        let limit = self.max_record_size()?;
+        let mut updated = 0;
+        let mut after = None;
+        loop {
+            let (matched, exhausted) = self.next_matches(&mut after, UPDATE_BATCH, &predicate)?;
+            let mut rewritten = Vec::with_capacity(matched.len());
+            for (id, old, data) in matched {
+                let mut data = f(data);
+                self.before_save(&mut data)?;
+                let value = self.encode(&id, &data)?;
+                limit::check_size(&self.name, &value, limit)?;
+                let keys: Vec<Vec<Vec<u8>>> = indexes.iter().map(|index| index.keys(&data)).collect();
+                rewritten.push((id, old, data, value, keys));
+            }
+            for (id, _, _, _, _) in &rewritten {
+                self.shared.access.write(&self.name, id);
+            }
+
+            let written = trees[..].transaction(|views| {
+                let index_views = pair_views(&indexes, &views[1..]);
+                let version_view = version_view(views, &indexes, &versions);
+                let usage = self.usage(views, quota);
+                let mut written = Vec::new();
+                for (n, (id, old, _, value, keys)) in rewritten.iter().enumerate() {
+                    if views[0].get(id)?.as_ref() == Some(old) {
+                        let previous = write_entry(&views[0], &index_views, version_view, id, Some(value.clone()), keys)?;
+                        limit::track(usage.as_ref(), id, previous.as_deref(), Some(value))?;
+                        written.push(n);
+                    }
+                }
+                return Ok::<_, ConflictableTransactionError<DBError>>(written);
+            })?;
+
+            for n in written {
+                let (id, _, data, _, _) = &rewritten[n];
+                self.written(id, AuditOp::Update, data)?;
+                self.shared.hooks.after_save(&self.name, data);
+                updated += 1;
+            }
+            if exhausted {
+                self.make_durable()?;
+                return Ok(updated);
+            }
+        }
+    }
+
+    // up to `limit` records after `after` that `predicate` accepts, with their stored
+    // bytes, moving `after` along; the flag says whether the collection ran out
+    fn next_matches<P>(&self, after: &mut Option<IVec>, limit: usize, predicate: &P) -> Result<(Matches<T>, bool), DBError>
+    where
+        T: DeserializeOwned,
+        P: Fn(&T) -> bool,
+    {
+        let entries = match after {
+            None => self.tree.iter(),
+            Some(after) => self.tree.range((Bound::Excluded(after.clone()), Bound::Unbounded)),
+        };
+        let mut matched = Vec::new();
+        for entry in entries {
+            let (id, value) = entry?;
+            *after = Some(id.clone());
+            let data = self.decode(&id, &value)?;
+            if predicate(&data) {
+                matched.push((id, value, data));
+                if matched.len() == limit {
+                    return Ok((matched, false));
+                }
+            }
+        }
+        return Ok((matched, true));
+    }
+
+    // removes the records that still hold the values they were matched with, in one transaction
+    fn remove_batch(&self, matched: &Matches<T>) -> Result<usize, DBError> {
+        if matched.is_empty() {
+            return Ok(0);
+        }
+        let indexes = self.shared.indexes.for_collection(&self.name);
+        let versions = self.shared.versions.get(&self.name);
+        let keys = vec![Vec::new(); indexes.len()];
+        let quota = self.quota()?;
+        let trees = self.trees(&indexes, &versions, quota)?;
+        let removed = trees[..].transaction(|views| {
+            let index_views = pair_views(&indexes, &views[1..]);
+            let version_view = version_view(views, &indexes, &versions);
+            let usage = self.usage(views, quota);
+            let mut removed = Vec::new();
+            for (id, value, _) in matched {
+                if views[0].get(id)?.as_ref() == Some(value) {
+                    let previous = write_entry(&views[0], &index_views, version_view, id, None, &keys)?;
+                    limit::track(usage.as_ref(), id, previous.as_deref(), None)?;
+                    removed.push(id.clone());
+                }
+            }
+            return Ok::<_, ConflictableTransactionError<DBError>>(removed);
+        })?;
+
+        for id in &removed {
+            if let Some(expiries) = self.shared.ttls.get(&self.name) {
+                ttl::clear(&expiries, id)?;
+            }
+            self.record_delete(id)?;
+        }
+        return Ok(removed.len());
+    }
+
+    /// Starts keeping a version counter per record that goes up on every write.
+    /// Records already stored count as version 0 until they are next written.
+    pub fn track_versions(&self) -> Result<(), DBError> {
+        self.shared.writable()?;
+        self.shared.versions.get_or_open(&self.conn, &self.name)?;
+        return Ok(());
+    }
+
+    /// The current version of `id`, `None` when there is no such record.
+    pub fn version(&self, id: impl Key) -> Result<Option<u64>, DBError> {
+        let versions = match self.shared.versions.get(&self.name) {
+            None => return Err(untracked(&self.name)),
+            Some(versions) => versions,
+        };
+        let id = id.to_key();
+        if !self.tree.contains_key(id.as_ref())? {
+            return Ok(None);
+        }
+        return Ok(Some(version::parse(versions.get(id.as_ref())?)));
+    }
+
+    /// Replaces `id` only while it is still at version `expected`, returning the new
+    /// version; somebody else having written it since fails with `DBErrorKind::Conflict`.
+    pub fn update_if_version(&self, id: impl Key, expected: u64, mut data: T) -> Result<u64, DBError>
+    where
+        T: Serialize + 'static,
+    {
+        self.before_save(&mut data)?;
+        self.commit(id.to_key().as_ref(), Some(&data), Expect::Version(expected))?;
+        self.shared.hooks.after_save(&self.name, &data);
+        return Ok(expected + 1);
+    }
+
+    /// With soft delete on, `delete` moves records aside with a timestamp instead of
+    /// removing them, so they can be brought back with `restore`. The setting is stored
+    /// in the database.
+    pub fn set_soft_delete(&self, enabled: bool) -> Result<(), DBError> {
+        self.shared.writable()?;
+        let meta = self.conn.open_tree(META_TREE)?;
+        match enabled {
+            true => meta.insert(self.soft_delete_key(), &[1])?,
+            false => meta.remove(self.soft_delete_key())?,
+        };
+        return Ok(());
+    }
+
+    /// Caps the encoded size of records written here, so oversized ones fail with
+    /// `DBErrorKind::TooLarge` instead of being stored. `None` goes back to the limit
+    /// set with `DBManager::with_max_record_size`, if any. The setting is stored in the
+    /// database; records already stored are left alone.
+    pub fn set_max_record_size(&self, bytes: Option<usize>) -> Result<(), DBError> {
+        self.shared.writable()?;
+        return limit::set_max_record_size(&self.conn, &self.name, bytes);
+    }
+
+    /// The limit writes here are held to, see `set_max_record_size`.
+    pub fn max_record_size(&self) -> Result<Option<usize>, DBError> {
+        return limit::max_record_size(&self.conn, &self.shared, &self.name);
+    }
+
+    /// Caps how many records, and how many bytes of keys and values, this collection
+    /// holds; writes that would go past it fail with `DBErrorKind::QuotaExceeded`,
+    /// while deletes always go through. `None` removes the quota. The setting is stored
+    /// in the database along with a count of what the collection holds, which every
+    /// write then updates in its own transaction, so checks are cheap and hold under
+    /// concurrent writers. Setting a quota counts the records once; `merge` is refused
+    /// while there is one.
+    pub fn set_quota(&self, quota: Option<Quota>) -> Result<(), DBError> {
+        self.shared.writable()?;
+        return limit::set_quota(&self.conn, &self.tree, &self.name, quota);
+    }
+
+    pub fn quota(&self) -> Result<Option<Quota>, DBError> {
+        return limit::quota(&self.conn, &self.shared, &self.name);
+    }
+
+    /// The `n` keys of this collection read and written most while access tracking was
+    /// on, see `DBManager::track_access`.
+    pub fn hot_keys(&self, n: usize) -> Vec<HotKey> {
+        return self.shared.access.hottest(Some(&self.name), n);
+    }
+
+    pub fn soft_delete_enabled(&self) -> Result<bool, DBError> {
+        match self.shared.existing(&self.conn, META_TREE)? {
+            None => return Ok(false),
+            Some(meta) => return Ok(meta.contains_key(self.soft_delete_key())?),
+        }
+    }
+
+    /// Brings back a soft-deleted record, failing if the id has been reused since.
+    pub fn restore(&self, id: impl Key) -> Result<(), DBError>
+    where
+        T: Serialize + DeserializeOwned + 'static,
+    {
+        self.shared.writable()?;
+        let trash = self.trash()?;
+        let id = id.to_key();
+        let id = id.as_ref();
+        let entry = match trash.get(id)? {
+            None => return Err(DBError::new(DBErrorKind::NotFound("restore operation failed".to_string()))),
+            Some(entry) => entry,
+        };
+        if self.tree.contains_key(id)? {
+            let id = String::from_utf8_lossy(id);
+            return Err(DBError::new(DBErrorKind::WriteFailed(format!("{} has been replaced", id))));
+        }
+
+        let (_, bytes) = trashed(id, &entry)?;
+        let data = self.decode(id, bytes)?;
+        self.commit(id, Some(&data), Expect::Any)?;
+        trash.remove(id)?;
+        return Ok(());
+    }
+
+    /// Every soft-deleted record, in id order.
+    pub fn deleted(&self) -> Result<Vec<Tombstone<T>>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        let trash = match self.shared.existing(&self.conn, &self.trash_name())? {
+            None => return Ok(Vec::new()),
+            Some(trash) => trash,
+        };
+        let mut tombstones = Vec::new();
+        for entry in trash.iter() {
+            let (id, entry) = entry?;
+            let (millis, bytes) = trashed(&id, &entry)?;
+            tombstones.push(Tombstone {
+                id: String::from_utf8_lossy(&id).into_owned(),
+                data: self.decode(&id, bytes)?,
+                deleted_at: UNIX_EPOCH + Duration::from_millis(millis),
+            });
+        }
+        return Ok(tombstones);
+    }
+
+    /// `get_all` with the soft-deleted records appended.
+    pub fn get_all_including_deleted(&self) -> Result<Vec<T>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        let mut records = self.get_all()?;
+        records.extend(self.deleted()?.into_iter().map(|tombstone| tombstone.data));
+        return Ok(records);
+    }
+
+    /// Removes a record for good, whether it is live or soft-deleted.
+    pub fn hard_delete(&self, id: impl Key) -> Result<bool, DBError> {
+        self.shared.writable()?;
+        let id = id.to_key();
+        let id = id.as_ref();
+        self.shared.relations.check_delete(&self.shared.indexes, &self.name, id)?;
+        let stored = match self.hooked(id)? {
+            None if self.shared.hooks.on_delete(&self.name) => self.trash()?.get(id)?.map(|entry| entry.subslice(8, entry.len() - 8)),
+            stored => stored,
+        };
+        if let Some(bytes) = stored {
+            self.shared.hooks.before_delete(&self.name, id, &bytes)?;
+        }
+        let trashed = self.trash()?.remove(id)?.is_some();
+        let dependents = self.shared.relations.dependents(&self.shared.indexes, &self.name, id)?;
+        if !dependents.is_empty() {
+            let removed = self.remove_cascading(id, None, &dependents)?;
+            self.make_durable()?;
+            return Ok(removed || trashed);
+        }
+        let removed = self.remove(id)?.is_some();
+        self.make_durable()?;
+        return Ok(removed || trashed);
+    }
+
+    // hooks run first, so normalised values are what gets validated
+    fn before_save(&self, data: &mut T) -> Result<(), DBError>
+    where
+        T: 'static,
+    {
+        self.shared.hooks.before_save(&self.name, data)?;
+        return self.shared.validators.check(&self.name, data);
+    }
+
+    // the stored record, when a delete hook needs to see it first
+    fn hooked(&self, id: &[u8]) -> Result<Option<IVec>, DBError> {
+        if !self.shared.hooks.on_delete(&self.name) {
+            return Ok(None);
+        }
+        return Ok(self.tree.get(id)?);
+    }
+
+    fn saved_all(&self, ids: &[String], records: &[T]) -> Result<(), DBError>
+    where
+        T: 'static,
+    {
+        for (id, data) in ids.iter().zip(records) {
+            self.written(id.as_bytes(), AuditOp::Insert, data)?;
+            self.shared.hooks.after_save(&self.name, data);
+        }
+        return Ok(());
+    }
+
+    // deletes along with the children of relations that cascade or null out
+    fn remove_cascading(&self, id: &[u8], trash: Option<Tree>, dependents: &[Dependents]) -> Result<bool, DBError> {
+        return relation::delete_cascading(&self.conn, &self.shared, &self.name, id, trash, dependents, self.actor.as_deref());
+    }
+
+    /// Permanently drops every soft-deleted record, returning how many there were.
+    pub fn empty_trash(&self) -> Result<usize, DBError> {
+        self.shared.writable()?;
+        let trash = self.trash()?;
+        let count = trash.len();
+        trash.clear()?;
+        return Ok(count);
+    }
+
+    fn soft_delete_key(&self) -> Vec<u8> {
+        return format!("soft_delete/{}", self.name).into_bytes();
+    }
+
+    fn trash_name(&self) -> String {
+        return format!("__trash/{}", self.name);
+    }
+
+    fn trash(&self) -> Result<Tree, DBError> {
+        return Ok(self.conn.open_tree(self.trash_name())?);
+    }
+
+    // removes the record and files it in the trash, stamped with the deletion time, in one go
+    fn move_to_trash(&self, id: &[u8]) -> Result<bool, DBError> {
+        self.shared.writable()?;
+        if let Some(expiries) = self.shared.ttls.get(&self.name) {
+            ttl::clear(&expiries, id)?;
+        }
+
+        let indexes = self.shared.indexes.for_collection(&self.name);
+        let keys = vec![Vec::new(); indexes.len()];
+        let versions = self.shared.versions.get(&self.name);
+        let quota = self.quota()?;
+        let mut trees = self.trees(&indexes, &versions, quota)?;
+        trees.push(self.trash()?);
+        let deleted_at = ttl::now_millis().to_be_bytes();
+
+        let moved = trees[..].transaction(|views| {
+            let (trash, views) = views.split_last().unwrap();
+            let index_views = pair_views(&indexes, &views[1..]);
+            let version_view = version_view(views, &indexes, &versions);
+            let previous = match write_entry(&views[0], &index_views, version_view, id, None, &keys)? {
+                None => return Ok::<_, ConflictableTransactionError<DBError>>(false),
+                Some(previous) => previous,
+            };
+            limit::track(self.usage(views, quota).as_ref(), id, Some(&previous), None)?;
+            trash.insert(id, [&deleted_at[..], &previous].concat())?;
+            return Ok(true);
+        })?;
+        if moved {
+            self.record_delete(id)?;
+        }
+        return Ok(moved);
+    }
+
+    /// Indexes `T` by the value `f` extracts, rebuilding the index from the
+    /// records already stored. Meant to be called once at startup.
+    pub fn create_index<K, F>(&self, name: &str, f: F) -> Result<(), DBError>
+    where
+        T: DeserializeOwned + 'static,
+        K: AsRef<[u8]>,
+        F: Fn(&T) -> K + Send + Sync + 'static,
+    {
+        return self.add_index(name, false, f);
+    }
+
+    /// Like `create_index`, but a second record with the same value is
+    /// rejected with `DBErrorKind::UniqueViolation`.
+    pub fn create_unique_index<K, F>(&self, name: &str, f: F) -> Result<(), DBError>
+    where
+        T: DeserializeOwned + 'static,
+        K: AsRef<[u8]>,
+        F: Fn(&T) -> K + Send + Sync + 'static,
+    {
+        return self.add_index(name, true, f);
+    }
+
+    fn add_index<K, F>(&self, name: &str, unique: bool, f: F) -> Result<(), DBError>
+    where
+        T: DeserializeOwned + 'static,
+        K: AsRef<[u8]>,
+        F: Fn(&T) -> K + Send + Sync + 'static,
+    {
+        return self.add_index_values(name, unique, move |data: &T| vec![f(data).as_ref().to_vec()]);
+    }
+
+    // like `add_index`, for records indexed under any number of values
+    pub(super) fn add_index_values<F>(&self, name: &str, unique: bool, f: F) -> Result<(), DBError>
+    where
+        T: DeserializeOwned + 'static,
+        F: Fn(&T) -> Vec<Vec<u8>> + Send + Sync + 'static,
+    {
+        // a read-only database can use an index built earlier, but not build one
+        let tree_name = index_tree_name(&self.name, name);
+        let tree = match self.shared.existing(&self.conn, &tree_name)? {
+            None => return Err(DBError::new(DBErrorKind::ReadOnly(format!("index {} was never built", name)))),
+            Some(tree) => tree,
+        };
+        let entry = IndexEntry::new(name, tree, unique, f);
+        if !self.shared.read_only {
+            entry.rebuild(&self.tree, |id, bytes| self.decode(id, bytes))?;
+        }
+        self.shared.indexes.register(&self.name, entry);
+        return Ok(());
+    }
+
+    /// Indexes `T` under each of the values `f` extracts, e.g. a list of tags, so
+    /// `find_by_tag` finds a record by any one of them. Meant to be called once at startup.
+    pub fn create_tag_index<K, F>(&self, name: &str, f: F) -> Result<(), DBError>
+    where
+        T: DeserializeOwned + 'static,
+        K: AsRef<[u8]>,
+        F: Fn(&T) -> Vec<K> + Send + Sync + 'static,
+    {
+        return self.add_index_values(name, false, move |data: &T| {
+            let mut tags: Vec<Vec<u8>> = f(data).iter().map(|tag| tag.as_ref().to_vec()).collect();
+            tags.sort();
+            tags.dedup();
+            return tags;
+        });
+    }
+
+    /// Records carrying `tag` in the tag index `name`.
+    pub fn find_by_tag(&self, name: &str, tag: impl AsRef<[u8]>) -> Result<Vec<T>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        return self.find_by_index(name, tag);
+    }
+
+    /// Indexes the words of the text fields `f` extracts, so `search` finds records by
+    /// them without a scan. Meant to be called once at startup.
+    pub fn create_text_index<F>(&self, f: F) -> Result<(), DBError>
+    where
+        T: DeserializeOwned + 'static,
+        F: Fn(&T) -> Vec<String> + Send + Sync + 'static,
+    {
+        return self.add_index_values(search::TEXT_INDEX, false, move |data: &T| search::tokens(&f(data)));
+    }
+
+    /// Records containing every word of `query`, in key order. Words are matched
+    /// whole and regardless of case.
+    pub fn search(&self, query: &str) -> Result<Vec<T>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        self.expire()?;
+        let index = match self.shared.indexes.find(&self.name, search::TEXT_INDEX) {
+            None => return Err(DBError::new(DBErrorKind::NotFound(format!("text index on {}", self.name)))),
+            Some(index) => index,
+        };
+        let terms = search::terms(query);
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut postings = Vec::with_capacity(terms.len());
+        for term in &terms {
+            postings.push(index.ids_for(term.as_bytes())?);
+        }
+        let mut records = Vec::new();
+        for id in search::intersect(postings) {
+            if let Some(bytes) = self.tree.get(&id)? {
+                records.push(self.decode(&id, &bytes)?);
+            }
+        }
+        return Ok(records);
+    }
+
+    pub fn find_by_index(&self, name: &str, value: impl AsRef<[u8]>) -> Result<Vec<T>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        self.expire()?;
+        let index = self.index(name)?;
+        let mut records = Vec::new();
+        for id in index.ids_for(value.as_ref())? {
+            if let Some(bytes) = self.tree.get(&id)? {
+                records.push(self.decode(&id, &bytes)?);
+            }
+        }
+        return Ok(records);
+    }
+
+    pub(super) fn expire(&self) -> Result<usize, DBError> {
+        let expiries = match self.shared.ttls.get(&self.name) {
+            None => return Ok(0),
+            Some(_) if self.shared.read_only => return Ok(0),
+            Some(expiries) => expiries,
+        };
+
+        let mut removed = 0;
+        for id in ttl::due(&expiries, ttl::now_millis())? {
+            ttl::clear(&expiries, &id)?;
+            if self.remove(&id)?.is_some() {
+                removed += 1;
+            }
+        }
+        return Ok(removed);
+    }
+
+    /// The schema this collection's records are versioned with, if any.
+    pub(super) fn schema(&self) -> Option<Schema> {
+        return self.shared.schemas.get(&self.name);
+    }
+
+    pub(super) fn encode(&self, id: &[u8], data: &T) -> Result<Vec<u8>, DBError>
+    where
+        T: Serialize,
+    {
+        let bytes = self.codec.encode_for(&self.name, id, data)?;
+        return Ok(schema::stamp(self.shared.schemas.get(&self.name).as_ref(), bytes));
+    }
+
+    pub(super) fn decode(&self, id: &[u8], bytes: &[u8]) -> Result<T, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        return self.decode_as(id, bytes);
+    }
+
+    fn decode_as<P: DeserializeOwned>(&self, id: &[u8], bytes: &[u8]) -> Result<P, DBError> {
+        let bytes = schema::current(self.shared.schemas.get(&self.name).as_ref(), id, bytes)?;
+        return self.codec.decode_for(&self.name, id, &bytes);
+    }
+
+    fn index(&self, name: &str) -> Result<IndexEntry, DBError> {
+        match self.shared.indexes.find(&self.name, name) {
+            None => return Err(DBError::new(DBErrorKind::NotFound(format!("index {}", name)))),
+            Some(index) => return Ok(index),
+        }
+    }
+
+    // the data tree, then its index trees, then the version counters if there are any
+    // the trees a write takes part in: the data, its indexes, its version counters and,
+    // last, the usage count when there is a quota
+    fn trees(&self, indexes: &[IndexEntry], versions: &Option<Tree>, quota: Option<Quota>) -> Result<Vec<Tree>, DBError> {
+        let mut trees = Vec::with_capacity(indexes.len() + 3);
+        trees.push(self.tree.clone());
+        trees.extend(indexes.iter().map(|index| index.tree.clone()));
+        trees.extend(versions.iter().cloned());
+        if quota.is_some() {
+            trees.push(self.conn.open_tree(META_TREE)?);
+        }
+        return Ok(trees);
+    }
+
+    fn usage<'a>(&'a self, views: &'a [TransactionalTree], quota: Option<Quota>) -> Option<Usage<'a>> {
+        return quota.map(|quota| Usage::new(&self.name, quota, views.last().unwrap()));
+    }
+
+    // removing needs no type information, index entries are found through their reverse keys
+    fn remove(&self, id: &[u8]) -> Result<Option<IVec>, DBError> {
+        let previous = self.remove_unaudited(id)?;
+        if previous.is_some() {
+            self.record_delete(id)?;
+        }
+        return Ok(previous);
+    }
+
+    fn remove_unaudited(&self, id: &[u8]) -> Result<Option<IVec>, DBError> {
+        self.shared.writable()?;
+        if let Some(expiries) = self.shared.ttls.get(&self.name) {
+            ttl::clear(&expiries, id)?;
+        }
+
+        let indexes = self.shared.indexes.for_collection(&self.name);
+        let versions = self.shared.versions.get(&self.name);
+        let quota = self.quota()?;
+        if indexes.is_empty() && versions.is_none() && quota.is_none() {
+            return Ok(self.tree.remove(id)?);
+        }
+
+        let keys = vec![Vec::new(); indexes.len()];
+        let trees = self.trees(&indexes, &versions, quota)?;
+        let previous = trees[..].transaction(|views| {
+            let index_views = pair_views(&indexes, &views[1..]);
+            let version_view = version_view(views, &indexes, &versions);
+            let previous = write_entry(&views[0], &index_views, version_view, id, None, &keys)?;
+            limit::track(self.usage(views, quota).as_ref(), id, previous.as_deref(), None)?;
+            return Ok::<_, ConflictableTransactionError<DBError>>(previous);
+        })?;
+        return Ok(previous);
+    }
+
+    // every typed write funnels through here so secondary indexes never drift from the data
+    fn commit(&self, id: &[u8], data: Option<&T>, expect: Expect) -> Result<Option<IVec>, DBError>
+    where
+        T: Serialize + 'static,
+    {
+        let previous = self.commit_unaudited(id, data, expect)?;
+        match (data, &previous) {
+            (None, None) => {}
+            (None, Some(_)) => self.record_delete(id)?,
+            (Some(data), None) => self.written(id, AuditOp::Insert, data)?,
+            (Some(data), Some(_)) => self.written(id, AuditOp::Update, data)?,
+        }
+        self.make_durable()?;
+        return Ok(previous);
+    }
+
+    fn commit_unaudited(&self, id: &[u8], data: Option<&T>, expect: Expect) -> Result<Option<IVec>, DBError>
+    where
+        T: Serialize + 'static,
+    {
+        self.shared.writable()?;
+        let value = match data {
+            None => None,
+            Some(data) => {
+                let value = self.encode(id, data)?;
+                limit::check_size(&self.name, &value, self.max_record_size()?)?;
+                Some(value)
+            }
+        };
+        self.shared.access.write(&self.name, id);
+
+        let indexes = self.shared.indexes.for_collection(&self.name);
+        let versions = self.shared.versions.get(&self.name);
+        let quota = self.quota()?;
+        if indexes.is_empty() && versions.is_none() && quota.is_none() {
+            match expect {
+                Expect::Any => match value {
+                    None => return Ok(self.tree.remove(id)?),
+                    Some(value) => return Ok(self.tree.insert(id, value)?),
+                },
+                Expect::Exists => {
+                    // only replace a value that is already there, never create one
+                    let previous = self.tree.fetch_and_update(id, |old| old.and(value.clone()))?;
+                    if previous.is_none() {
+                        return Err(not_found());
+                    }
+                    return Ok(previous);
+                }
+                Expect::Current(current) => {
+                    match self.tree.compare_and_swap(id, Some(current.clone()), value)? {
+                        Err(_) => return Err(modified_concurrently()),
+                        Ok(()) => return Ok(Some(current)),
+                    }
+                }
+                Expect::Absent => match self.tree.compare_and_swap(id, None as Option<&[u8]>, value)? {
+                    Err(_) => return Err(modified_concurrently()),
+                    Ok(()) => return Ok(None),
+                },
+                Expect::Version(_) => return Err(untracked(&self.name)),
+            }
+        }
+
+        let keys: Vec<Vec<Vec<u8>>> = indexes
+            .iter()
+            .map(|index| match data {
+                None => Vec::new(),
+                Some(data) => index.keys(data),
+            })
+            .collect();
+
+        let trees = self.trees(&indexes, &versions, quota)?;
+        let previous = trees[..].transaction(|views| {
+            let current = views[0].get(id)?;
+            let version_view = version_view(views, &indexes, &versions);
+            match &expect {
+                Expect::Exists if current.is_none() => return abort(not_found()),
+                Expect::Current(expected) if current.as_ref() != Some(expected) => {
+                    return abort(modified_concurrently())
+                }
+                Expect::Absent if current.is_some() => return abort(modified_concurrently()),
+                Expect::Version(_) if current.is_none() => return abort(not_found()),
+                Expect::Version(expected) => {
+                    let found = match version_view {
+                        None => return abort(untracked(&self.name)),
+                        Some(view) => version::parse(view.get(id)?),
+                    };
+                    if found != *expected {
+                        return abort(version::conflict(id, *expected, found));
+                    }
+                }
+                _ => {}
+            }
+
+            let index_views = pair_views(&indexes, &views[1..]);
+            write_entry(&views[0], &index_views, version_view, id, value.clone(), &keys)?;
+            limit::track(self.usage(views, quota).as_ref(), id, current.as_deref(), value.as_deref())?;
+            return Ok(current);
+        })?;
+        return Ok(previous);
+    }
+}
+
+// a trash entry is the time of the delete in milliseconds, then the record
+fn trashed<'a>(id: &[u8], entry: &'a [u8]) -> Result<(u64, &'a [u8]), DBError> {
+    match entry.split_first_chunk::<8>() {
+        None => return Err(DBError::new(DBErrorKind::ReadFailed(format!("trash entry of {}", String::from_utf8_lossy(id))))),
+        Some((millis, bytes)) => return Ok((u64::from_be_bytes(*millis), bytes)),
+    }
+}
+
+// errors about the record itself, as opposed to the database failing
+fn refuses_record(err: &DBError) -> bool {
+    return matches!(
+        err.kind(),
+        DBErrorKind::UniqueViolation(_)
+            | DBErrorKind::DuplicateKey(_)
+            | DBErrorKind::ConstraintViolation(_)
+            | DBErrorKind::TooLarge(_)
+            | DBErrorKind::QuotaExceeded(_)
+            | DBErrorKind::Validation(_)
+            | DBErrorKind::SerializeFailed(_)
+    );
+}
+
+fn apply_merge<T, M, C, F>(
+    codec: &C,
+    name: &str,
+    schema: Option<&Schema>,
+    id: &[u8],
+    old: Option<&[u8]>,
+    operand: &[u8],
+    f: &F,
+) -> Result<Option<Vec<u8>>, DBError>
+where
+    T: Serialize + DeserializeOwned,
+    M: DeserializeOwned,
+    C: Codec,
+    F: Fn(Option<T>, M) -> Option<T>,
+{
+    let current = match old {
+        None => None,
+        Some(bytes) => Some(codec.decode_for(name, id, &schema::current(schema, id, bytes)?)?),
+    };
+    match f(current, codec.decode_for(name, id, operand)?) {
+        None => return Ok(None),
+        Some(merged) => return Ok(Some(schema::stamp(schema, codec.encode_for(name, id, &merged)?))),
+    }
+}
+
+fn pair_views<'a>(indexes: &'a [IndexEntry], views: &'a [TransactionalTree]) -> Vec<(&'a IndexEntry, &'a TransactionalTree)> {
+    return indexes.iter().zip(views).collect();
+}
+
+fn version_view<'a>(
+    views: &'a [TransactionalTree],
+    indexes: &[IndexEntry],
+    versions: &Option<Tree>,
+) -> Option<&'a TransactionalTree> {
+    return versions.as_ref().map(|_| &views[1 + indexes.len()]);
+}
+
+fn untracked(collection: &str) -> DBError {
+    return DBError::new(DBErrorKind::Other(format!("{} does not track versions", collection)));
+}
+
+fn not_found() -> DBError {
+    return DBError::new(DBErrorKind::NotFound("update operation failed".to_string()));
+}
+
+fn modified_concurrently() -> DBError {
+    return DBError::new(DBErrorKind::Conflict("record was modified concurrently".to_string()));
+}
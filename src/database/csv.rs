@@ -0,0 +1,227 @@
+use std::fmt;
+use std::io::BufRead;
+
+use serde::de::value::MapDeserializer;
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+
+use super::{DBError, DBErrorKind};
+
+/// What `Collection::import_csv` did: the ids of the inserted records and the rows it skipped.
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    pub ids: Vec<String>,
+    pub errors: Vec<RowError>,
+}
+
+/// A row that could not be imported; `line` is where it starts in the input, counting from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowError {
+    pub line: usize,
+    pub message: String,
+}
+
+// a parsed record and the line it starts on
+pub(super) type Record = (usize, Vec<String>);
+
+/// Reads RFC 4180 records one at a time: comma separated, fields optionally quoted with
+/// `"`, quotes doubled inside them, and quoted fields free to span lines.
+pub(super) struct Records<R> {
+    input: R,
+    line: usize,
+}
+
+impl<R: BufRead> Records<R> {
+    pub(super) fn new(input: R) -> Self {
+        return Records { input, line: 0 };
+    }
+
+    /// The next record and the line it starts on. A syntax error is reported as the inner
+    /// `Err`; being unable to tell where the record ends, reading stops there.
+    pub(super) fn next_record(&mut self) -> Result<Option<Result<Record, RowError>>, DBError> {
+        let mut fields = Vec::new();
+        let mut field = Vec::new();
+        let mut quoted = false;
+        let mut was_quoted = false;
+        let mut start = self.line + 1;
+        let mut buf = Vec::new();
+
+        loop {
+            buf.clear();
+            let read = match self.input.read_until(b'\n', &mut buf) {
+                Err(err) => return Err(DBError::with_source(DBErrorKind::ReadFailed("csv input".to_string()), err)),
+                Ok(read) => read,
+            };
+            if read == 0 {
+                if quoted {
+                    return Ok(Some(Err(RowError {
+                        line: start,
+                        message: "unterminated quoted field".to_string(),
+                    })));
+                }
+                if fields.is_empty() && field.is_empty() && !was_quoted {
+                    return Ok(None);
+                }
+                break;
+            }
+            self.line += 1;
+
+            // blank lines between records are skipped
+            if !quoted && fields.is_empty() && field.is_empty() && !was_quoted && (buf == b"\n" || buf == b"\r\n") {
+                start = self.line + 1;
+                continue;
+            }
+
+            let mut bytes = buf.iter().copied().peekable();
+            let mut ended = false;
+            while let Some(byte) = bytes.next() {
+                if quoted {
+                    match byte {
+                        b'"' if bytes.peek() == Some(&b'"') => {
+                            field.push(b'"');
+                            bytes.next();
+                        }
+                        b'"' => quoted = false,
+                        _ => field.push(byte),
+                    }
+                    continue;
+                }
+                match byte {
+                    b'"' if field.is_empty() && !was_quoted => {
+                        quoted = true;
+                        was_quoted = true;
+                    }
+                    b',' => {
+                        fields.push(std::mem::take(&mut field));
+                        was_quoted = false;
+                    }
+                    b'\r' if bytes.peek() == Some(&b'\n') => {}
+                    b'\n' => ended = true,
+                    _ => field.push(byte),
+                }
+            }
+            if ended {
+                break;
+            }
+        }
+        fields.push(field);
+
+        let mut record = Vec::with_capacity(fields.len());
+        for field in fields {
+            match String::from_utf8(field) {
+                Err(_) => {
+                    return Ok(Some(Err(RowError {
+                        line: start,
+                        message: "field is not valid UTF-8".to_string(),
+                    })))
+                }
+                Ok(field) => record.push(field),
+            }
+        }
+        return Ok(Some(Ok((start, record))));
+    }
+}
+
+#[derive(Debug)]
+struct FieldError(String);
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str(&self.0);
+    }
+}
+
+impl std::error::Error for FieldError {}
+
+impl de::Error for FieldError {
+    fn custom<M: fmt::Display>(msg: M) -> Self {
+        return FieldError(msg.to_string());
+    }
+}
+
+/// Deserializes one row into `T`, matching fields to the header by name.
+pub(super) fn from_row<T: DeserializeOwned>(headers: &[String], fields: &[String]) -> Result<T, String> {
+    if headers.len() != fields.len() {
+        return Err(format!("expected {} fields, found {}", headers.len(), fields.len()));
+    }
+    let entries = headers.iter().map(String::as_str).zip(fields.iter().map(|field| Field(field)));
+    let row = MapDeserializer::<_, FieldError>::new(entries);
+    return T::deserialize(row).map_err(|err| err.0);
+}
+
+// a single cell; everything arrives as text, so it is parsed into whatever type is asked for
+struct Field<'a>(&'a str);
+
+impl Field<'_> {
+    fn parse<N: std::str::FromStr>(&self) -> Result<N, FieldError> {
+        match self.0.trim().parse() {
+            Err(_) => return Err(FieldError(format!("invalid number {:?}", self.0))),
+            Ok(number) => return Ok(number),
+        }
+    }
+}
+
+impl<'de> IntoDeserializer<'de, FieldError> for Field<'_> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        return self;
+    }
+}
+
+macro_rules! parse_number {
+    ($($method:ident => $visit:ident),*) => {
+        $(fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FieldError> {
+            return visitor.$visit(self.parse()?);
+        })*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Field<'_> {
+    type Error = FieldError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FieldError> {
+        return visitor.visit_str(self.0);
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FieldError> {
+        match self.0.trim() {
+            "true" | "TRUE" | "1" => return visitor.visit_bool(true),
+            "false" | "FALSE" | "0" => return visitor.visit_bool(false),
+            other => return Err(FieldError(format!("invalid boolean {:?}", other))),
+        }
+    }
+
+    parse_number!(
+        deserialize_i8 => visit_i8, deserialize_i16 => visit_i16, deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64, deserialize_u8 => visit_u8, deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32, deserialize_u64 => visit_u64, deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64
+    );
+
+    // an empty cell is a missing value
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FieldError> {
+        if self.0.is_empty() {
+            return visitor.visit_none();
+        }
+        return visitor.visit_some(self);
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, FieldError> {
+        return visitor.visit_newtype_struct(self);
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, FieldError> {
+        return visitor.visit_enum(self.0.into_deserializer());
+    }
+
+    forward_to_deserialize_any! {
+        char str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+        i128 u128
+    }
+}
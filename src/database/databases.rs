@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use super::{DBError, DBErrorKind, DBManager};
+
+static GLOBAL: OnceLock<DBManager> = OnceLock::new();
+
+pub(super) fn set_global(db: DBManager) -> Result<(), DBError> {
+    if GLOBAL.set(db).is_err() {
+        return Err(DBError::new(DBErrorKind::Conflict("the global database is already set".to_string())));
+    }
+    return Ok(());
+}
+
+pub(super) fn global() -> Result<&'static DBManager, DBError> {
+    match GLOBAL.get() {
+        None => return Err(DBError::new(DBErrorKind::NotFound("global database, set it with DBManager::set_global".to_string()))),
+        Some(db) => return Ok(db),
+    }
+}
+
+/// Hands out `DBManager`s by name for apps that use several databases, e.g.
+/// `registry.open("cache", "data/cache")`, opening each one only once.
+///
+/// sled refuses to open a directory twice in one process; going through the registry
+/// every caller shares the same handle instead. Clones share the registry.
+#[derive(Debug, Clone, Default)]
+pub struct DatabaseRegistry {
+    inner: Arc<Mutex<BTreeMap<String, DBManager>>>,
+}
+
+impl DatabaseRegistry {
+    pub fn new() -> Self {
+        return DatabaseRegistry::default();
+    }
+
+    /// The database registered as `name`, opening the one at `path` the first time.
+    /// A path another name already opened is shared rather than opened again; the
+    /// same name with a different path is a `DBErrorKind::Conflict`.
+    pub fn open(&self, name: &str, path: impl Into<String>) -> Result<DBManager, DBError> {
+        let path = path.into();
+        let mut open = self.inner.lock().unwrap();
+        if let Some(db) = open.get(name) {
+            if db.database_name != path {
+                let message = format!("{} is already open at {}, not {}", name, db.database_name, path);
+                return Err(DBError::new(DBErrorKind::Conflict(message)));
+            }
+            return Ok(db.clone());
+        }
+        let db = match open.values().find(|db| db.database_name == path) {
+            None => DBManager::new(path)?,
+            Some(db) => db.clone(),
+        };
+        open.insert(name.to_string(), db.clone());
+        return Ok(db);
+    }
+
+    /// Like `open`, for databases opened some other way, e.g. with `DBManager::builder`
+    /// or `DBManager::in_memory`. `open` only runs if nothing is registered as `name` yet.
+    pub fn open_with<F>(&self, name: &str, open: F) -> Result<DBManager, DBError>
+    where
+        F: FnOnce() -> Result<DBManager, DBError>,
+    {
+        let mut databases = self.inner.lock().unwrap();
+        if let Some(db) = databases.get(name) {
+            return Ok(db.clone());
+        }
+        let db = open()?;
+        databases.insert(name.to_string(), db.clone());
+        return Ok(db);
+    }
+
+    /// The database registered as `name`, which has to have been opened already.
+    pub fn get(&self, name: &str) -> Result<DBManager, DBError> {
+        match self.inner.lock().unwrap().get(name) {
+            None => return Err(DBError::new(DBErrorKind::NotFound(format!("database {}", name)))),
+            Some(db) => return Ok(db.clone()),
+        }
+    }
+
+    /// Names of the registered databases, sorted.
+    pub fn names(&self) -> Vec<String> {
+        return self.inner.lock().unwrap().keys().cloned().collect();
+    }
+
+    /// Flushes every registered database, stopping at the first that fails.
+    pub fn flush_all(&self) -> Result<(), DBError> {
+        let databases: Vec<DBManager> = self.inner.lock().unwrap().values().cloned().collect();
+        for db in databases {
+            db.conn.flush()?;
+        }
+        return Ok(());
+    }
+
+    /// Flushes the database registered as `name` and forgets it, returning whether
+    /// there was one. Handles already handed out keep working until dropped.
+    pub fn close(&self, name: &str) -> Result<bool, DBError> {
+        let db = self.inner.lock().unwrap().remove(name);
+        match db {
+            None => return Ok(false),
+            Some(db) => {
+                db.conn.flush()?;
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Flushes and forgets every registered database, for the end of `main`.
+    pub fn shutdown(&self) -> Result<(), DBError> {
+        self.flush_all()?;
+        self.inner.lock().unwrap().clear();
+        return Ok(());
+    }
+}
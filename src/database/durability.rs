@@ -0,0 +1,83 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use sled::Db;
+
+use super::DBError;
+
+/// When a write made through a handle reaches the disk, see `Collection::with_durability`.
+///
+/// sled's flush always ends in an fsync, so `Flush` and `Fsync` differ in whether the
+/// write waits for it, not in what ends up on disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// Returns as soon as the write is in memory; it is flushed with everything else by
+    /// the background flush, see `DBManagerBuilder::flush_every_ms`.
+    #[default]
+    Buffered,
+    /// Asks for a flush right after the write, without waiting for it. Flushes asked
+    /// for while one is running are folded into a single one after it.
+    Flush,
+    /// Returns only once the write has been flushed and fsynced, reporting a failed flush.
+    Fsync,
+}
+
+// the database to flush next, if a write asked for it since the last flush started,
+// and whether the flushing thread is running
+type Pending = (Option<Db>, bool);
+
+/// Runs the flushes asked for with `Durability::Flush` one after another, on a single
+/// thread that lives only while there is something left to flush.
+#[derive(Clone, Default)]
+pub(super) struct Flusher {
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl fmt::Debug for Flusher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "Flusher(running: {})", self.pending.lock().unwrap().1);
+    }
+}
+
+impl Flusher {
+    fn request(&self, conn: &Db) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.0 = Some(conn.clone());
+        if pending.1 {
+            return;
+        }
+        pending.1 = true;
+        let flusher = self.clone();
+        thread::spawn(move || flusher.run());
+    }
+
+    fn run(&self) {
+        loop {
+            let conn = {
+                let mut pending = self.pending.lock().unwrap();
+                match pending.0.take() {
+                    None => {
+                        pending.1 = false;
+                        return;
+                    }
+                    Some(conn) => conn,
+                }
+            };
+            // a failure here shows up again on the next flush that is waited for
+            let _ = conn.flush();
+        }
+    }
+}
+
+/// Makes a write that just succeeded as durable as `durability` asks.
+pub(super) fn settle(conn: &Db, durability: Durability, flusher: &Flusher) -> Result<(), DBError> {
+    match durability {
+        Durability::Buffered => {}
+        Durability::Flush => flusher.request(conn),
+        Durability::Fsync => {
+            conn.flush()?;
+        }
+    }
+    return Ok(());
+}
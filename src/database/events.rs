@@ -0,0 +1,90 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+type SaveListener<T> = dyn Fn(&str, &T) + Send + Sync;
+type DeleteListener = dyn Fn(&str) + Send + Sync;
+
+#[derive(Clone)]
+struct Typed {
+    type_id: TypeId,
+    // an `Arc<SaveListener<T>>` for the registered type
+    listener: Arc<dyn Any + Send + Sync>,
+}
+
+#[derive(Clone, Default)]
+struct Listeners {
+    inserts: Vec<Typed>,
+    updates: Vec<Typed>,
+    deletes: Vec<Arc<DeleteListener>>,
+}
+
+/// Callbacks run after writes succeed, see `Collection::on_insert`.
+#[derive(Clone, Default)]
+pub(super) struct EventRegistry {
+    inner: Arc<RwLock<HashMap<String, Listeners>>>,
+}
+
+impl fmt::Debug for EventRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str("EventRegistry");
+    }
+}
+
+fn typed<T: 'static>(listener: Arc<SaveListener<T>>) -> Typed {
+    return Typed {
+        type_id: TypeId::of::<T>(),
+        listener: Arc::new(listener),
+    };
+}
+
+fn notify<T: 'static>(listeners: &[Typed], id: &str, data: &T) {
+    for typed in listeners.iter().filter(|typed| typed.type_id == TypeId::of::<T>()) {
+        (typed.listener.downcast_ref::<Arc<SaveListener<T>>>().unwrap())(id, data);
+    }
+}
+
+impl EventRegistry {
+    pub(super) fn on_insert<T: 'static>(&self, collection: &str, listener: Arc<SaveListener<T>>) {
+        self.inner.write().unwrap().entry(collection.to_string()).or_default().inserts.push(typed(listener));
+    }
+
+    pub(super) fn on_update<T: 'static>(&self, collection: &str, listener: Arc<SaveListener<T>>) {
+        self.inner.write().unwrap().entry(collection.to_string()).or_default().updates.push(typed(listener));
+    }
+
+    pub(super) fn on_delete(&self, collection: &str, listener: Arc<DeleteListener>) {
+        self.inner.write().unwrap().entry(collection.to_string()).or_default().deletes.push(listener);
+    }
+
+    pub(super) fn forget(&self, prefix: &str) {
+        self.inner.write().unwrap().retain(|collection, _| !collection.starts_with(prefix));
+    }
+
+    // listeners are copied out first, so they are free to write to the database themselves
+    fn get(&self, collection: &str) -> Option<Listeners> {
+        return self.inner.read().unwrap().get(collection).cloned();
+    }
+
+    pub(super) fn inserted<T: 'static>(&self, collection: &str, id: &[u8], data: &T) {
+        if let Some(listeners) = self.get(collection) {
+            notify(&listeners.inserts, &String::from_utf8_lossy(id), data);
+        }
+    }
+
+    pub(super) fn updated<T: 'static>(&self, collection: &str, id: &[u8], data: &T) {
+        if let Some(listeners) = self.get(collection) {
+            notify(&listeners.updates, &String::from_utf8_lossy(id), data);
+        }
+    }
+
+    pub(super) fn deleted(&self, collection: &str, id: &[u8]) {
+        if let Some(listeners) = self.get(collection) {
+            let id = String::from_utf8_lossy(id);
+            for listener in &listeners.deletes {
+                listener(&id);
+            }
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sled::{Db, Tree};
+
+use super::registry::existing_tree;
+use super::{DBError, DBErrorKind};
+
+// bookkeeping that has to survive a reopen, one key per collection
+pub(super) const META_TREE: &str = "__meta";
+
+/// How records are encoded on disk.
+///
+/// Bincode is compact but tied to the exact struct layout; JSON can be read with
+/// standard tools and matches fields by name, so reordering them is harmless.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Bincode,
+    Json,
+}
+
+/// Turns records into bytes and back.
+///
+/// `Format` is the built-in implementation; plug in another encoding (MessagePack,
+/// CBOR, ...) with `DBManager::collection_with_codec`. Only `Format`s are remembered
+/// per collection, a custom codec has to be passed every time the collection is opened.
+pub trait Codec: Clone {
+    fn encode<T: Serialize>(&self, data: &T) -> Result<Vec<u8>, DBError>;
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, DBError>;
+
+    /// `encode` for the record stored under `id` in `collection`, which is how
+    /// collections call it. Codecs that bind values to where they are stored, like
+    /// `Encrypted`, override this pair.
+    fn encode_for<T: Serialize>(&self, _collection: &str, _id: &[u8], data: &T) -> Result<Vec<u8>, DBError> {
+        return self.encode(data);
+    }
+
+    fn decode_for<T: DeserializeOwned>(&self, _collection: &str, _id: &[u8], bytes: &[u8]) -> Result<T, DBError> {
+        return self.decode(bytes);
+    }
+
+    /// The `Format` this codec is, `None` for any other codec. Collections written with
+    /// one that is not a `Format` are kept out of transactions, which only know formats.
+    fn format(&self) -> Option<Format> {
+        return None;
+    }
+}
+
+impl Format {
+    fn tag(self) -> u8 {
+        match self {
+            Format::Bincode => return 0,
+            Format::Json => return 1,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Format> {
+        match tag {
+            0 => return Some(Format::Bincode),
+            1 => return Some(Format::Json),
+            _ => return None,
+        }
+    }
+}
+
+impl Codec for Format {
+    fn encode<T: Serialize>(&self, data: &T) -> Result<Vec<u8>, DBError> {
+        let kind = || DBErrorKind::SerializeFailed("record".to_string());
+        match self {
+            Format::Bincode => return bincode::serialize(data).map_err(|err| DBError::with_source(kind(), err)),
+            Format::Json => return crate::json::to_vec(data).map_err(|err| DBError::with_source(kind(), err)),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, DBError> {
+        let kind = || DBErrorKind::DeserializeFailed("record".to_string());
+        match self {
+            Format::Bincode => return bincode::deserialize(bytes).map_err(|err| DBError::with_source(kind(), err)),
+            Format::Json => return crate::json::from_slice(bytes).map_err(|err| DBError::with_source(kind(), err)),
+        }
+    }
+
+    fn format(&self) -> Option<Format> {
+        return Some(*self);
+    }
+}
+
+fn meta_key(tree: &[u8]) -> Vec<u8> {
+    let mut key = b"format/".to_vec();
+    key.extend_from_slice(tree);
+    return key;
+}
+
+fn codec_key(tree: &[u8]) -> Vec<u8> {
+    let mut key = b"codec/".to_vec();
+    key.extend_from_slice(tree);
+    return key;
+}
+
+/// Remembers that `tree` has been written with a codec that is not a `Format`.
+pub(super) fn mark_codec(conn: &Db, tree: &Tree) -> Result<(), DBError> {
+    conn.open_tree(META_TREE)?.insert(codec_key(&tree.name()), &[])?;
+    return Ok(());
+}
+
+/// Fails when `tree` has been opened with a codec that is not a `Format`, whose records
+/// a transaction could not encode.
+pub(super) fn check_no_codec(conn: &Db, tree: &Tree) -> Result<(), DBError> {
+    let marked = match existing_tree(conn, META_TREE)? {
+        None => false,
+        Some(meta) => meta.contains_key(codec_key(&tree.name()))?,
+    };
+    if marked {
+        return Err(DBError::new(DBErrorKind::Other(format!(
+            "{} is encoded with a custom codec, which transactions cannot write",
+            String::from_utf8_lossy(&tree.name())
+        ))));
+    }
+    return Ok(());
+}
+
+// the format recorded for `tree` in `meta`, if any
+fn lookup(meta: Option<&Tree>, tree: &Tree) -> Result<Option<Format>, DBError> {
+    match meta.map(|meta| meta.get(meta_key(&tree.name()))).transpose()?.flatten() {
+        None => return Ok(None),
+        Some(tag) => match tag.first().copied().and_then(Format::from_tag) {
+            None => return Err(DBError::new(DBErrorKind::ReadFailed("unknown storage format".to_string()))),
+            Some(format) => return Ok(Some(format)),
+        },
+    }
+}
+
+/// The format `tree` was written with, read without recording anything.
+#[cfg(feature = "admin")]
+pub(super) fn recorded(conn: &Db, tree: &Tree) -> Result<Format, DBError> {
+    return Ok(lookup(existing_tree(conn, META_TREE)?.as_ref(), tree)?.unwrap_or(Format::Bincode));
+}
+
+/// Works out the format of `tree`, recording it the first time a non default one is picked.
+///
+/// A tree keeps the format it was first written with: asking for a different one later is
+/// an error, and trees that predate the record hold bincode. A read-only database
+/// records nothing.
+pub(super) fn resolve(
+    conn: &Db,
+    tree: &Tree,
+    requested: Option<Format>,
+    default: Format,
+    read_only: bool,
+) -> Result<Format, DBError> {
+    let meta = match read_only {
+        true => existing_tree(conn, META_TREE)?,
+        false => Some(conn.open_tree(META_TREE)?),
+    };
+    let key = meta_key(&tree.name());
+    let recorded = lookup(meta.as_ref(), tree)?;
+
+    let name = String::from_utf8_lossy(&tree.name()).into_owned();
+    match (recorded, requested) {
+        (Some(recorded), Some(requested)) if recorded != requested => {
+            return Err(DBError::new(DBErrorKind::Other(format!(
+                "{} is stored as {:?}, not {:?}",
+                name, recorded, requested
+            ))))
+        }
+        (Some(recorded), _) => return Ok(recorded),
+        (None, _) => {}
+    }
+
+    let wanted = requested.unwrap_or(default);
+    if wanted == Format::Bincode && requested.is_none() {
+        return Ok(Format::Bincode);
+    }
+    if wanted != Format::Bincode && !tree.is_empty() {
+        if requested.is_some() {
+            return Err(DBError::new(DBErrorKind::Other(format!(
+                "{} already holds bincode records",
+                name
+            ))));
+        }
+        return Ok(Format::Bincode);
+    }
+    if let (false, Some(meta)) = (read_only, meta) {
+        meta.insert(key, vec![wanted.tag()])?;
+    }
+    return Ok(wanted);
+}
+
+/// A record as the tools show it: json records come back as they were written,
+/// anything else as its raw bytes in hex.
+#[cfg(any(feature = "cli", feature = "admin"))]
+pub(super) fn render(id: &[u8], bytes: &[u8], format: Format, schema: Option<&super::schema::Schema>) -> crate::json::Value {
+    use crate::json::{self, Value};
+    use std::fmt::Write as _;
+
+    let mut record = std::collections::BTreeMap::new();
+    record.insert("id".to_string(), Value::String(String::from_utf8_lossy(id).into_owned()));
+    let decoded = match format {
+        Format::Json => json::from_slice::<Value>(super::schema::payload(schema, bytes)).ok(),
+        Format::Bincode => None,
+    };
+    match decoded {
+        Some(value) => record.insert("value".to_string(), value),
+        None => {
+            let mut hex = String::with_capacity(bytes.len() * 2);
+            for byte in bytes {
+                let _ = write!(hex, "{:02x}", byte);
+            }
+            record.insert("bytes".to_string(), Value::String(hex))
+        }
+    };
+    return Value::Object(record);
+}
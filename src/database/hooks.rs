@@ -0,0 +1,113 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use super::DBError;
+
+/// Code a record type runs around its own writes once registered with
+/// `Collection::register_hooks`. Every method does nothing unless overridden.
+///
+/// Hooks run for the typed writes of a collection (`insert`, `update`, `upsert`,
+/// `modify`, `cas` and friends) and for `delete` and `hard_delete`; transactions,
+/// merges and records removed by a cascade or expiry skip them.
+pub trait Hooks {
+    /// Runs before the record is encoded, e.g. to fill in derived fields or normalise
+    /// input. An error stops the write.
+    fn before_save(&mut self) -> Result<(), DBError> {
+        return Ok(());
+    }
+
+    /// Runs once the record has been written.
+    fn after_save(&self) {}
+
+    /// Runs with the stored record before it is deleted. An error stops the delete.
+    fn before_delete(&self) -> Result<(), DBError> {
+        return Ok(());
+    }
+}
+
+// gets the id and the stored bytes of the record about to go
+type DeleteHook = dyn Fn(&[u8], &[u8]) -> Result<(), DBError> + Send + Sync;
+
+struct Typed<T> {
+    before_save: fn(&mut T) -> Result<(), DBError>,
+    after_save: fn(&T),
+}
+
+#[derive(Clone)]
+struct Entry {
+    type_id: TypeId,
+    // a `Typed<T>` for the registered type
+    typed: Arc<dyn Any + Send + Sync>,
+    // decodes the stored record itself, deletes do not know the type
+    before_delete: Arc<DeleteHook>,
+}
+
+/// The record types whose `Hooks` run on each collection.
+#[derive(Clone, Default)]
+pub(super) struct HookRegistry {
+    inner: Arc<RwLock<HashMap<String, Entry>>>,
+}
+
+impl fmt::Debug for HookRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str("HookRegistry");
+    }
+}
+
+impl HookRegistry {
+    pub(super) fn register<T, F>(&self, collection: &str, before_delete: F)
+    where
+        T: Hooks + 'static,
+        F: Fn(&[u8], &[u8]) -> Result<(), DBError> + Send + Sync + 'static,
+    {
+        let typed: Typed<T> = Typed {
+            before_save: T::before_save,
+            after_save: T::after_save,
+        };
+        let entry = Entry {
+            type_id: TypeId::of::<T>(),
+            typed: Arc::new(typed),
+            before_delete: Arc::new(before_delete),
+        };
+        self.inner.write().unwrap().insert(collection.to_string(), entry);
+    }
+
+    pub(super) fn forget(&self, prefix: &str) {
+        self.inner.write().unwrap().retain(|collection, _| !collection.starts_with(prefix));
+    }
+
+    fn get(&self, collection: &str) -> Option<Entry> {
+        return self.inner.read().unwrap().get(collection).cloned();
+    }
+
+    pub(super) fn before_save<T: 'static>(&self, collection: &str, data: &mut T) -> Result<(), DBError> {
+        match self.get(collection) {
+            Some(entry) if entry.type_id == TypeId::of::<T>() => {
+                let typed = entry.typed.downcast_ref::<Typed<T>>().unwrap();
+                return (typed.before_save)(data);
+            }
+            _ => return Ok(()),
+        }
+    }
+
+    pub(super) fn after_save<T: 'static>(&self, collection: &str, data: &T) {
+        if let Some(entry) = self.get(collection).filter(|entry| entry.type_id == TypeId::of::<T>()) {
+            let typed = entry.typed.downcast_ref::<Typed<T>>().unwrap();
+            (typed.after_save)(data);
+        }
+    }
+
+    /// Whether deletes from `collection` have to read the record for its hook first.
+    pub(super) fn on_delete(&self, collection: &str) -> bool {
+        return self.inner.read().unwrap().contains_key(collection);
+    }
+
+    pub(super) fn before_delete(&self, collection: &str, id: &[u8], stored: &[u8]) -> Result<(), DBError> {
+        match self.get(collection) {
+            None => return Ok(()),
+            Some(entry) => return (entry.before_delete)(id, stored),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::sync::Mutex;
+
+use uuid::Uuid;
+
+use super::ttl;
+use super::{DBError, DBErrorKind};
+
+/// How `DBManager::gen_id` and derived `Id` impls make fresh record ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdStrategy {
+    /// Random v4 UUIDs, the default.
+    #[default]
+    Uuid,
+    /// ULIDs, which sort by creation time so iterating or range scanning a collection
+    /// returns records in the order they were inserted.
+    Ulid,
+    /// Snowflake ids from the given node, as 19 zero-padded decimal digits so they
+    /// still sort by creation time.
+    Snowflake(Snowflake),
+}
+
+impl IdStrategy {
+    pub fn generate(&self) -> String {
+        match self {
+            IdStrategy::Uuid => return Uuid::new_v4().to_string(),
+            IdStrategy::Ulid => return gen_ulid(),
+            IdStrategy::Snowflake(snowflake) => return format!("{:019}", snowflake.next_id()),
+        }
+    }
+}
+
+const NODE_BITS: u32 = 10;
+const SEQUENCE_BITS: u32 = 12;
+
+// the last (millisecond, sequence) handed out by any node in this process
+static LAST_SNOWFLAKE: Mutex<(u64, u64)> = Mutex::new((0, 0));
+
+/// Compact, time-ordered 64-bit ids for several app instances that each run their
+/// own database: 41 bits of milliseconds since `Snowflake::EPOCH_MILLIS`, 10 bits of
+/// node and a 12 bit sequence. Ids are unique as long as every instance has its own node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snowflake {
+    node: u16,
+}
+
+impl Snowflake {
+    /// 2024-01-01T00:00:00Z, where the timestamps start; they run out 69 years later.
+    pub const EPOCH_MILLIS: u64 = 1_704_067_200_000;
+    pub const MAX_NODE: u16 = (1 << NODE_BITS) - 1;
+
+    pub fn new(node: u16) -> Result<Self, DBError> {
+        if node > Snowflake::MAX_NODE {
+            let message = format!("snowflake node {} is above {}", node, Snowflake::MAX_NODE);
+            return Err(DBError::new(DBErrorKind::Other(message)));
+        }
+        return Ok(Snowflake { node });
+    }
+
+    pub fn node(&self) -> u16 {
+        return self.node;
+    }
+
+    /// The next id, strictly greater than any this process handed out before. Past
+    /// 4096 ids in a millisecond it waits for the next one.
+    pub fn next_id(&self) -> u64 {
+        let mut last = LAST_SNOWFLAKE.lock().unwrap();
+        let (last_millis, last_sequence) = *last;
+        // a clock that went back keeps counting on from where it was
+        let mut millis = ttl::now_millis().saturating_sub(Snowflake::EPOCH_MILLIS).max(last_millis);
+        let mut sequence = 0;
+        if millis == last_millis {
+            sequence = last_sequence + 1;
+            while sequence >> SEQUENCE_BITS != 0 {
+                std::thread::yield_now();
+                millis = ttl::now_millis().saturating_sub(Snowflake::EPOCH_MILLIS);
+                if millis > last_millis {
+                    sequence = 0;
+                }
+            }
+        }
+        *last = (millis, sequence);
+        return millis << (NODE_BITS + SEQUENCE_BITS) | (self.node as u64) << SEQUENCE_BITS | sequence;
+    }
+
+    /// The node an id was made on.
+    pub fn node_of(id: u64) -> u16 {
+        return ((id >> SEQUENCE_BITS) & Snowflake::MAX_NODE as u64) as u16;
+    }
+}
+
+// Crockford's base 32, which leaves out I, L, O and U
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+// the last ulid handed out, so ids made within the same millisecond still ascend
+static LAST_ULID: Mutex<u128> = Mutex::new(0);
+
+/// A ULID: 48 bits of milliseconds since the epoch then 80 random bits, as 26 characters.
+/// Ids made in the same millisecond by this process are strictly increasing.
+pub fn gen_ulid() -> String {
+    // the random bytes of a v4 uuid, skipping the ones holding its version and variant
+    let bytes = Uuid::new_v4().into_bytes();
+    let mut random = [0; 16];
+    random[6..12].copy_from_slice(&bytes[..6]);
+    random[12..].copy_from_slice(&bytes[9..13]);
+    let random = u128::from_be_bytes(random);
+    let now = (ttl::now_millis() as u128 & ((1 << 48) - 1)) << 80;
+
+    let mut last = LAST_ULID.lock().unwrap();
+    let value = match *last >> 80 >= now >> 80 {
+        // same millisecond (or the clock went back): carry on from the last one
+        true => *last + 1,
+        false => now | random,
+    };
+    *last = value;
+    return encode(value);
+}
+
+fn encode(value: u128) -> String {
+    let mut out = String::with_capacity(26);
+    for i in (0..26).rev() {
+        out.push(ALPHABET[((value >> (i * 5)) & 0x1f) as usize] as char);
+    }
+    return out;
+}
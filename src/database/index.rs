@@ -0,0 +1,242 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use sled::transaction::{abort, TransactionalTree};
+use sled::{IVec, Tree};
+
+use super::collection::SortDirection;
+use super::key::push_escaped;
+use super::transaction::TxResult;
+use super::version;
+use super::{DBError, DBErrorKind};
+
+type Extractor<T> = dyn Fn(&T) -> Vec<Vec<u8>> + Send + Sync;
+
+// forward entries map an indexed value to a record id, the reverse entry lets us
+// drop a record's old values on overwrite or delete without knowing its type.
+// unique indexes key forward entries by the value alone so a clash is a single lookup
+const FORWARD: u8 = b'f';
+const REVERSE: u8 = b'r';
+
+pub(super) fn index_tree_name(collection: &str, index: &str) -> String {
+    return format!("__index/{}/{}", collection, index);
+}
+
+// values are escaped, which keeps entries in the same order as the raw values
+// while staying prefix free
+fn forward_prefix(value: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(value.len() + 3);
+    key.push(FORWARD);
+    push_escaped(&mut key, value);
+    return key;
+}
+
+fn forward_key(value: &[u8], id: &[u8], unique: bool) -> Vec<u8> {
+    let mut key = forward_prefix(value);
+    if !unique {
+        key.extend_from_slice(id);
+    }
+    return key;
+}
+
+fn unique_violation(index: &str, value: &[u8]) -> DBError {
+    return DBError::new(DBErrorKind::UniqueViolation(format!(
+        "{} already holds {}",
+        index,
+        String::from_utf8_lossy(value)
+    )));
+}
+
+fn reverse_key(id: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(id.len() + 1);
+    key.push(REVERSE);
+    key.extend_from_slice(id);
+    return key;
+}
+
+fn decode_values(bytes: &[u8]) -> Vec<Vec<u8>> {
+    return bincode::deserialize(bytes).unwrap_or_default();
+}
+
+fn encode_values(values: &[Vec<u8>]) -> Vec<u8> {
+    // a list of byte strings always serializes
+    return bincode::serialize(values).unwrap();
+}
+
+#[derive(Clone)]
+pub(super) struct IndexEntry {
+    pub(super) name: String,
+    pub(super) tree: Tree,
+    unique: bool,
+    type_id: TypeId,
+    extractor: Arc<dyn Any + Send + Sync>,
+}
+
+impl IndexEntry {
+    pub(super) fn new<T, F>(name: &str, tree: Tree, unique: bool, extractor: F) -> Self
+    where
+        T: 'static,
+        F: Fn(&T) -> Vec<Vec<u8>> + Send + Sync + 'static,
+    {
+        let extractor: Box<Extractor<T>> = Box::new(extractor);
+        return IndexEntry {
+            name: name.to_string(),
+            tree,
+            unique,
+            type_id: TypeId::of::<T>(),
+            extractor: Arc::new(extractor),
+        };
+    }
+
+    /// The index values for `data`, empty when this index belongs to another type.
+    pub(super) fn keys<T: 'static>(&self, data: &T) -> Vec<Vec<u8>> {
+        if self.type_id != TypeId::of::<T>() {
+            return Vec::new();
+        }
+        match self.extractor.downcast_ref::<Box<Extractor<T>>>() {
+            None => return Vec::new(),
+            Some(extractor) => return extractor(data),
+        }
+    }
+
+    pub(super) fn ids_for(&self, value: &[u8]) -> Result<Vec<IVec>, DBError> {
+        let prefix = forward_prefix(value);
+        if self.unique {
+            return Ok(self.tree.get(&prefix)?.into_iter().collect());
+        }
+
+        let mut ids = Vec::new();
+        for entry in self.tree.scan_prefix(&prefix) {
+            let (_, id) = entry?;
+            ids.push(id);
+        }
+        return Ok(ids);
+    }
+
+    /// Every indexed record id, ordered by the indexed value.
+    pub(super) fn ordered_ids(&self, direction: SortDirection) -> Result<Vec<IVec>, DBError> {
+        let entries = self.tree.scan_prefix([FORWARD]);
+        let mut ids = Vec::new();
+        match direction {
+            SortDirection::Ascending => {
+                for entry in entries {
+                    ids.push(entry?.1);
+                }
+            }
+            SortDirection::Descending => {
+                for entry in entries.rev() {
+                    ids.push(entry?.1);
+                }
+            }
+        }
+        return Ok(ids);
+    }
+
+    pub(super) fn rebuild<T, D>(&self, data: &Tree, decode: D) -> Result<(), DBError>
+    where
+        T: 'static,
+        D: Fn(&[u8], &[u8]) -> Result<T, DBError>,
+    {
+        self.tree.clear()?;
+        for entry in data.iter() {
+            let (id, bytes) = entry?;
+            // the default tree can hold other models, those are simply not indexed
+            let record: T = match decode(&id, &bytes) {
+                Err(_) => continue,
+                Ok(record) => record,
+            };
+            let values = self.keys(&record);
+            for value in &values {
+                let key = forward_key(value, &id, self.unique);
+                if self.unique && self.tree.get(&key)?.is_some_and(|other| other != id) {
+                    return Err(unique_violation(&self.name, value));
+                }
+                self.tree.insert(key, &id)?;
+            }
+            if !values.is_empty() {
+                self.tree.insert(reverse_key(&id), encode_values(&values))?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Writes (or with `None`, removes) a record and keeps every index view in step.
+///
+/// `keys[i]` holds the new values for `indexes[i]`; `versions` is the collection's
+/// version counters when it tracks them.
+pub(super) fn write_entry(
+    data: &TransactionalTree,
+    indexes: &[(&IndexEntry, &TransactionalTree)],
+    versions: Option<&TransactionalTree>,
+    id: &[u8],
+    value: Option<Vec<u8>>,
+    keys: &[Vec<Vec<u8>>],
+) -> TxResult<Option<IVec>> {
+    let reverse = reverse_key(id);
+    for ((entry, index), values) in indexes.iter().zip(keys) {
+        if let Some(old) = index.remove(reverse.as_slice())? {
+            for old_value in decode_values(&old) {
+                index.remove(forward_key(&old_value, id, entry.unique))?;
+            }
+        }
+        if value.is_some() && !values.is_empty() {
+            for new_value in values {
+                let key = forward_key(new_value, id, entry.unique);
+                if entry.unique && index.get(key.as_slice())?.is_some_and(|other| other != id) {
+                    return abort(unique_violation(&entry.name, new_value));
+                }
+                index.insert(key, id)?;
+            }
+            index.insert(reverse.as_slice(), encode_values(values))?;
+        }
+    }
+
+    if let Some(versions) = versions {
+        version::bump(versions, id, value.is_none())?;
+    }
+
+    match value {
+        None => return Ok(data.remove(id)?),
+        Some(bytes) => return Ok(data.insert(id, bytes)?),
+    }
+}
+
+#[derive(Clone, Default)]
+pub(super) struct IndexRegistry {
+    inner: Arc<RwLock<HashMap<String, Vec<IndexEntry>>>>,
+}
+
+impl fmt::Debug for IndexRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str("IndexRegistry");
+    }
+}
+
+impl IndexRegistry {
+    pub(super) fn register(&self, collection: &str, entry: IndexEntry) {
+        let mut inner = self.inner.write().unwrap();
+        let entries = inner.entry(collection.to_string()).or_default();
+        entries.retain(|existing| existing.name != entry.name);
+        entries.push(entry);
+    }
+
+    /// Drops the indexes of every collection whose name starts with `prefix`.
+    pub(super) fn forget(&self, prefix: &str) {
+        self.inner.write().unwrap().retain(|collection, _| !collection.starts_with(prefix));
+    }
+
+    pub(super) fn for_collection(&self, collection: &str) -> Vec<IndexEntry> {
+        let inner = self.inner.read().unwrap();
+        return inner.get(collection).cloned().unwrap_or_default();
+    }
+
+    pub(super) fn find(&self, collection: &str, name: &str) -> Option<IndexEntry> {
+        return self
+            .for_collection(collection)
+            .into_iter()
+            .find(|entry| entry.name == name);
+    }
+}
@@ -0,0 +1,108 @@
+use uuid::Uuid;
+
+/// Something a record can be stored under, e.g. `collection.get(42u64)`.
+///
+/// Strings and byte strings are stored as they are. Integers are big-endian, with the
+/// sign bit flipped for signed ones, so they sort numerically; `u64` keys are not the
+/// same as the decimal string of the number. A `Uuid` is its 16 raw bytes, not the
+/// 36-character string `gen_id` makes. Tuples join their parts so that keys sharing
+/// the first part stay together and sort part by part.
+pub trait Key {
+    fn to_key(&self) -> impl AsRef<[u8]> + '_;
+}
+
+impl<K: Key + ?Sized> Key for &K {
+    fn to_key(&self) -> impl AsRef<[u8]> + '_ {
+        return (**self).to_key();
+    }
+}
+
+impl Key for str {
+    fn to_key(&self) -> impl AsRef<[u8]> + '_ {
+        return self.as_bytes();
+    }
+}
+
+impl Key for String {
+    fn to_key(&self) -> impl AsRef<[u8]> + '_ {
+        return self.as_bytes();
+    }
+}
+
+impl Key for [u8] {
+    fn to_key(&self) -> impl AsRef<[u8]> + '_ {
+        return self;
+    }
+}
+
+impl Key for Vec<u8> {
+    fn to_key(&self) -> impl AsRef<[u8]> + '_ {
+        return self.as_slice();
+    }
+}
+
+impl<const N: usize> Key for [u8; N] {
+    fn to_key(&self) -> impl AsRef<[u8]> + '_ {
+        return self.as_slice();
+    }
+}
+
+impl Key for Uuid {
+    fn to_key(&self) -> impl AsRef<[u8]> + '_ {
+        return self.as_bytes();
+    }
+}
+
+macro_rules! unsigned_key {
+    ($($ty:ty),*) => {
+        $(impl Key for $ty {
+            fn to_key(&self) -> impl AsRef<[u8]> + '_ {
+                return self.to_be_bytes();
+            }
+        })*
+    };
+}
+
+macro_rules! signed_key {
+    ($($ty:ty => $unsigned:ty),*) => {
+        $(impl Key for $ty {
+            fn to_key(&self) -> impl AsRef<[u8]> + '_ {
+                // flipping the sign bit puts negative numbers before positive ones
+                return ((*self as $unsigned) ^ (1 << (<$unsigned>::BITS - 1))).to_be_bytes();
+            }
+        })*
+    };
+}
+
+unsigned_key!(u8, u16, u32, u64, u128);
+signed_key!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+/// Appends `bytes` escaped (0x00 -> 0x00 0xff) and terminated by 0x00 0x01, which keeps
+/// the order of the raw bytes while making the result prefix free.
+pub(super) fn push_escaped(out: &mut Vec<u8>, bytes: &[u8]) {
+    for byte in bytes {
+        out.push(*byte);
+        if *byte == 0 {
+            out.push(0xff);
+        }
+    }
+    out.extend_from_slice(&[0x00, 0x01]);
+}
+
+macro_rules! tuple_key {
+    ($($part:ident),*) => {
+        impl<$($part: Key),*> Key for ($($part,)*) {
+            #[allow(non_snake_case)]
+            fn to_key(&self) -> impl AsRef<[u8]> + '_ {
+                let ($($part,)*) = self;
+                let mut key = Vec::new();
+                $(push_escaped(&mut key, $part.to_key().as_ref());)*
+                return key;
+            }
+        }
+    };
+}
+
+tuple_key!(A, B);
+tuple_key!(A, B, C);
+tuple_key!(A, B, C, D);
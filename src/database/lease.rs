@@ -0,0 +1,99 @@
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sled::{IVec, Tree};
+use uuid::Uuid;
+
+use super::ttl::now_millis;
+use super::DBError;
+
+// a lease is stored as its expiry, in milliseconds since the epoch, then the holder's token
+fn encode(expires_at: u64, token: &[u8; 16]) -> Vec<u8> {
+    return [&expires_at.to_be_bytes()[..], token].concat();
+}
+
+fn expiry_of(value: &[u8]) -> u64 {
+    // anything unreadable counts as long expired, so it can be taken over
+    return value.get(..8).and_then(|bytes| bytes.try_into().ok()).map_or(0, u64::from_be_bytes);
+}
+
+fn expiry_after(ttl: Duration) -> u64 {
+    return now_millis().saturating_add(ttl.as_millis() as u64);
+}
+
+/// Takes the lease on `key` for `ttl` unless someone else holds one that has not expired.
+pub(super) fn acquire(tree: &Tree, key: &[u8], ttl: Duration) -> Result<Option<Lease>, DBError> {
+    let token = *Uuid::new_v4().as_bytes();
+    loop {
+        let current = tree.get(key)?;
+        if current.as_ref().is_some_and(|value| expiry_of(value) > now_millis()) {
+            return Ok(None);
+        }
+        let expires_at = expiry_after(ttl);
+        match tree.compare_and_swap(key, current, Some(encode(expires_at, &token)))? {
+            // someone else took or released it in between, look again
+            Err(_) => continue,
+            Ok(()) => {
+                return Ok(Some(Lease {
+                    tree: tree.clone(),
+                    key: key.to_vec(),
+                    token,
+                    expires_at,
+                }));
+            }
+        }
+    }
+}
+
+/// A claim on one record that lapses on its own once its time is up, see
+/// `Collection::acquire_lease`. It is kept in the database, so it holds across
+/// processes; dropping it leaves it in place until it expires or is released.
+pub struct Lease {
+    tree: Tree,
+    key: Vec<u8>,
+    token: [u8; 16],
+    expires_at: u64,
+}
+
+impl fmt::Debug for Lease {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.debug_struct("Lease").field("key", &self.key()).field("expires_at", &self.expires_at).finish();
+    }
+}
+
+impl Lease {
+    pub fn key(&self) -> String {
+        return String::from_utf8_lossy(&self.key).into_owned();
+    }
+
+    pub fn expires_at(&self) -> SystemTime {
+        return UNIX_EPOCH + Duration::from_millis(self.expires_at);
+    }
+
+    pub fn is_expired(&self) -> bool {
+        return self.expires_at <= now_millis();
+    }
+
+    fn stored(&self) -> IVec {
+        return IVec::from(encode(self.expires_at, &self.token));
+    }
+
+    /// Extends the lease to `ttl` from now. `false` means it was lost, taken over by
+    /// someone else after it expired, and nothing was changed.
+    pub fn renew(&mut self, ttl: Duration) -> Result<bool, DBError> {
+        let expires_at = expiry_after(ttl);
+        match self.tree.compare_and_swap(&self.key, Some(self.stored()), Some(encode(expires_at, &self.token)))? {
+            Err(_) => return Ok(false),
+            Ok(()) => {
+                self.expires_at = expires_at;
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Gives the lease up so others can take it right away. `false` means it had
+    /// already been taken over.
+    pub fn release(self) -> Result<bool, DBError> {
+        return Ok(self.tree.compare_and_swap(&self.key, Some(self.stored()), None::<IVec>)?.is_ok());
+    }
+}
@@ -0,0 +1,178 @@
+use serde_derive::{Deserialize, Serialize};
+use sled::transaction::TransactionalTree;
+use sled::{Db, Tree};
+
+use super::format::{Codec, Format, META_TREE};
+use super::registry::Registries;
+use super::transaction::{abort, TxResult};
+use super::{DBError, DBErrorKind};
+
+/// How much one collection may hold, see `Collection::set_quota`. Bytes count keys
+/// and encoded values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quota {
+    pub max_records: Option<usize>,
+    pub max_bytes: Option<u64>,
+}
+
+fn max_record_size_key(collection: &str) -> Vec<u8> {
+    return format!("max_record_size/{}", collection).into_bytes();
+}
+
+/// Stores the largest encoded record `collection` accepts, `None` to fall back to the
+/// database-wide limit.
+pub(super) fn set_max_record_size(conn: &Db, collection: &str, bytes: Option<usize>) -> Result<(), DBError> {
+    let meta = conn.open_tree(META_TREE)?;
+    match bytes {
+        None => meta.remove(max_record_size_key(collection))?,
+        Some(bytes) => meta.insert(max_record_size_key(collection), &(bytes as u64).to_be_bytes())?,
+    };
+    return Ok(());
+}
+
+/// The limit writes to `collection` are held to: its own if it has one, otherwise the
+/// database's.
+pub(super) fn max_record_size(conn: &Db, shared: &Registries, collection: &str) -> Result<Option<usize>, DBError> {
+    let stored = match shared.existing(conn, META_TREE)? {
+        None => None,
+        Some(meta) => meta.get(max_record_size_key(collection))?,
+    };
+    match stored {
+        None => return Ok(shared.max_record_size),
+        Some(bytes) => {
+            let bytes: [u8; 8] = match bytes.as_ref().try_into() {
+                Err(_) => return Err(DBError::new(DBErrorKind::ReadFailed(format!("record size limit of {}", collection)))),
+                Ok(bytes) => bytes,
+            };
+            return Ok(Some(u64::from_be_bytes(bytes) as usize));
+        }
+    }
+}
+
+/// Fails with `DBErrorKind::TooLarge` when an encoded record is over `limit`.
+pub(super) fn check_size(collection: &str, value: &[u8], limit: Option<usize>) -> Result<(), DBError> {
+    match limit {
+        Some(limit) if value.len() > limit => {
+            return Err(DBError::new(DBErrorKind::TooLarge(format!(
+                "record of {} bytes in {}, the limit is {}",
+                value.len(),
+                collection,
+                limit
+            ))));
+        }
+        _ => return Ok(()),
+    }
+}
+
+fn quota_key(collection: &str) -> Vec<u8> {
+    return format!("quota/{}", collection).into_bytes();
+}
+
+// records and bytes a collection with a quota holds, kept up to date by every write
+fn usage_key(collection: &str) -> Vec<u8> {
+    return format!("usage/{}", collection).into_bytes();
+}
+
+fn encode_usage(records: u64, bytes: u64) -> Vec<u8> {
+    return [records.to_be_bytes(), bytes.to_be_bytes()].concat();
+}
+
+fn decode_usage(value: &[u8]) -> (u64, u64) {
+    let half = |at: usize| value.get(at..at + 8).and_then(|bytes| bytes.try_into().ok()).map_or(0, u64::from_be_bytes);
+    return (half(0), half(8));
+}
+
+/// Stores `quota`, then counts what `tree` holds so far; from then on writes keep the
+/// count, so checking the quota never walks the tree.
+pub(super) fn set_quota(conn: &Db, tree: &Tree, collection: &str, quota: Option<Quota>) -> Result<(), DBError> {
+    let meta = conn.open_tree(META_TREE)?;
+    let quota = match quota {
+        None => {
+            meta.remove(quota_key(collection))?;
+            meta.remove(usage_key(collection))?;
+            return Ok(());
+        }
+        Some(quota) => quota,
+    };
+    // the quota goes first so every write from here on is tracked; the count is only
+    // stored if none of them came in while the tree was walked, otherwise it is redone
+    meta.insert(quota_key(collection), Format::Bincode.encode(&quota)?)?;
+    loop {
+        let tracked = meta.get(usage_key(collection))?;
+        let (records, bytes) = count(tree)?;
+        if meta.compare_and_swap(usage_key(collection), tracked, Some(encode_usage(records, bytes)))?.is_ok() {
+            return Ok(());
+        }
+    }
+}
+
+pub(super) fn quota(conn: &Db, shared: &Registries, collection: &str) -> Result<Option<Quota>, DBError> {
+    let meta = match shared.existing(conn, META_TREE)? {
+        None => return Ok(None),
+        Some(meta) => meta,
+    };
+    match meta.get(quota_key(collection))? {
+        None => return Ok(None),
+        Some(bytes) => return Ok(Some(Format::Bincode.decode(&bytes)?)),
+    }
+}
+
+/// Records and bytes held in `tree`.
+fn count(tree: &Tree) -> Result<(u64, u64), DBError> {
+    let mut records = 0;
+    let mut bytes = 0;
+    for entry in tree.iter() {
+        let (key, value) = entry?;
+        records += 1;
+        bytes += (key.len() + value.len()) as u64;
+    }
+    return Ok((records, bytes));
+}
+
+/// The usage count of a collection with a quota, as seen from inside the transaction
+/// writing to it. Every write to such a collection has to go through `track`.
+pub(super) struct Usage<'a> {
+    collection: &'a str,
+    quota: Quota,
+    meta: &'a TransactionalTree,
+}
+
+impl<'a> Usage<'a> {
+    pub(super) fn new(collection: &'a str, quota: Quota, meta: &'a TransactionalTree) -> Self {
+        return Usage { collection, quota, meta };
+    }
+}
+
+/// Brings the usage count along with a write that replaced `previous` by `value`
+/// under `id`, aborting with `DBErrorKind::QuotaExceeded` when a write that stores
+/// something leaves the collection past its quota. Deletes always go through.
+pub(super) fn track(usage: Option<&Usage>, id: &[u8], previous: Option<&[u8]>, value: Option<&[u8]>) -> TxResult<()> {
+    let usage = match usage {
+        None => return Ok(()),
+        Some(usage) => usage,
+    };
+    let key = usage_key(usage.collection);
+    let (mut records, mut bytes) = match usage.meta.get(&key)? {
+        None => (0, 0),
+        Some(stored) => decode_usage(&stored),
+    };
+    let size = |value: &[u8]| (id.len() + value.len()) as u64;
+    if let Some(previous) = previous {
+        records = records.saturating_sub(1);
+        bytes = bytes.saturating_sub(size(previous));
+    }
+    if let Some(value) = value {
+        records += 1;
+        bytes += size(value);
+        if let Some(max) = usage.quota.max_records.filter(|max| records > *max as u64) {
+            let message = format!("{} would hold {} records, the quota is {}", usage.collection, records, max);
+            return abort(DBError::new(DBErrorKind::QuotaExceeded(message)));
+        }
+        if let Some(max) = usage.quota.max_bytes.filter(|max| bytes > *max) {
+            let message = format!("{} would hold {} bytes, the quota is {}", usage.collection, bytes, max);
+            return abort(DBError::new(DBErrorKind::QuotaExceeded(message)));
+        }
+    }
+    usage.meta.insert(key, encode_usage(records, bytes))?;
+    return Ok(());
+}
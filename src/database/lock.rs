@@ -0,0 +1,72 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+
+// the collection and key of every lock held
+type Held = HashSet<(String, Vec<u8>)>;
+
+/// The keys locked with `Collection::lock`, shared by every handle onto a database.
+#[derive(Clone, Default)]
+pub(super) struct KeyLocks {
+    held: Arc<(Mutex<Held>, Condvar)>,
+}
+
+impl fmt::Debug for KeyLocks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "KeyLocks({})", self.held.0.lock().unwrap().len());
+    }
+}
+
+impl KeyLocks {
+    /// Waits until no one else holds the lock on `key` in `collection`, then takes it.
+    pub(super) fn lock(&self, collection: &str, key: &[u8]) -> KeyGuard {
+        let (held, released) = &*self.held;
+        let entry = (collection.to_string(), key.to_vec());
+        let mut held = held.lock().unwrap();
+        while held.contains(&entry) {
+            held = released.wait(held).unwrap();
+        }
+        held.insert(entry.clone());
+        return self.guard(entry);
+    }
+
+    /// Takes the lock on `key` in `collection` if it is free, `None` if it is held.
+    pub(super) fn try_lock(&self, collection: &str, key: &[u8]) -> Option<KeyGuard> {
+        let entry = (collection.to_string(), key.to_vec());
+        if !self.held.0.lock().unwrap().insert(entry.clone()) {
+            return None;
+        }
+        return Some(self.guard(entry));
+    }
+
+    fn guard(&self, entry: (String, Vec<u8>)) -> KeyGuard {
+        return KeyGuard {
+            locks: self.clone(),
+            entry,
+        };
+    }
+}
+
+/// Holds the lock on one key until dropped, see `Collection::lock`.
+pub struct KeyGuard {
+    locks: KeyLocks,
+    entry: (String, Vec<u8>),
+}
+
+impl fmt::Debug for KeyGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("KeyGuard")
+            .field("collection", &self.entry.0)
+            .field("key", &String::from_utf8_lossy(&self.entry.1))
+            .finish();
+    }
+}
+
+impl Drop for KeyGuard {
+    fn drop(&mut self) {
+        let (held, released) = &*self.locks.held;
+        held.lock().unwrap().remove(&self.entry);
+        released.notify_all();
+    }
+}
@@ -0,0 +1,83 @@
+use std::fmt;
+
+use super::format::META_TREE;
+use super::{DBError, DBErrorKind, DBManager};
+
+type Step = Box<dyn Fn(&DBManager) -> Result<(), DBError> + Send + Sync>;
+
+const PREFIX: &[u8] = b"migration/";
+
+/// An ordered list of named schema migrations.
+///
+/// Each one runs at most once per database: the names of applied migrations are
+/// stored in the database itself, and `run` only executes the ones not seen yet.
+#[derive(Default)]
+pub struct Migrations {
+    steps: Vec<(String, Step)>,
+}
+
+impl fmt::Debug for Migrations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.steps.iter().map(|(name, _)| name.as_str()).collect();
+        return f.debug_struct("Migrations").field("steps", &names).finish();
+    }
+}
+
+impl Migrations {
+    pub fn new() -> Self {
+        return Migrations::default();
+    }
+
+    /// Appends a migration; they run in the order they were added. Names must stay stable.
+    pub fn add<F>(mut self, name: &str, step: F) -> Self
+    where
+        F: Fn(&DBManager) -> Result<(), DBError> + Send + Sync + 'static,
+    {
+        self.steps.push((name.to_string(), Box::new(step)));
+        return self;
+    }
+
+    /// Runs every pending migration in order, returning the names of the ones applied.
+    ///
+    /// A failing migration stops the run and is not recorded, so it is retried next time.
+    pub fn run(&self, db: &DBManager) -> Result<Vec<String>, DBError> {
+        let mut seen = std::collections::HashSet::new();
+        for (name, _) in &self.steps {
+            if !seen.insert(name) {
+                return Err(DBError::new(DBErrorKind::Other(format!("duplicate migration {}", name))));
+            }
+        }
+
+        let meta = db.conn.open_tree(META_TREE)?;
+        let mut next = meta.scan_prefix(PREFIX).count() as u64;
+        let mut applied = Vec::new();
+        for (name, step) in &self.steps {
+            let key = [PREFIX, name.as_bytes()].concat();
+            if meta.contains_key(&key)? {
+                continue;
+            }
+            step(db)?;
+            meta.insert(key, &next.to_be_bytes())?;
+            meta.flush()?;
+            next += 1;
+            applied.push(name.clone());
+        }
+        return Ok(applied);
+    }
+}
+
+/// Names of the migrations applied to `db`, oldest first.
+pub(super) fn applied(db: &DBManager) -> Result<Vec<String>, DBError> {
+    let meta = match db.shared.existing(&db.conn, META_TREE)? {
+        None => return Ok(Vec::new()),
+        Some(meta) => meta,
+    };
+    let mut applied = Vec::new();
+    for entry in meta.scan_prefix(PREFIX) {
+        let (key, order) = entry?;
+        let order = order.as_ref().try_into().map(u64::from_be_bytes).unwrap_or(u64::MAX);
+        applied.push((order, String::from_utf8_lossy(&key[PREFIX.len()..]).into_owned()));
+    }
+    applied.sort();
+    return Ok(applied.into_iter().map(|(_, name)| name).collect());
+}
@@ -0,0 +1,84 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::{Codec, Collection, DBError, Format, Id};
+
+/// A view of a collection restricted to keys starting with `prefix`.
+///
+/// Ids going in and out are the unprefixed part, the prefix only exists on disk.
+pub struct Namespace<T, C = Format> {
+    collection: Collection<T, C>,
+    prefix: String,
+}
+
+impl<T, C: Clone> Clone for Namespace<T, C> {
+    fn clone(&self) -> Self {
+        return Namespace {
+            collection: self.collection.clone(),
+            prefix: self.prefix.clone(),
+        };
+    }
+}
+
+impl<T, C: Codec> Namespace<T, C> {
+    pub(super) fn new(collection: Collection<T, C>, prefix: &str) -> Self {
+        return Namespace {
+            collection,
+            prefix: prefix.to_string(),
+        };
+    }
+
+    pub fn prefix(&self) -> &str {
+        return &self.prefix;
+    }
+
+    fn key(&self, id: &str) -> String {
+        return format!("{}{}", self.prefix, id);
+    }
+
+    pub fn insert(&self, data: T) -> Result<String, DBError>
+    where
+        T: Serialize + Id + 'static,
+    {
+        let id = data.gen_id();
+        self.collection.upsert(self.key(&id), data)?;
+        return Ok(id);
+    }
+
+    pub fn get(&self, id: String) -> Result<T, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        return self.collection.get(self.key(&id));
+    }
+
+    pub fn get_all(&self) -> Result<Vec<T>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        self.collection.expire()?;
+        let mut records = Vec::new();
+        for entry in self.collection.tree.scan_prefix(&self.prefix) {
+            let (id, value) = entry?;
+            records.push(self.collection.decode(&id, &value)?);
+        }
+        return Ok(records);
+    }
+
+    pub fn count(&self) -> Result<usize, DBError> {
+        self.collection.expire()?;
+        let mut count = 0;
+        for key in self.collection.tree.scan_prefix(&self.prefix).keys() {
+            key?;
+            count += 1;
+        }
+        return Ok(count);
+    }
+
+    pub fn delete(&self, id: String) -> Result<Option<T>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        return self.collection.delete(self.key(&id));
+    }
+}
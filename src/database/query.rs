@@ -0,0 +1,158 @@
+use std::cmp::Ordering;
+
+use serde::de::DeserializeOwned;
+
+use super::{Codec, Collection, DBError, SortDirection};
+
+type Filter<'q, T> = Box<dyn Fn(&T) -> bool + 'q>;
+type Order<'q, T> = Box<dyn Fn(&T, &T) -> Ordering + 'q>;
+
+/// Filters, ordering and paging over a collection, run in one go by `run`. Usually
+/// written with the `query!` macro, e.g.
+/// `query!(users where age > 18 and name == "Ann", order_by name, limit 20)`.
+pub struct Query<'q, T, C = super::Format> {
+    collection: &'q Collection<T, C>,
+    filters: Vec<Filter<'q, T>>,
+    indexed: Option<(String, Vec<u8>)>,
+    order: Option<Order<'q, T>>,
+    offset: usize,
+    limit: Option<usize>,
+}
+
+impl<'q, T, C: Codec> Query<'q, T, C> {
+    pub(super) fn new(collection: &'q Collection<T, C>) -> Self {
+        return Query {
+            collection,
+            filters: Vec::new(),
+            indexed: None,
+            order: None,
+            offset: 0,
+            limit: None,
+        };
+    }
+
+    /// Keeps only records `predicate` accepts; several filters must all accept.
+    pub fn filter<P>(mut self, predicate: P) -> Self
+    where
+        P: Fn(&T) -> bool + 'q,
+    {
+        self.filters.push(Box::new(predicate));
+        return self;
+    }
+
+    /// Reads the candidates through index `name` with `value`, if the collection has
+    /// such an index, instead of scanning. Only a hint: the filters still decide, and a
+    /// lookup finding nothing falls back to a scan, so an index storing its values
+    /// some other way than `value` costs time but never drops records.
+    pub fn indexed(mut self, name: &str, value: impl AsRef<[u8]>) -> Self {
+        self.indexed = Some((name.to_string(), value.as_ref().to_vec()));
+        return self;
+    }
+
+    /// Orders the results by what `key_fn` extracts.
+    pub fn order_by<K, F>(mut self, key_fn: F, direction: SortDirection) -> Self
+    where
+        K: Ord,
+        F: Fn(&T) -> K + 'q,
+    {
+        self.order = Some(Box::new(move |a, b| match direction {
+            SortDirection::Ascending => key_fn(a).cmp(&key_fn(b)),
+            SortDirection::Descending => key_fn(b).cmp(&key_fn(a)),
+        }));
+        return self;
+    }
+
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        return self;
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        return self;
+    }
+
+    pub fn run(self) -> Result<Vec<T>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        let accepts = |data: &T| self.filters.iter().all(|filter| filter(data));
+        let mut records = Vec::new();
+        if let Some((name, value)) = &self.indexed {
+            if self.collection.has_index(name) {
+                records = self.collection.find_by_index(name, value)?.into_iter().filter(|data| accepts(data)).collect();
+            }
+        }
+        if records.is_empty() {
+            records = self.collection.find_where(accepts)?;
+        }
+
+        if let Some(order) = &self.order {
+            records.sort_by(|a, b| order(a, b));
+        }
+        let records = records.into_iter().skip(self.offset);
+        match self.limit {
+            None => return Ok(records.collect()),
+            Some(limit) => return Ok(records.take(limit).collect()),
+        }
+    }
+}
+
+/// Runs a query over a collection, e.g.
+/// `query!(users where age >= 18 and name != "root", order_by name desc, offset 20, limit 20)`
+/// over a `Collection`, or `query!(db, User where country == country, limit 100)` over
+/// the collection of a `Model`. Both evaluate to a `Result<Vec<_>, DBError>`.
+///
+/// Conditions compare a field with a literal, a variable or a parenthesized
+/// expression and are joined with `and`. An `==` condition reads through the index
+/// named after its field when there is one, see `Query::indexed` for how that index
+/// has to store its values. Every clause after the conditions is optional, but they
+/// come in this order.
+#[macro_export]
+macro_rules! query {
+    (@where $query:expr; $field:ident == $value:tt $($rest:tt)*) => {
+        $crate::query!(@joined $query
+            .filter(|record| record.$field == $value)
+            .indexed(stringify!($field), $crate::database::Key::to_key(&$value).as_ref().to_vec()); $($rest)*)
+    };
+    (@where $query:expr; $field:ident $op:tt $value:tt $($rest:tt)*) => {
+        $crate::query!(@joined $query.filter(|record| record.$field $op $value); $($rest)*)
+    };
+    (@joined $query:expr; and $($rest:tt)*) => {
+        $crate::query!(@where $query; $($rest)*)
+    };
+    (@joined $query:expr; $($rest:tt)*) => {
+        $crate::query!(@clauses $query; $($rest)*)
+    };
+
+    (@clauses $query:expr; , order_by $field:ident desc $($rest:tt)*) => {
+        $crate::query!(@clauses $query
+            .order_by(|record| record.$field.clone(), $crate::database::SortDirection::Descending); $($rest)*)
+    };
+    (@clauses $query:expr; , order_by $field:ident $($rest:tt)*) => {
+        $crate::query!(@clauses $query
+            .order_by(|record| record.$field.clone(), $crate::database::SortDirection::Ascending); $($rest)*)
+    };
+    (@clauses $query:expr; , offset $offset:tt $($rest:tt)*) => {
+        $crate::query!(@clauses $query.offset($offset); $($rest)*)
+    };
+    (@clauses $query:expr; , limit $limit:tt $($rest:tt)*) => {
+        $crate::query!(@clauses $query.limit($limit); $($rest)*)
+    };
+    (@clauses $query:expr;) => {
+        $query.run()
+    };
+
+    ($db:expr, $model:ty where $($rest:tt)*) => {
+        $db.collection_for::<$model>().and_then(|records| $crate::query!(records where $($rest)*))
+    };
+    ($db:expr, $model:ty $(, $($rest:tt)*)?) => {
+        $db.collection_for::<$model>().and_then(|records| $crate::query!(records $(, $($rest)*)?))
+    };
+    ($records:ident where $($rest:tt)*) => {
+        $crate::query!(@where $records.query(); $($rest)*)
+    };
+    ($records:ident $(, $($rest:tt)*)?) => {
+        $crate::query!(@clauses $records.query(); $(, $($rest)*)?)
+    };
+}
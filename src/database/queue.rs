@@ -0,0 +1,64 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::{Collection, DBError};
+
+/// A durable first-in first-out queue, see `DBManager::queue`. Items are kept in a
+/// tree of their own under increasing sequence numbers, so they come out in the order
+/// they went in, across reopens.
+pub struct Queue<T> {
+    items: Collection<T>,
+}
+
+impl<T> Clone for Queue<T> {
+    fn clone(&self) -> Self {
+        return Queue {
+            items: self.items.clone(),
+        };
+    }
+}
+
+impl<T> Queue<T> {
+    pub(super) fn new(items: Collection<T>) -> Self {
+        return Queue { items };
+    }
+
+    /// The name of the tree holding the items of the queue called `name`.
+    pub(super) fn tree_name(name: &str) -> String {
+        return format!("__queue/{}", name);
+    }
+
+    /// Adds `item` at the back of the queue.
+    pub fn push(&self, item: T) -> Result<(), DBError>
+    where
+        T: Serialize + 'static,
+    {
+        self.items.insert_auto(item)?;
+        return Ok(());
+    }
+
+    /// Removes and returns the item at the front, `None` when the queue is empty. Of
+    /// several callers popping at once, each item goes to exactly one of them.
+    pub fn pop(&self) -> Result<Option<T>, DBError>
+    where
+        T: Serialize + DeserializeOwned + 'static,
+    {
+        return Ok(self.items.pop_min()?.map(|(_, item)| item));
+    }
+
+    /// The item at the front, left in place.
+    pub fn peek(&self) -> Result<Option<T>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        return Ok(self.items.first()?.map(|(_, item)| item));
+    }
+
+    pub fn len(&self) -> Result<usize, DBError> {
+        return self.items.count();
+    }
+
+    pub fn is_empty(&self) -> Result<bool, DBError> {
+        return Ok(self.len()? == 0);
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+
+use sled::{Db, Tree};
+
+use super::access::AccessTracker;
+use super::durability::Flusher;
+use super::events::EventRegistry;
+use super::hooks::HookRegistry;
+use super::index::IndexRegistry;
+use super::lock::KeyLocks;
+use super::relation::RelationRegistry;
+use super::schema::SchemaRegistry;
+use super::validate::ValidatorRegistry;
+use super::verify::CheckRegistry;
+use super::{DBError, DBErrorKind};
+
+/// Runtime state shared by every handle onto the same database.
+#[derive(Debug, Clone)]
+pub(super) struct Registries {
+    pub(super) indexes: IndexRegistry,
+    pub(super) schemas: SchemaRegistry,
+    pub(super) checks: CheckRegistry,
+    pub(super) hooks: HookRegistry,
+    pub(super) validators: ValidatorRegistry,
+    pub(super) events: EventRegistry,
+    pub(super) relations: RelationRegistry,
+    pub(super) ttls: SideTrees,
+    pub(super) versions: SideTrees,
+    pub(super) audits: SideTrees,
+    pub(super) changes: SideTrees,
+    pub(super) access: AccessTracker,
+    // held while a message is published to a topic, so offsets land in order
+    pub(super) publishing: Arc<Mutex<()>>,
+    pub(super) locks: KeyLocks,
+    pub(super) flusher: Flusher,
+    pub(super) read_only: bool,
+    // largest encoded record any collection accepts, unless it has a limit of its own
+    pub(super) max_record_size: Option<usize>,
+}
+
+/// Opens `name` only if it already exists.
+pub(super) fn existing_tree(conn: &Db, name: &str) -> Result<Option<Tree>, DBError> {
+    if !conn.tree_names().iter().any(|existing| existing == name.as_bytes()) {
+        return Ok(None);
+    }
+    return Ok(Some(conn.open_tree(name)?));
+}
+
+impl Registries {
+    pub(super) fn load(conn: &Db, read_only: bool) -> Result<Self, DBError> {
+        return Ok(Registries {
+            indexes: IndexRegistry::default(),
+            schemas: SchemaRegistry::default(),
+            checks: CheckRegistry::default(),
+            hooks: HookRegistry::default(),
+            validators: ValidatorRegistry::default(),
+            events: EventRegistry::default(),
+            relations: RelationRegistry::default(),
+            ttls: SideTrees::load(conn, "__ttl/")?,
+            versions: SideTrees::load(conn, "__version/")?,
+            audits: SideTrees::load(conn, "__audit/")?,
+            changes: SideTrees::load(conn, "__sync/")?,
+            access: AccessTracker::default(),
+            publishing: Arc::default(),
+            locks: KeyLocks::default(),
+            flusher: Flusher::default(),
+            read_only,
+            max_record_size: None,
+        });
+    }
+
+    /// Forgets everything registered for collections whose name starts with `prefix`,
+    /// once their trees are gone.
+    pub(super) fn forget(&self, prefix: &str) {
+        self.indexes.forget(prefix);
+        self.schemas.forget(prefix);
+        self.checks.forget(prefix);
+        self.hooks.forget(prefix);
+        self.validators.forget(prefix);
+        self.events.forget(prefix);
+        self.relations.forget(prefix);
+        self.ttls.forget(prefix);
+        self.versions.forget(prefix);
+        self.audits.forget(prefix);
+        self.changes.forget(prefix);
+        self.access.forget(prefix);
+    }
+
+    /// Fails with `DBErrorKind::ReadOnly` when the database was opened read-only.
+    pub(super) fn writable(&self) -> Result<(), DBError> {
+        if self.read_only {
+            return Err(DBError::new(DBErrorKind::ReadOnly("database was opened read-only".to_string())));
+        }
+        return Ok(());
+    }
+
+    /// Opens a tree, which in a read-only database has to exist already: `None` if it does not.
+    pub(super) fn existing(&self, conn: &Db, name: &str) -> Result<Option<Tree>, DBError> {
+        match self.read_only {
+            true => return existing_tree(conn, name),
+            false => return Ok(Some(conn.open_tree(name)?)),
+        }
+    }
+
+    /// Like `existing`, but a missing tree is a `DBErrorKind::NotFound` error.
+    pub(super) fn open_tree(&self, conn: &Db, name: &str) -> Result<Tree, DBError> {
+        match self.existing(conn, name)? {
+            None => return Err(DBError::new(DBErrorKind::NotFound(format!("collection {}", name)))),
+            Some(tree) => return Ok(tree),
+        }
+    }
+
+    /// Picks up side trees that appeared underneath, e.g. from a restored backup.
+    pub(super) fn reload(&self, conn: &Db) -> Result<(), DBError> {
+        self.ttls.reload(conn)?;
+        self.versions.reload(conn)?;
+        self.audits.reload(conn)?;
+        return self.changes.reload(conn);
+    }
+}
+
+/// Per-collection bookkeeping trees named `{prefix}{collection}`, which only
+/// exist for collections that have used the feature they belong to.
+#[derive(Clone)]
+pub(super) struct SideTrees {
+    prefix: &'static str,
+    inner: Arc<RwLock<HashMap<String, Tree>>>,
+}
+
+impl fmt::Debug for SideTrees {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "SideTrees({})", self.prefix);
+    }
+}
+
+impl SideTrees {
+    fn load(conn: &Db, prefix: &'static str) -> Result<Self, DBError> {
+        let mut trees = HashMap::new();
+        for name in conn.tree_names() {
+            if let Some(collection) = name.strip_prefix(prefix.as_bytes()) {
+                let collection = String::from_utf8_lossy(collection).into_owned();
+                trees.insert(collection, conn.open_tree(&name)?);
+            }
+        }
+        return Ok(SideTrees {
+            prefix,
+            inner: Arc::new(RwLock::new(trees)),
+        });
+    }
+
+    fn reload(&self, conn: &Db) -> Result<(), DBError> {
+        let loaded = SideTrees::load(conn, self.prefix)?;
+        let loaded = loaded.inner.read().unwrap().clone();
+        self.inner.write().unwrap().extend(loaded);
+        return Ok(());
+    }
+
+    fn forget(&self, prefix: &str) {
+        self.inner.write().unwrap().retain(|collection, _| !collection.starts_with(prefix));
+    }
+
+    pub(super) fn get(&self, collection: &str) -> Option<Tree> {
+        return self.inner.read().unwrap().get(collection).cloned();
+    }
+
+    pub(super) fn get_or_open(&self, conn: &Db, collection: &str) -> Result<Tree, DBError> {
+        if let Some(tree) = self.get(collection) {
+            return Ok(tree);
+        }
+        let tree = conn.open_tree(format!("{}{}", self.prefix, collection))?;
+        self.inner.write().unwrap().insert(collection.to_string(), tree.clone());
+        return Ok(tree);
+    }
+
+    pub(super) fn collections(&self) -> Vec<String> {
+        return self.inner.read().unwrap().keys().cloned().collect();
+    }
+}
@@ -0,0 +1,453 @@
+use std::any::Any;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sled::transaction::{abort, TransactionalTree};
+use sled::{Db, IVec, Transactional, Tree};
+
+use super::audit::{self, AuditOp};
+use super::format::META_TREE;
+use super::index::{write_entry, IndexEntry, IndexRegistry};
+use super::limit::{self, Quota, Usage};
+use super::registry::Registries;
+use super::sync::{self, DELETED, SAVED};
+use super::ttl;
+use super::{Collection, DBError, DBErrorKind, Model};
+
+type ForeignKey<C> = dyn Fn(&C) -> Option<String> + Send + Sync;
+
+// rewrites a stored child with its foreign key cleared, returning the new bytes
+// and its values for each of the given indexes
+type Nullify = dyn Fn(&[u8], &[u8], &[IndexEntry]) -> Result<(Vec<u8>, Vec<Vec<Vec<u8>>>), DBError> + Send + Sync;
+
+/// What deleting a parent does while children still point at it.
+/// Clearing the children's foreign key instead is `Relation::on_delete_set_null`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnDelete {
+    /// Delete it anyway, leaving the children dangling.
+    Allow,
+    /// Refuse with `DBErrorKind::ConstraintViolation`.
+    Restrict,
+    /// Delete the children along with it, in the same transaction.
+    Cascade,
+}
+
+#[derive(Clone)]
+enum Policy {
+    Allow,
+    Restrict,
+    Cascade,
+    SetNull(Arc<Nullify>),
+}
+
+/// A declared foreign key from `C` records to the `P` record each one belongs to,
+/// see `DBManager::relation`.
+///
+/// Children are found through an index on the foreign key named after the relation,
+/// so loading them is a lookup rather than a scan.
+pub struct Relation<P, C> {
+    name: String,
+    parents: Collection<P>,
+    children: Collection<C>,
+    foreign_key: Arc<ForeignKey<C>>,
+    relations: RelationRegistry,
+}
+
+impl<P, C> Clone for Relation<P, C> {
+    fn clone(&self) -> Self {
+        return Relation {
+            name: self.name.clone(),
+            parents: self.parents.clone(),
+            children: self.children.clone(),
+            foreign_key: self.foreign_key.clone(),
+            relations: self.relations.clone(),
+        };
+    }
+}
+
+impl<P: Model, C: Model> Relation<P, C> {
+    pub(super) fn declare<F>(
+        parents: Collection<P>,
+        children: Collection<C>,
+        name: &str,
+        foreign_key: F,
+        relations: &RelationRegistry,
+    ) -> Result<Self, DBError>
+    where
+        C: DeserializeOwned + 'static,
+        F: Fn(&C) -> Option<String> + Send + Sync + 'static,
+    {
+        let foreign_key: Arc<ForeignKey<C>> = Arc::new(foreign_key);
+        let key = foreign_key.clone();
+        children.add_index_values(name, false, move |child: &C| key(child).into_iter().map(String::into_bytes).collect())?;
+        relations.register(RelationEntry {
+            name: name.to_string(),
+            parent: parents.name().to_string(),
+            child: children.name().to_string(),
+            foreign_key: Arc::new(foreign_key.clone()),
+            on_delete: Policy::Allow,
+        });
+        return Ok(Relation {
+            name: name.to_string(),
+            parents,
+            children,
+            foreign_key,
+            relations: relations.clone(),
+        });
+    }
+
+    pub fn name(&self) -> &str {
+        return &self.name;
+    }
+
+    /// Sets what deleting a parent with children does, `OnDelete::Allow` until changed.
+    /// Cascades go one level deep, the children's own relations are not followed.
+    pub fn on_delete(self, policy: OnDelete) -> Self {
+        let policy = match policy {
+            OnDelete::Allow => Policy::Allow,
+            OnDelete::Restrict => Policy::Restrict,
+            OnDelete::Cascade => Policy::Cascade,
+        };
+        self.relations.set_policy(self.children.name(), &self.name, policy);
+        return self;
+    }
+
+    /// Keeps the children when their parent is deleted, rewriting them with `clear`,
+    /// which should unset the foreign key, in the same transaction as the delete.
+    pub fn on_delete_set_null<F>(self, clear: F) -> Self
+    where
+        C: Serialize + DeserializeOwned + 'static,
+        F: Fn(&mut C) + Send + Sync + 'static,
+    {
+        let children = self.children.clone();
+        let nullify: Arc<Nullify> = Arc::new(move |id, bytes, indexes| {
+            let mut child = children.decode(id, bytes)?;
+            clear(&mut child);
+            let keys = indexes.iter().map(|index| index.keys(&child)).collect();
+            return Ok((children.encode(id, &child)?, keys));
+        });
+        self.relations.set_policy(self.children.name(), &self.name, Policy::SetNull(nullify));
+        return self;
+    }
+
+    /// The record `child` belongs to, `None` when its foreign key is unset or dangling.
+    pub fn parent(&self, child: &C) -> Result<Option<P>, DBError>
+    where
+        P: DeserializeOwned,
+    {
+        match (self.foreign_key)(child) {
+            None => return Ok(None),
+            Some(key) => return Ok(self.parents.get_many(&[key])?.pop().flatten()),
+        }
+    }
+
+    /// The records belonging to `parent`.
+    pub fn children(&self, parent: &P) -> Result<Vec<C>, DBError>
+    where
+        C: DeserializeOwned,
+    {
+        return self.children_of(&parent.key());
+    }
+
+    /// The records belonging to the parent keyed `key`, which need not exist.
+    pub fn children_of(&self, key: &str) -> Result<Vec<C>, DBError>
+    where
+        C: DeserializeOwned,
+    {
+        return self.children.find_by_index(&self.name, key);
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct RelationEntry {
+    pub(super) name: String,
+    pub(super) parent: String,
+    pub(super) child: String,
+    on_delete: Policy,
+    // an `Arc<ForeignKey<C>>` for the child type
+    foreign_key: Arc<dyn Any + Send + Sync>,
+}
+
+impl RelationEntry {
+    pub(super) fn foreign_key<C: 'static>(&self, child: &C) -> Result<Option<String>, DBError> {
+        match self.foreign_key.downcast_ref::<Arc<ForeignKey<C>>>() {
+            None => return Err(DBError::new(DBErrorKind::Other(format!("relation {} is declared for another type", self.name)))),
+            Some(foreign_key) => return Ok(foreign_key(child)),
+        }
+    }
+}
+
+/// Every relation declared on a database, by child collection and name.
+#[derive(Clone, Default)]
+pub(super) struct RelationRegistry {
+    inner: Arc<RwLock<Vec<RelationEntry>>>,
+}
+
+impl fmt::Debug for RelationRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str("RelationRegistry");
+    }
+}
+
+impl RelationRegistry {
+    fn register(&self, entry: RelationEntry) {
+        let mut inner = self.inner.write().unwrap();
+        inner.retain(|existing| existing.child != entry.child || existing.name != entry.name);
+        inner.push(entry);
+    }
+
+    fn set_policy(&self, child: &str, name: &str, policy: Policy) {
+        let mut inner = self.inner.write().unwrap();
+        for entry in inner.iter_mut().filter(|entry| entry.child == child && entry.name == name) {
+            entry.on_delete = policy.clone();
+        }
+    }
+
+    fn declared_on(&self, parent: &str) -> Vec<RelationEntry> {
+        let inner = self.inner.read().unwrap();
+        return inner.iter().filter(|entry| entry.parent == parent).cloned().collect();
+    }
+
+    /// Whether any relation has `parent` on its parent side.
+    pub(super) fn is_parent(&self, parent: &str) -> bool {
+        return self.inner.read().unwrap().iter().any(|entry| entry.parent == parent);
+    }
+
+    /// Fails with `DBErrorKind::ConstraintViolation`, naming the referrers, when `key` in
+    /// `parent` still has children under a restricting relation.
+    pub(super) fn check_delete(&self, indexes: &IndexRegistry, parent: &str, key: &[u8]) -> Result<(), DBError> {
+        let mut referrers = Vec::new();
+        for entry in self.declared_on(parent) {
+            if !matches!(entry.on_delete, Policy::Restrict) {
+                continue;
+            }
+            let index = match indexes.find(&entry.child, &entry.name) {
+                None => continue,
+                Some(index) => index,
+            };
+            for id in index.ids_for(key)? {
+                referrers.push(format!("{}/{}", entry.child, String::from_utf8_lossy(&id)));
+            }
+        }
+        if !referrers.is_empty() {
+            return Err(DBError::new(DBErrorKind::ConstraintViolation(format!(
+                "{}/{} is still referenced by {}",
+                parent,
+                String::from_utf8_lossy(key),
+                referrers.join(", ")
+            ))));
+        }
+        return Ok(());
+    }
+
+    /// The children of `key` in `parent` that cascading or nulling relations have to
+    /// rewrite when it is deleted.
+    pub(super) fn dependents(&self, indexes: &IndexRegistry, parent: &str, key: &[u8]) -> Result<Vec<Dependents>, DBError> {
+        let mut dependents = Vec::new();
+        for entry in self.declared_on(parent) {
+            let nullify = match entry.on_delete {
+                Policy::Allow | Policy::Restrict => continue,
+                Policy::Cascade => None,
+                Policy::SetNull(nullify) => Some(nullify),
+            };
+            let ids = match indexes.find(&entry.child, &entry.name) {
+                None => continue,
+                Some(index) => index.ids_for(key)?,
+            };
+            if !ids.is_empty() {
+                dependents.push(Dependents {
+                    child: entry.child,
+                    ids,
+                    nullify,
+                });
+            }
+        }
+        return Ok(dependents);
+    }
+
+    pub(super) fn forget(&self, prefix: &str) {
+        self.inner
+            .write()
+            .unwrap()
+            .retain(|entry| !entry.child.starts_with(prefix) && !entry.parent.starts_with(prefix));
+    }
+
+    pub(super) fn find(&self, child: &str, name: &str) -> Result<RelationEntry, DBError> {
+        let inner = self.inner.read().unwrap();
+        match inner.iter().find(|entry| entry.child == child && entry.name == name) {
+            None => return Err(DBError::new(DBErrorKind::NotFound(format!("relation {} on {}", name, child)))),
+            Some(entry) => return Ok(entry.clone()),
+        }
+    }
+}
+
+/// The children one relation has to delete, or with `nullify` rewrite, along with a parent.
+pub(super) struct Dependents {
+    child: String,
+    ids: Vec<IVec>,
+    nullify: Option<Arc<Nullify>>,
+}
+
+// where a collection's data, index and version trees, and the usage count of its
+// quota, sit among a transaction's trees
+struct Layout {
+    name: String,
+    data: usize,
+    indexes: Vec<(IndexEntry, usize)>,
+    versions: Option<usize>,
+    usage: Option<(Quota, usize)>,
+}
+
+impl Layout {
+    fn usage<'a>(&'a self, views: &'a [TransactionalTree]) -> Option<Usage<'a>> {
+        return self.usage.map(|(quota, at)| Usage::new(&self.name, quota, &views[at]));
+    }
+
+    fn index_views<'a>(&'a self, views: &'a [TransactionalTree]) -> Vec<(&'a IndexEntry, &'a TransactionalTree)> {
+        return self.indexes.iter().map(|(entry, at)| (entry, &views[*at])).collect();
+    }
+
+    fn index_entries(&self) -> Vec<IndexEntry> {
+        return self.indexes.iter().map(|(entry, _)| entry.clone()).collect();
+    }
+}
+
+// the trees of every collection taking part, each once even when collections share them
+#[derive(Default)]
+struct TreeSet {
+    trees: Vec<Tree>,
+}
+
+impl TreeSet {
+    fn position(&mut self, tree: Tree) -> usize {
+        if let Some(at) = self.trees.iter().position(|existing| existing.name() == tree.name()) {
+            return at;
+        }
+        self.trees.push(tree);
+        return self.trees.len() - 1;
+    }
+
+    fn layout(&mut self, conn: &Db, shared: &Registries, collection: &str) -> Result<Layout, DBError> {
+        let data = self.position(shared.open_tree(conn, collection)?);
+        let indexes = shared
+            .indexes
+            .for_collection(collection)
+            .into_iter()
+            .map(|entry| {
+                let at = self.position(entry.tree.clone());
+                return (entry, at);
+            })
+            .collect();
+        let versions = shared.versions.get(collection).map(|tree| self.position(tree));
+        let usage = match limit::quota(conn, shared, collection)? {
+            None => None,
+            Some(quota) => Some((quota, self.position(conn.open_tree(META_TREE)?))),
+        };
+        return Ok(Layout {
+            name: collection.to_string(),
+            data,
+            indexes,
+            versions,
+            usage,
+        });
+    }
+}
+
+/// Deletes `id` from `parent` together with its dependents in one transaction, filing
+/// the parent in `trash` when given. False if the parent did not exist.
+pub(super) fn delete_cascading(
+    conn: &Db,
+    shared: &Registries,
+    parent: &str,
+    id: &[u8],
+    trash: Option<Tree>,
+    dependents: &[Dependents],
+    actor: Option<&str>,
+) -> Result<bool, DBError> {
+    shared.writable()?;
+    let mut trees = TreeSet::default();
+    let parent_layout = trees.layout(conn, shared, parent)?;
+    let trash = trash.map(|tree| trees.position(tree));
+    let mut children = Vec::with_capacity(dependents.len());
+    for dependents in dependents {
+        let layout = trees.layout(conn, shared, &dependents.child)?;
+        let entries = layout.index_entries();
+        children.push((dependents, layout, entries));
+    }
+    let deleted_at = ttl::now_millis().to_be_bytes();
+
+    let deleted = trees.trees[..].transaction(|views| {
+        let no_keys = vec![Vec::new(); parent_layout.indexes.len()];
+        let version_view = parent_layout.versions.map(|at| &views[at]);
+        let index_views = parent_layout.index_views(views);
+        let previous = match write_entry(&views[parent_layout.data], &index_views, version_view, id, None, &no_keys)? {
+            None => return Ok(false),
+            Some(previous) => previous,
+        };
+        limit::track(parent_layout.usage(views).as_ref(), id, Some(&previous), None)?;
+        if let Some(at) = trash {
+            views[at].insert(id, [&deleted_at[..], &previous].concat())?;
+        }
+
+        for (dependents, layout, entries) in &children {
+            let data = &views[layout.data];
+            let version_view = layout.versions.map(|at| &views[at]);
+            let index_views = layout.index_views(views);
+            let usage = layout.usage(views);
+            let no_keys = vec![Vec::new(); entries.len()];
+            for child in &dependents.ids {
+                match &dependents.nullify {
+                    None => {
+                        let previous = write_entry(data, &index_views, version_view, child, None, &no_keys)?;
+                        limit::track(usage.as_ref(), child, previous.as_deref(), None)?;
+                    }
+                    Some(nullify) => {
+                        let bytes = match data.get(child)? {
+                            None => continue,
+                            Some(bytes) => bytes,
+                        };
+                        let (value, keys) = match nullify(child, &bytes, entries) {
+                            Err(err) => return abort(err),
+                            Ok(rewritten) => rewritten,
+                        };
+                        write_entry(data, &index_views, version_view, child, Some(value.clone()), &keys)?;
+                        limit::track(usage.as_ref(), child, Some(&bytes), Some(&value))?;
+                    }
+                }
+            }
+        }
+        return Ok(true);
+    })?;
+
+    // expiry bookkeeping lives outside the transaction, as it does for a plain delete
+    if let Some(expiries) = shared.ttls.get(parent) {
+        ttl::clear(&expiries, id)?;
+    }
+    for dependents in dependents.iter().filter(|dependents| dependents.nullify.is_none()) {
+        if let Some(expiries) = shared.ttls.get(&dependents.child) {
+            for child in &dependents.ids {
+                ttl::clear(&expiries, child)?;
+            }
+        }
+    }
+    if deleted {
+        audit::record(conn, shared, parent, id, AuditOp::Delete, actor)?;
+        sync::stamp(shared, conn, parent, id, DELETED, None)?;
+        shared.events.deleted(parent, id);
+        for dependents in dependents {
+            let op = if dependents.nullify.is_none() { AuditOp::Delete } else { AuditOp::Update };
+            let tag = if op == AuditOp::Delete { DELETED } else { SAVED };
+            for child in &dependents.ids {
+                audit::record(conn, shared, &dependents.child, child, op, actor)?;
+                sync::stamp(shared, conn, &dependents.child, child, tag, None)?;
+                // update listeners want the typed record, which is not at hand here
+                if op == AuditOp::Delete {
+                    shared.events.deleted(&dependents.child, child);
+                }
+            }
+        }
+    }
+    return Ok(deleted);
+}
@@ -0,0 +1,214 @@
+//! An interactive shell for browsing a database, see `Shell`. Built with the `repl`
+//! feature and started from the command line with `rustpm <database> shell`.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::json::{self, Value};
+
+use super::schema;
+use super::{Collection, DBError, DBErrorKind, DBManager, Format};
+
+const HELP: &str = "commands:
+  collections               every collection and how many records it holds
+  use <collection>          picks the collection the commands below work on
+  keys [prefix]             ids in the collection, optionally only those starting with prefix
+  get <id>                  one record, pretty printed
+  find <field> <op> <value> records whose field compares to value; op is one of
+                            == != < <= > >= contains, fields nest with dots (address.city)
+  count                     how many records the collection holds
+  help                      this text
+  quit                      leaves the shell";
+
+// how many ids and records a listing shows before it stops
+const LIMIT: usize = 100;
+
+type Decoder = Box<dyn Fn(&[u8], &[u8]) -> Result<Value, DBError>>;
+
+/// A line based shell over a database: browse collections and keys, pretty-print
+/// records and filter them by field.
+///
+/// Records of collections stored as json print as they are; others need their type
+/// given with `register` first, as bincode cannot be read without it.
+pub struct Shell {
+    db: DBManager,
+    decoders: HashMap<String, Decoder>,
+    current: Option<Collection<()>>,
+}
+
+impl Shell {
+    pub fn new(db: DBManager) -> Self {
+        return Shell {
+            db,
+            decoders: HashMap::new(),
+            current: None,
+        };
+    }
+
+    /// Shows the records of `collection` by decoding them as `T` with its codec.
+    pub fn register<T>(mut self, collection: &str) -> Result<Self, DBError>
+    where
+        T: DeserializeOwned + Serialize + 'static,
+    {
+        let typed = self.db.collection::<T>(collection)?;
+        let decoder = move |id: &[u8], bytes: &[u8]| {
+            let data = typed.decode(id, bytes)?;
+            return json::to_value(&data).map_err(|err| DBError::with_source(DBErrorKind::SerializeFailed("record as json".to_string()), err));
+        };
+        self.decoders.insert(collection.to_string(), Box::new(decoder));
+        return Ok(self);
+    }
+
+    /// Reads commands from `input` until it ends or says `quit`, answering on `out`.
+    /// A failing command is reported and the shell carries on.
+    pub fn run(&mut self, input: impl BufRead, out: &mut impl Write) -> Result<(), DBError> {
+        let prompt = |shell: &Shell, out: &mut dyn Write| {
+            let name = shell.current.as_ref().map_or("", |collection| collection.name());
+            let _ = write!(out, "{}> ", name);
+            let _ = out.flush();
+        };
+        prompt(self, out);
+        for line in input.lines() {
+            let line = match line {
+                Err(err) => return Err(DBError::with_source(DBErrorKind::ReadFailed("shell input".to_string()), err)),
+                Ok(line) => line,
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.first() == Some(&"quit") {
+                return Ok(());
+            }
+            let result = self.command(&words).and_then(|lines| {
+                for line in lines {
+                    writeln!(out, "{}", line).map_err(|err| DBError::with_source(DBErrorKind::WriteFailed("shell output".to_string()), err))?;
+                }
+                return Ok(());
+            });
+            if let Err(err) = result {
+                let _ = writeln!(out, "error: {}", err);
+            }
+            prompt(self, out);
+        }
+        return Ok(());
+    }
+
+    fn command(&mut self, words: &[&str]) -> Result<Vec<String>, DBError> {
+        match words {
+            [] => return Ok(Vec::new()),
+            ["help"] => return Ok(vec![HELP.to_string()]),
+            ["collections"] => {
+                let collections = self.db.stats()?.collections;
+                return Ok(collections.into_iter().map(|(name, records)| format!("{}\t{}", name, records)).collect());
+            }
+            ["use", name] => {
+                if !self.db.stats()?.collections.contains_key(*name) {
+                    return Err(DBError::new(DBErrorKind::NotFound(format!("collection {}", name))));
+                }
+                self.current = Some(self.db.collection(name)?);
+                return Ok(Vec::new());
+            }
+            ["keys"] => return self.keys(""),
+            ["keys", prefix] => return self.keys(prefix),
+            ["get", id] => {
+                let collection = self.current()?;
+                match collection.tree.get(id)? {
+                    None => return Err(DBError::new(DBErrorKind::NotFound(format!("{} in {}", id, collection.name())))),
+                    Some(bytes) => return Ok(vec![pretty(&self.decode(collection, id.as_bytes(), &bytes)?)]),
+                }
+            }
+            ["find", field, op, value @ ..] if !value.is_empty() => {
+                let expected = value.join(" ");
+                // values read as json where they can, so `age > 18` compares numbers
+                let expected = json::parse(&expected).unwrap_or(Value::String(expected));
+                return self.find(field, op, &expected);
+            }
+            ["count"] => return Ok(vec![self.current()?.tree.len().to_string()]),
+            _ => return Err(DBError::new(DBErrorKind::Other(format!("unknown command {:?}, try help", words.join(" "))))),
+        }
+    }
+
+    fn current(&self) -> Result<&Collection<()>, DBError> {
+        match &self.current {
+            None => return Err(DBError::new(DBErrorKind::Other("pick a collection with use first".to_string()))),
+            Some(collection) => return Ok(collection),
+        }
+    }
+
+    fn decode(&self, collection: &Collection<()>, id: &[u8], bytes: &[u8]) -> Result<Value, DBError> {
+        if let Some(decoder) = self.decoders.get(collection.name()) {
+            return decoder(id, bytes);
+        }
+        match collection.codec() {
+            Format::Json => {
+                let body = schema::payload(collection.schema().as_ref(), bytes);
+                return json::from_slice(body).map_err(|err| DBError::with_source(DBErrorKind::DeserializeFailed("json record".to_string()), err));
+            }
+            Format::Bincode => {
+                let message = format!("{} holds bincode, register its type to read it", collection.name());
+                return Err(DBError::new(DBErrorKind::Other(message)));
+            }
+        }
+    }
+
+    fn keys(&self, prefix: &str) -> Result<Vec<String>, DBError> {
+        let mut keys = Vec::new();
+        for key in self.current()?.tree.scan_prefix(prefix).keys().take(LIMIT) {
+            keys.push(String::from_utf8_lossy(&key?).into_owned());
+        }
+        return Ok(keys);
+    }
+
+    fn find(&self, field: &str, op: &str, expected: &Value) -> Result<Vec<String>, DBError> {
+        let collection = self.current()?;
+        let mut found = Vec::new();
+        for entry in collection.tree.iter() {
+            let (id, bytes) = entry?;
+            let record = self.decode(collection, &id, &bytes)?;
+            let value = field.split('.').try_fold(&record, |value, part| value.get(part));
+            if matches(value, op, expected)? {
+                found.push(format!("{}\t{}", String::from_utf8_lossy(&id), record));
+                if found.len() == LIMIT {
+                    break;
+                }
+            }
+        }
+        return Ok(found);
+    }
+}
+
+fn pretty(value: &Value) -> String {
+    return json::to_string_pretty(value).unwrap_or_else(|_| value.to_string());
+}
+
+fn compare(value: &Value, expected: &Value) -> Option<Ordering> {
+    match (value.as_f64(), expected.as_f64(), value.as_str(), expected.as_str()) {
+        (Some(value), Some(expected), _, _) => return value.partial_cmp(&expected),
+        (_, _, Some(value), Some(expected)) => return Some(value.cmp(expected)),
+        _ => return None,
+    }
+}
+
+fn matches(value: Option<&Value>, op: &str, expected: &Value) -> Result<bool, DBError> {
+    let value = match value {
+        None => return Ok(op == "!="),
+        Some(value) => value,
+    };
+    let order = compare(value, expected);
+    match op {
+        "==" => return Ok(value == expected || order == Some(Ordering::Equal)),
+        "!=" => return Ok(value != expected && order != Some(Ordering::Equal)),
+        "<" => return Ok(order == Some(Ordering::Less)),
+        "<=" => return Ok(matches!(order, Some(Ordering::Less | Ordering::Equal))),
+        ">" => return Ok(order == Some(Ordering::Greater)),
+        ">=" => return Ok(matches!(order, Some(Ordering::Greater | Ordering::Equal))),
+        "contains" => match (value, expected.as_str()) {
+            (Value::String(value), Some(expected)) => return Ok(value.contains(expected)),
+            (Value::Array(items), _) => return Ok(items.contains(expected)),
+            _ => return Ok(false),
+        },
+        _ => return Err(DBError::new(DBErrorKind::Other(format!("unknown operator {}", op)))),
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::HashSet;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use sled::{Db, Event, Tree};
+
+use super::registry::Registries;
+use super::{DBError, DBErrorKind};
+
+// the stream is a header followed by frames, each a tag, the tree name and the key,
+// plus the value for a set. every byte string is a u64 BE length and the bytes.
+// a tree's stream starts with a clear and a set per record it holds, then its changes
+const MAGIC: &[u8] = b"rustpm-replica\x01";
+const SET: u8 = b's';
+const REMOVE: u8 = b'r';
+const CLEAR: u8 = b'c';
+
+// how often the background threads look up from waiting to see if they should stop
+const POLL: Duration = Duration::from_millis(50);
+// how long a primary may take to send the rest of a frame it has started
+const FRAME_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn write_bytes(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    out.write_all(&(bytes.len() as u64).to_be_bytes())?;
+    return out.write_all(bytes);
+}
+
+fn write_frame(out: &Mutex<BufWriter<TcpStream>>, tag: u8, tree: &[u8], key: &[u8], value: Option<&[u8]>) -> io::Result<()> {
+    let mut out = out.lock().unwrap();
+    out.write_all(&[tag])?;
+    write_bytes(&mut *out, tree)?;
+    write_bytes(&mut *out, key)?;
+    if let Some(value) = value {
+        write_bytes(&mut *out, value)?;
+    }
+    return out.flush();
+}
+
+fn failed(err: io::Error) -> DBError {
+    return DBError::with_source(DBErrorKind::Other("replication stream failed".to_string()), err);
+}
+
+/// Streams every change to a standby started with `DBManager::serve_replica`, stopped
+/// when dropped. See `DBManager::replicate_to`.
+pub struct Replicator {
+    stop: Arc<AtomicBool>,
+    error: Arc<Mutex<Option<String>>>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Replicator {
+    pub(super) fn start(conn: &Db, addr: impl ToSocketAddrs) -> Result<Self, DBError> {
+        let mut stream = TcpStream::connect(addr).map_err(failed)?;
+        stream.write_all(MAGIC).map_err(failed)?;
+        let out = Arc::new(Mutex::new(BufWriter::new(stream)));
+        let stop = Arc::new(AtomicBool::new(false));
+        let error = Arc::new(Mutex::new(None));
+
+        let mut handles = Vec::new();
+        for name in conn.tree_names() {
+            let tree = conn.open_tree(&name)?;
+            // watch first so nothing written while the snapshot is sent is missed
+            let changes = tree.watch_prefix(vec![]);
+            let (out, stop, error) = (out.clone(), stop.clone(), error.clone());
+            let handle = thread::Builder::new()
+                .name("rustpm-replicator".to_string())
+                .spawn(move || {
+                    if let Err(err) = forward(&tree, changes, &out, &stop) {
+                        error.lock().unwrap().get_or_insert(err.to_string());
+                        stop.store(true, Ordering::SeqCst);
+                    }
+                })
+                .expect("failed to spawn replicator");
+            handles.push(handle);
+        }
+        return Ok(Replicator { stop, error, handles });
+    }
+
+    /// Why replication stopped, if the connection to the standby failed.
+    pub fn error(&self) -> Option<String> {
+        return self.error.lock().unwrap().clone();
+    }
+}
+
+impl Drop for Replicator {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn forward(tree: &Tree, mut changes: sled::Subscriber, out: &Mutex<BufWriter<TcpStream>>, stop: &AtomicBool) -> io::Result<()> {
+    let name = tree.name();
+    write_frame(out, CLEAR, &name, b"", None)?;
+    for entry in tree.iter() {
+        let (key, value) = entry.map_err(io::Error::other)?;
+        write_frame(out, SET, &name, &key, Some(&value))?;
+    }
+
+    while !stop.load(Ordering::SeqCst) {
+        match changes.next_timeout(POLL) {
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            Ok(Event::Insert { key, value }) => write_frame(out, SET, &name, &key, Some(&value))?,
+            Ok(Event::Remove { key }) => write_frame(out, REMOVE, &name, &key, None)?,
+        }
+    }
+    return Ok(());
+}
+
+/// A standby taking in the changes a `Replicator` sends, until dropped. See
+/// `DBManager::serve_replica`.
+pub struct Replica {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    error: Arc<Mutex<Option<String>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Replica {
+    pub(super) fn start(conn: Db, shared: Registries, addr: impl ToSocketAddrs) -> Result<Self, DBError> {
+        shared.writable()?;
+        let listener = TcpListener::bind(addr).map_err(failed)?;
+        listener.set_nonblocking(true).map_err(failed)?;
+        let addr = listener.local_addr().map_err(failed)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let error = Arc::new(Mutex::new(None));
+
+        let (running, failure) = (stop.clone(), error.clone());
+        let handle = thread::Builder::new()
+            .name("rustpm-replica".to_string())
+            .spawn(move || {
+                while !running.load(Ordering::SeqCst) {
+                    match listener.accept() {
+                        Err(err) if err.kind() == ErrorKind::WouldBlock => thread::sleep(POLL),
+                        // such as running out of file descriptors, which does not clear at once
+                        Err(err) => {
+                            *failure.lock().unwrap() = Some(err.to_string());
+                            thread::sleep(POLL);
+                        }
+                        // one primary at a time, a new connection starts over from its snapshot
+                        Ok((stream, _)) => {
+                            if let Err(err) = apply(&conn, &shared, stream, &running) {
+                                *failure.lock().unwrap() = Some(err.to_string());
+                            }
+                        }
+                    }
+                }
+            })
+            .expect("failed to spawn replica");
+        return Ok(Replica {
+            addr,
+            stop,
+            error,
+            handle: Some(handle),
+        });
+    }
+
+    /// Where the standby listens, useful after binding port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        return self.addr;
+    }
+
+    /// Why the last connection from a primary ended, if it broke off.
+    pub fn error(&self) -> Option<String> {
+        return self.error.lock().unwrap().clone();
+    }
+}
+
+impl Drop for Replica {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn damaged(reason: &str) -> DBError {
+    return DBError::new(DBErrorKind::ReadFailed(format!("replication stream is damaged: {}", reason)));
+}
+
+// reads through `take` so a damaged length cannot allocate more than was sent
+fn read_bytes(input: &mut impl Read) -> Result<Vec<u8>, DBError> {
+    let mut len = [0; 8];
+    input.read_exact(&mut len).map_err(failed)?;
+    let len = u64::from_be_bytes(len);
+    let mut bytes = Vec::new();
+    input.by_ref().take(len).read_to_end(&mut bytes).map_err(failed)?;
+    if bytes.len() as u64 != len {
+        return Err(damaged("truncated value"));
+    }
+    return Ok(bytes);
+}
+
+// the connection from the primary, waiting out read timeouts until the replica is
+// stopped, which reads as the end of the stream, or a frame takes too long to arrive
+struct Incoming<'a> {
+    stream: TcpStream,
+    stop: &'a AtomicBool,
+    deadline: Option<Instant>,
+}
+
+impl Read for Incoming<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.stream.read(buf) {
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    if self.stop.load(Ordering::SeqCst) {
+                        return Ok(0);
+                    }
+                    if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        return Err(io::Error::new(ErrorKind::TimedOut, "primary stalled part way through a frame"));
+                    }
+                }
+                read => return read,
+            }
+        }
+    }
+}
+
+fn apply(conn: &Db, shared: &Registries, stream: TcpStream, stop: &AtomicBool) -> Result<(), DBError> {
+    stream.set_nonblocking(false).map_err(failed)?;
+    stream.set_read_timeout(Some(POLL)).map_err(failed)?;
+    let mut input = BufReader::new(Incoming {
+        stream,
+        stop,
+        deadline: Some(Instant::now() + FRAME_TIMEOUT),
+    });
+    let mut magic = [0; MAGIC.len()];
+    input.read_exact(&mut magic).map_err(failed)?;
+    if magic != MAGIC {
+        return Err(damaged("not a replication stream"));
+    }
+
+    let mut seen = HashSet::new();
+    while !stop.load(Ordering::SeqCst) {
+        // the wait for the next frame has no deadline, reading the frame itself does
+        input.get_mut().deadline = None;
+        let mut tag = [0; 1];
+        if input.read(&mut tag).map_err(failed)? == 0 {
+            return Ok(());
+        }
+        input.get_mut().deadline = Some(Instant::now() + FRAME_TIMEOUT);
+        let name = read_bytes(&mut input)?;
+        let key = read_bytes(&mut input)?;
+        let tree = conn.open_tree(&name)?;
+        match tag[0] {
+            SET => {
+                tree.insert(key, read_bytes(&mut input)?)?;
+            }
+            REMOVE => {
+                tree.remove(key)?;
+            }
+            CLEAR => tree.clear()?,
+            _ => return Err(damaged("unknown frame")),
+        }
+        // expiry, version and audit trees are found by name, like after a restore
+        if seen.insert(name) {
+            shared.reload(conn)?;
+        }
+    }
+    return Ok(());
+}
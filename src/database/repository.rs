@@ -0,0 +1,73 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::{Collection, DBError, Model};
+
+/// A `Model`'s collection with the element type pinned, keyed by `Model::key`.
+pub struct Repository<T> {
+    collection: Collection<T>,
+}
+
+impl<T> Clone for Repository<T> {
+    fn clone(&self) -> Self {
+        return Repository {
+            collection: self.collection.clone(),
+        };
+    }
+}
+
+impl<T: Model> Repository<T> {
+    pub(super) fn new(collection: Collection<T>) -> Self {
+        return Repository { collection };
+    }
+
+    /// The underlying collection, for the operations not mirrored here.
+    pub fn collection(&self) -> &Collection<T> {
+        return &self.collection;
+    }
+
+    pub fn insert(&self, data: T) -> Result<String, DBError>
+    where
+        T: Serialize + 'static,
+    {
+        let key = data.key();
+        self.collection.upsert(key.clone(), data)?;
+        return Ok(key);
+    }
+
+    pub fn update(&self, data: T) -> Result<(), DBError>
+    where
+        T: Serialize + 'static,
+    {
+        return self.collection.update(data.key(), data);
+    }
+
+    pub fn get(&self, key: String) -> Result<T, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        return self.collection.get(key);
+    }
+
+    pub fn all(&self) -> Result<Vec<T>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        return self.collection.get_all();
+    }
+
+    pub fn exists(&self, key: String) -> Result<bool, DBError> {
+        return self.collection.exists(key);
+    }
+
+    pub fn count(&self) -> Result<usize, DBError> {
+        return self.collection.count();
+    }
+
+    pub fn delete(&self, key: String) -> Result<Option<T>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        return self.collection.delete(key);
+    }
+}
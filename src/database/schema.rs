@@ -0,0 +1,121 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use super::{DBError, DBErrorKind};
+
+/// A record type whose stored layout is versioned.
+///
+/// Once registered (`Collection::register_versioned`), every write is stamped with
+/// `VERSION` and reads of records stamped with an older version (or written before
+/// versioning, which count as version 0) go through `upgrade` first.
+pub trait Versioned: Sized {
+    const VERSION: u32;
+
+    /// Rebuilds a record from the bytes an older release stored, as encoded by its codec.
+    fn upgrade(old_version: u32, bytes: &[u8]) -> Result<Self, DBError>;
+}
+
+// the upgrade hook is stored already re-encoded so reading needs no type information
+type Upgrade = Arc<dyn Fn(&[u8], u32, &[u8]) -> Result<Vec<u8>, DBError> + Send + Sync>;
+
+// 0xff never starts a json document and is an unlikely first byte for bincode
+const MAGIC: &[u8] = b"\xffrv";
+const HEADER_LEN: usize = MAGIC.len() + 4;
+
+#[derive(Clone)]
+pub(super) struct Schema {
+    version: u32,
+    upgrade: Upgrade,
+}
+
+impl Schema {
+    pub(super) fn new<F>(version: u32, upgrade: F) -> Self
+    where
+        F: Fn(&[u8], u32, &[u8]) -> Result<Vec<u8>, DBError> + Send + Sync + 'static,
+    {
+        return Schema {
+            version,
+            upgrade: Arc::new(upgrade),
+        };
+    }
+}
+
+/// Splits a stored value into its version stamp and the encoded record.
+pub(super) fn split(bytes: &[u8]) -> (u32, &[u8]) {
+    if bytes.len() >= HEADER_LEN && bytes.starts_with(MAGIC) {
+        let version = u32::from_be_bytes(bytes[MAGIC.len()..HEADER_LEN].try_into().unwrap());
+        return (version, &bytes[HEADER_LEN..]);
+    }
+    return (0, bytes);
+}
+
+/// Prefixes an encoded record with the current version, when the collection has one.
+pub(super) fn stamp(schema: Option<&Schema>, bytes: Vec<u8>) -> Vec<u8> {
+    match schema {
+        None => return bytes,
+        Some(schema) => {
+            let mut stamped = Vec::with_capacity(HEADER_LEN + bytes.len());
+            stamped.extend_from_slice(MAGIC);
+            stamped.extend_from_slice(&schema.version.to_be_bytes());
+            stamped.extend_from_slice(&bytes);
+            return stamped;
+        }
+    }
+}
+
+/// The encoded record in a stored value, without its version stamp. Only collections
+/// with a schema have stamps; anywhere else the value is the record, whatever it starts with.
+pub(super) fn payload<'a>(schema: Option<&Schema>, bytes: &'a [u8]) -> &'a [u8] {
+    match schema {
+        None => return bytes,
+        Some(_) => return split(bytes).1,
+    }
+}
+
+/// The encoded record under `id` in the current layout, upgrading it if it was stored by
+/// an older version.
+pub(super) fn current<'a>(schema: Option<&Schema>, id: &[u8], bytes: &'a [u8]) -> Result<Cow<'a, [u8]>, DBError> {
+    let schema = match schema {
+        None => return Ok(Cow::Borrowed(bytes)),
+        Some(schema) => schema,
+    };
+    let (version, payload) = split(bytes);
+
+    if version == schema.version {
+        return Ok(Cow::Borrowed(payload));
+    }
+    if version > schema.version {
+        return Err(DBError::new(DBErrorKind::ReadFailed(format!(
+            "record has version {}, newer than {}",
+            version, schema.version
+        ))));
+    }
+    return Ok(Cow::Owned((schema.upgrade)(id, version, payload)?));
+}
+
+#[derive(Clone, Default)]
+pub(super) struct SchemaRegistry {
+    inner: Arc<RwLock<HashMap<String, Schema>>>,
+}
+
+impl fmt::Debug for SchemaRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str("SchemaRegistry");
+    }
+}
+
+impl SchemaRegistry {
+    pub(super) fn register(&self, collection: &str, schema: Schema) {
+        self.inner.write().unwrap().insert(collection.to_string(), schema);
+    }
+
+    pub(super) fn forget(&self, prefix: &str) {
+        self.inner.write().unwrap().retain(|collection, _| !collection.starts_with(prefix));
+    }
+
+    pub(super) fn get(&self, collection: &str) -> Option<Schema> {
+        return self.inner.read().unwrap().get(collection).cloned();
+    }
+}
@@ -0,0 +1,42 @@
+use std::collections::HashSet;
+
+use sled::IVec;
+
+/// The index `Collection::create_text_index` keeps its postings in, one entry per word and record.
+pub(super) const TEXT_INDEX: &str = "__text";
+
+// words are runs of letters and digits, compared lowercased
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    return text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase);
+}
+
+/// The distinct words across a record's text fields, as index values.
+pub(super) fn tokens(fields: &[String]) -> Vec<Vec<u8>> {
+    let mut tokens: Vec<Vec<u8>> = fields.iter().flat_map(|field| words(field)).map(String::into_bytes).collect();
+    tokens.sort();
+    tokens.dedup();
+    return tokens;
+}
+
+/// The distinct words of a query.
+pub(super) fn terms(query: &str) -> Vec<String> {
+    let mut terms: Vec<String> = words(query).collect();
+    terms.sort();
+    terms.dedup();
+    return terms;
+}
+
+/// The ids present in every posting list, in the order of the shortest one.
+pub(super) fn intersect(mut postings: Vec<Vec<IVec>>) -> Vec<IVec> {
+    postings.sort_by_key(Vec::len);
+    let mut postings = postings.into_iter();
+    let shortest = match postings.next() {
+        None => return Vec::new(),
+        Some(shortest) => shortest,
+    };
+    let others: Vec<HashSet<IVec>> = postings.map(|ids| ids.into_iter().collect()).collect();
+    return shortest.into_iter().filter(|id| others.iter().all(|ids| ids.contains(id))).collect();
+}
@@ -0,0 +1,79 @@
+use std::collections::BTreeMap;
+
+use sled::Db;
+
+use super::DBError;
+
+/// A snapshot of the database for health pages, see `DBManager::stats`.
+///
+/// sled does not report cache hit rates outside its own `metrics` build, so they are
+/// not part of this.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// Bytes the database takes up on disk, logs and snapshots included.
+    pub size_on_disk: u64,
+    /// Records in the default collection, the one `insert_data` and friends use.
+    pub default_records: usize,
+    /// Records per named collection.
+    pub collections: BTreeMap<String, usize>,
+    /// Every tree sled holds, including the indexes and bookkeeping ones.
+    pub trees: Vec<String>,
+    /// Whether the database was recovered from a previous run rather than created fresh.
+    pub was_recovered: bool,
+}
+
+/// One collection found in the database, see `DBManager::list_collections`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectionInfo {
+    pub name: String,
+    pub records: usize,
+    /// Bytes of keys and stored values; sled's own overhead and compression are not counted.
+    pub bytes: u64,
+}
+
+// trees named like this hold bookkeeping rather than records
+fn internal(name: &str) -> bool {
+    return name.starts_with("__");
+}
+
+pub(super) fn collect(conn: &Db) -> Result<Stats, DBError> {
+    let mut collections = BTreeMap::new();
+    let mut trees = Vec::new();
+    for name in conn.tree_names() {
+        let name = String::from_utf8_lossy(&name).into_owned();
+        if !internal(&name) {
+            collections.insert(name.clone(), conn.open_tree(&name)?.len());
+        }
+        trees.push(name);
+    }
+    return Ok(Stats {
+        size_on_disk: conn.size_on_disk()?,
+        default_records: conn.len(),
+        collections,
+        trees,
+        was_recovered: conn.was_recovered(),
+    });
+}
+
+pub(super) fn list(conn: &Db) -> Result<Vec<CollectionInfo>, DBError> {
+    let mut collections = Vec::new();
+    for name in conn.tree_names() {
+        let name = String::from_utf8_lossy(&name).into_owned();
+        if internal(&name) {
+            continue;
+        }
+        let mut info = CollectionInfo {
+            records: 0,
+            bytes: 0,
+            name,
+        };
+        for entry in conn.open_tree(&info.name)?.iter() {
+            let (key, value) = entry?;
+            info.records += 1;
+            info.bytes += (key.len() + value.len()) as u64;
+        }
+        collections.push(info);
+    }
+    collections.sort_by(|a, b| a.name.cmp(&b.name));
+    return Ok(collections);
+}
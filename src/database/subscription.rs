@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use sled::{Event, IVec, Subscriber};
+
+use super::{Codec, Collection, DBError, DBErrorKind, Format};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeEvent<T> {
+    Inserted { id: String, data: T },
+    Updated { id: String, data: T },
+    Deleted { id: String },
+}
+
+/// Typed change feed over sled's `watch_prefix`.
+///
+/// sled only reports sets and removals, so the subscription remembers which keys
+/// exist (seeded when it is created) to tell inserts from updates.
+pub struct Subscription<T, C = Format> {
+    inner: Subscriber,
+    known: HashSet<IVec>,
+    collection: Collection<T, C>,
+}
+
+impl<T: DeserializeOwned, C: Codec> Subscription<T, C> {
+    pub(super) fn new(collection: &Collection<T, C>, prefix: &[u8]) -> Result<Self, DBError> {
+        let tree = &collection.tree;
+        // watch first so nothing written while seeding is missed
+        let inner = tree.watch_prefix(prefix);
+        let mut known = HashSet::new();
+        for key in tree.scan_prefix(prefix).keys() {
+            known.insert(key?);
+        }
+        return Ok(Subscription {
+            inner,
+            known,
+            collection: collection.clone(),
+        });
+    }
+
+    /// Waits up to `timeout` for the next change, `Ok(None)` when nothing happened.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Result<Option<ChangeEvent<T>>, DBError> {
+        loop {
+            match self.inner.next_timeout(timeout) {
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(DBError::new(DBErrorKind::Other("subscription closed".to_string())))
+                }
+                Ok(event) => {
+                    if let Some(change) = self.classify(event) {
+                        return change.map(Some);
+                    }
+                }
+            }
+        }
+    }
+
+    fn classify(&mut self, event: Event) -> Option<Result<ChangeEvent<T>, DBError>> {
+        match event {
+            Event::Insert { key, value } => {
+                let id = String::from_utf8_lossy(&key).into_owned();
+                let data = match self.collection.decode(&key, &value) {
+                    Err(err) => return Some(Err(err)),
+                    Ok(data) => data,
+                };
+                if self.known.insert(key) {
+                    return Some(Ok(ChangeEvent::Inserted { id, data }));
+                }
+                return Some(Ok(ChangeEvent::Updated { id, data }));
+            }
+            Event::Remove { key } => {
+                // removing a key that was never there changes nothing
+                if !self.known.remove(&key) {
+                    return None;
+                }
+                let id = String::from_utf8_lossy(&key).into_owned();
+                return Some(Ok(ChangeEvent::Deleted { id }));
+            }
+        }
+    }
+}
+
+impl<T: DeserializeOwned, C: Codec> Iterator for Subscription<T, C> {
+    type Item = Result<ChangeEvent<T>, DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let event = self.inner.next()?;
+            if let Some(change) = self.classify(event) {
+                return Some(change);
+            }
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_derive::{Deserialize, Serialize};
+use sled::transaction::ConflictableTransactionError;
+use sled::{IVec, Tree};
+
+use super::registry::Registries;
+use super::DBError;
+
+/// A position in a collection's change log, see `Collection::changes_since`. The
+/// default token is the start of the log. Tokens are only meaningful to the
+/// database that handed them out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct SyncToken(pub u64);
+
+/// The latest state of one record: `None` when it was deleted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change<T> {
+    pub id: String,
+    pub data: Option<T>,
+    /// Microseconds since the epoch of the write this state comes from, kept when
+    /// the change is applied elsewhere. No two writes in one process share a value.
+    pub modified: u64,
+}
+
+/// The records changed after a token, each once, oldest change first. Pass `token`
+/// to the next `changes_since` to pick up from here.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Changeset<T> {
+    pub changes: Vec<Change<T>>,
+    pub token: SyncToken,
+}
+
+/// A record changed both here and in the changes being applied, see `ConflictStrategy`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict<T> {
+    pub local: Change<T>,
+    pub remote: Change<T>,
+}
+
+type Resolver<T> = dyn Fn(&Conflict<T>) -> Option<T> + Send + Sync;
+
+/// How `Collection::apply_changes` settles a record that was also changed here since
+/// the last sync.
+#[derive(Clone, Default)]
+pub enum ConflictStrategy<T> {
+    /// The change with the later modification time wins; ties go to the larger
+    /// encoded record, so both sides settle the same way.
+    #[default]
+    LastWriterWins,
+    /// Keeps the local record, which goes out with the next changeset.
+    PreferLocal,
+    PreferRemote,
+    /// Gets both versions and returns the one to keep, `None` to delete the record.
+    /// The result is written as a new local change.
+    Resolve(Arc<Resolver<T>>),
+}
+
+impl<T> ConflictStrategy<T> {
+    pub fn resolve<F>(f: F) -> Self
+    where
+        F: Fn(&Conflict<T>) -> Option<T> + Send + Sync + 'static,
+    {
+        return ConflictStrategy::Resolve(Arc::new(f));
+    }
+}
+
+impl<T> fmt::Debug for ConflictStrategy<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConflictStrategy::LastWriterWins => return f.write_str("LastWriterWins"),
+            ConflictStrategy::PreferLocal => return f.write_str("PreferLocal"),
+            ConflictStrategy::PreferRemote => return f.write_str("PreferRemote"),
+            ConflictStrategy::Resolve(_) => return f.write_str("Resolve"),
+        }
+    }
+}
+
+/// What `Collection::apply_changes` did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ApplyReport {
+    /// Records written or deleted here.
+    pub applied: usize,
+    /// Changes that met a local change, however they were settled.
+    pub conflicts: usize,
+}
+
+// `s` + sequence + id -> modified + tag + origin lists the log in order, one entry per
+// record, `i` + id -> sequence + modified finds a record's entry again when it changes
+const BY_SEQUENCE: u8 = b's';
+const BY_ID: u8 = b'i';
+pub(super) const SAVED: u8 = b'u';
+pub(super) const DELETED: u8 = b'd';
+const LOCAL: u8 = b'l';
+const REMOTE: u8 = b'r';
+
+/// One entry of the log, with the raw id.
+pub(super) struct Entry {
+    pub(super) sequence: u64,
+    pub(super) id: IVec,
+    pub(super) modified: u64,
+    pub(super) deleted: bool,
+}
+
+// the clock, nudged forward where needed so every change gets its own stamp and
+// `apply_changes` can tell a change it has seen from one made in the same instant
+fn now_micros() -> u64 {
+    static LAST: AtomicU64 = AtomicU64::new(0);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_micros() as u64)
+        .unwrap_or(0);
+    let mut last = LAST.load(Ordering::SeqCst);
+    loop {
+        let next = now.max(last + 1);
+        match LAST.compare_exchange(last, next, Ordering::SeqCst, Ordering::SeqCst) {
+            Err(current) => last = current,
+            Ok(_) => return next,
+        }
+    }
+}
+
+fn id_key(id: &[u8]) -> Vec<u8> {
+    return [&[BY_ID], id].concat();
+}
+
+fn sequence_key(sequence: u64, id: &[u8]) -> Vec<u8> {
+    return [&[BY_SEQUENCE][..], &sequence.to_be_bytes(), id].concat();
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    return u64::from_be_bytes(bytes[at..at + 8].try_into().unwrap());
+}
+
+/// When `id` was last changed, `None` if the log has no entry for it.
+pub(super) fn modified(tree: &Tree, id: &[u8]) -> Result<Option<u64>, DBError> {
+    return Ok(tree.get(id_key(id))?.map(|entry| u64_at(&entry, 8)));
+}
+
+/// When `id` was changed here, rather than by applying changes, from `since` on.
+pub(super) fn pending(tree: &Tree, id: &[u8], since: SyncToken) -> Result<Option<u64>, DBError> {
+    let entry = match tree.get(id_key(id))? {
+        Some(entry) if u64_at(&entry, 0) >= since.0 => entry,
+        _ => return Ok(None),
+    };
+    match tree.get(sequence_key(u64_at(&entry, 0), id))? {
+        Some(logged) if logged.get(9) == Some(&LOCAL) => return Ok(Some(u64_at(&entry, 8))),
+        _ => return Ok(None),
+    }
+}
+
+/// Moves `id` to the end of `collection`'s log, when it keeps one. `modified` is
+/// now unless the change came from somewhere else.
+pub(super) fn stamp(
+    shared: &Registries,
+    conn: &sled::Db,
+    collection: &str,
+    id: &[u8],
+    tag: u8,
+    modified: Option<u64>,
+) -> Result<(), DBError> {
+    let tree = match shared.changes.get(collection) {
+        None => return Ok(()),
+        Some(tree) => tree,
+    };
+    let sequence = conn.generate_id()?;
+    let origin = if modified.is_some() { REMOTE } else { LOCAL };
+    let modified = modified.unwrap_or_else(now_micros);
+    // the old position is dropped with the new one written, so a concurrent stamp of
+    // the same id cannot leave it in the log twice
+    tree.transaction(|tx| {
+        if let Some(previous) = tx.get(id_key(id))? {
+            tx.remove(sequence_key(u64_at(&previous, 0), id))?;
+        }
+        tx.insert(sequence_key(sequence, id), [&modified.to_be_bytes()[..], &[tag, origin]].concat())?;
+        tx.insert(id_key(id), [sequence.to_be_bytes(), modified.to_be_bytes()].concat())?;
+        return Ok::<_, ConflictableTransactionError<DBError>>(());
+    })?;
+    return Ok(());
+}
+
+/// Log entries from `token` on, oldest first; a token is the next sequence to read.
+pub(super) fn since(tree: &Tree, token: SyncToken) -> Result<Vec<Entry>, DBError> {
+    let start = sequence_key(token.0, b"");
+    let mut entries = Vec::new();
+    for entry in tree.range(start..vec![BY_SEQUENCE + 1]) {
+        let (key, value) = entry?;
+        entries.push(Entry {
+            sequence: u64_at(&key, 1),
+            id: key.subslice(9, key.len() - 9),
+            modified: u64_at(&value, 0),
+            deleted: value[8] == DELETED,
+        });
+    }
+    return Ok(entries);
+}
@@ -0,0 +1,99 @@
+use sled::Db;
+
+use super::format::META_TREE;
+use super::transaction::{Transaction, TxResult};
+use super::{Codec, Collection, DBError, DBErrorKind, DBManager, Format};
+
+// the side trees a collection can have, each named by the prefix and the collection
+const SIDE_TREES: [&str; 7] = ["__index/", "__ttl/", "__version/", "__trash/", "__audit/", "__sync/", "__lease/"];
+
+/// One tenant's slice of the database, see `DBManager::tenant`.
+///
+/// Collections opened here are separate trees from those of other tenants and of
+/// the database itself, so keys, indexes and iteration never cross tenants.
+#[derive(Debug, Clone)]
+pub struct Tenant {
+    db: DBManager,
+    id: String,
+    prefix: String,
+}
+
+impl Tenant {
+    pub(super) fn new(db: DBManager, id: &str) -> Result<Self, DBError> {
+        if id.is_empty() || id.contains('/') {
+            return Err(DBError::new(DBErrorKind::Other(format!("invalid tenant id {:?}", id))));
+        }
+        return Ok(Tenant {
+            db,
+            id: id.to_string(),
+            prefix: prefix(id),
+        });
+    }
+
+    pub fn id(&self) -> &str {
+        return &self.id;
+    }
+
+    pub fn collection<T>(&self, name: &str) -> Result<Collection<T>, DBError> {
+        return self.db.collection(&self.tree_name(name));
+    }
+
+    pub fn collection_with_format<T>(&self, name: &str, format: Format) -> Result<Collection<T>, DBError> {
+        return self.db.collection_with_format(&self.tree_name(name), format);
+    }
+
+    pub fn collection_with_codec<T, C: Codec>(&self, name: &str, codec: C) -> Result<Collection<T, C>, DBError> {
+        return self.db.collection_with_codec(&self.tree_name(name), codec);
+    }
+
+    /// Like `DBManager::transaction`, over this tenant's collections.
+    pub fn transaction<F, R>(&self, collections: &[&str], f: F) -> Result<R, DBError>
+    where
+        F: Fn(&Transaction<'_>) -> TxResult<R>,
+    {
+        return self.db.transaction_in(&self.prefix, collections, f);
+    }
+
+    /// Names of the collections this tenant has, as passed to `collection`.
+    pub fn collections(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for name in self.db.conn.tree_names() {
+            if let Some(name) = name.strip_prefix(self.prefix.as_bytes()) {
+                names.push(String::from_utf8_lossy(name).into_owned());
+            }
+        }
+        return names;
+    }
+
+    fn tree_name(&self, collection: &str) -> String {
+        return format!("{}{}", self.prefix, collection);
+    }
+}
+
+fn prefix(id: &str) -> String {
+    return format!("tenant/{}/", id);
+}
+
+/// Drops every tree belonging to tenant `id` and its bookkeeping, returning how many
+/// collections it had.
+pub(super) fn delete(conn: &Db, id: &str) -> Result<usize, DBError> {
+    let prefix = prefix(id);
+    let mut collections = 0;
+    for name in conn.tree_names() {
+        let side = SIDE_TREES.iter().any(|side| name.starts_with(format!("{}{}", side, prefix).as_bytes()));
+        if name.starts_with(prefix.as_bytes()) || side {
+            conn.drop_tree(&name)?;
+            if !side {
+                collections += 1;
+            }
+        }
+    }
+
+    let meta = conn.open_tree(META_TREE)?;
+    for setting in ["format/", "codec/", "soft_delete/", "sequence/", "max_record_size/", "quota/", "usage/"] {
+        for key in meta.scan_prefix(format!("{}{}", setting, prefix)).keys() {
+            meta.remove(key?)?;
+        }
+    }
+    return Ok(collections);
+}
@@ -0,0 +1,105 @@
+use std::ops::Bound;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sled::Tree;
+
+use super::registry::Registries;
+use super::{Collection, DBError, DBErrorKind, Key};
+
+/// A durable log of messages, see `DBManager::topic`. Publishers append messages under
+/// increasing offsets, starting at 1; each subscriber reads on from the last offset it
+/// committed, which is kept in the database so it survives reopens.
+pub struct Topic<T> {
+    messages: Collection<T>,
+    offsets: Tree,
+    shared: Registries,
+}
+
+impl<T> Clone for Topic<T> {
+    fn clone(&self) -> Self {
+        return Topic {
+            messages: self.messages.clone(),
+            offsets: self.offsets.clone(),
+            shared: self.shared.clone(),
+        };
+    }
+}
+
+fn read_offset(bytes: &[u8], what: &str) -> Result<u64, DBError> {
+    match bytes.try_into() {
+        Err(_) => return Err(DBError::new(DBErrorKind::ReadFailed(format!("offset of {}", what)))),
+        Ok(bytes) => return Ok(u64::from_be_bytes(bytes)),
+    }
+}
+
+impl<T> Topic<T> {
+    pub(super) fn new(messages: Collection<T>, offsets: Tree, shared: Registries) -> Self {
+        return Topic { messages, offsets, shared };
+    }
+
+    /// The names of the trees holding the messages and the subscriber offsets of the
+    /// topic called `name`.
+    pub(super) fn tree_names(name: &str) -> (String, String) {
+        return (format!("__topic/{}", name), format!("__topic_offsets/{}", name));
+    }
+
+    /// Appends `message`, returning its offset.
+    pub fn publish(&self, message: T) -> Result<u64, DBError>
+    where
+        T: Serialize + 'static,
+    {
+        // offsets are handed out before the message is stored, so publishes take turns
+        // to keep a subscriber from committing past one that has not landed yet
+        let _turn = self.shared.publishing.lock().unwrap();
+        return self.messages.insert_auto(message);
+    }
+
+    /// Up to `max` messages published after `offset`, oldest first, with their offsets.
+    pub fn read(&self, offset: u64, max: usize) -> Result<Vec<(u64, T)>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        let start = Bound::Excluded(offset.to_key().as_ref().to_vec());
+        let mut messages = Vec::new();
+        for entry in self.messages.tree.range((start, Bound::Unbounded)).take(max) {
+            let (key, value) = entry?;
+            messages.push((read_offset(&key, self.messages.name())?, self.messages.decode(&key, &value)?));
+        }
+        return Ok(messages);
+    }
+
+    /// Up to `max` messages `subscriber` has not committed yet, oldest first. Reading
+    /// does not move it on, `commit` does, so messages are seen again until committed.
+    pub fn poll(&self, subscriber: &str, max: usize) -> Result<Vec<(u64, T)>, DBError>
+    where
+        T: DeserializeOwned,
+    {
+        return self.read(self.offset(subscriber)?, max);
+    }
+
+    /// Records that `subscriber` is done with every message up to `offset`. An offset
+    /// lower than the current one rewinds it, to read messages again.
+    pub fn commit(&self, subscriber: &str, offset: u64) -> Result<(), DBError> {
+        self.shared.writable()?;
+        self.offsets.insert(subscriber, &offset.to_be_bytes())?;
+        return Ok(());
+    }
+
+    /// The last offset `subscriber` committed, 0 if it never did.
+    pub fn offset(&self, subscriber: &str) -> Result<u64, DBError> {
+        match self.offsets.get(subscriber)? {
+            None => return Ok(0),
+            Some(bytes) => return read_offset(&bytes, subscriber),
+        }
+    }
+
+    /// How many messages the topic holds.
+    pub fn len(&self) -> Result<usize, DBError> {
+        return self.messages.count();
+    }
+
+    pub fn is_empty(&self) -> Result<bool, DBError> {
+        return Ok(self.len()? == 0);
+    }
+}
@@ -0,0 +1,31 @@
+use super::DBError;
+
+/// Runs `op` and, with the `log` feature on, logs it under the `rustpm_orm` target with
+/// the collection, key, how long it took and how it went. Without the feature this is
+/// just the call.
+#[cfg(feature = "log")]
+pub(super) fn traced<R>(op: &str, collection: &str, key: &[u8], f: impl FnOnce() -> Result<R, DBError>) -> Result<R, DBError> {
+    let started = std::time::Instant::now();
+    let result = f();
+    let duration = started.elapsed();
+    let key = String::from_utf8_lossy(key);
+    match &result {
+        Err(err) => log::debug!(
+            target: "rustpm_orm",
+            "{} collection={} key={} duration={:?} result=error error={}",
+            op, collection, key, duration, err
+        ),
+        Ok(_) => log::debug!(
+            target: "rustpm_orm",
+            "{} collection={} key={} duration={:?} result=ok",
+            op, collection, key, duration
+        ),
+    }
+    return result;
+}
+
+#[cfg(not(feature = "log"))]
+#[inline(always)]
+pub(super) fn traced<R>(_op: &str, _collection: &str, _key: &[u8], f: impl FnOnce() -> Result<R, DBError>) -> Result<R, DBError> {
+    return f();
+}
@@ -0,0 +1,203 @@
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sled::transaction::{ConflictableTransactionError, ConflictableTransactionResult, TransactionalTree};
+
+use super::format::{Codec, Format};
+use super::index::{write_entry, IndexEntry};
+use super::limit::{self, Quota, Usage};
+use super::schema::{self, Schema};
+use super::{DBError, DBErrorKind, Id, Model};
+
+pub type TxResult<R> = ConflictableTransactionResult<R, DBError>;
+
+/// Aborts the surrounding transaction, rolling back every write made in it.
+pub fn abort<R>(err: DBError) -> TxResult<R> {
+    return Err(ConflictableTransactionError::Abort(err));
+}
+
+// lets `?` abort a transaction on any `DBError`
+impl From<DBError> for ConflictableTransactionError<DBError> {
+    fn from(err: DBError) -> Self {
+        return ConflictableTransactionError::Abort(err);
+    }
+}
+
+/// The `Model` types a `DBManager::transaction_for` works on: one type, or a tuple of
+/// up to four, e.g. `db.transaction_for::<(User, Order), _>(|tx| ...)`.
+pub trait Models {
+    fn collections() -> Vec<&'static str>;
+}
+
+impl<A: Model> Models for A {
+    fn collections() -> Vec<&'static str> {
+        return vec![A::COLLECTION];
+    }
+}
+
+macro_rules! tuple_models {
+    ($($model:ident),*) => {
+        impl<$($model: Model),*> Models for ($($model,)*) {
+            fn collections() -> Vec<&'static str> {
+                let mut collections = Vec::new();
+                $(if !collections.contains(&$model::COLLECTION) {
+                    collections.push($model::COLLECTION);
+                })*
+                return collections;
+            }
+        }
+    };
+}
+
+tuple_models!(A);
+tuple_models!(A, B);
+tuple_models!(A, B, C);
+tuple_models!(A, B, C, D);
+
+// what a transaction needs to know about each collection taking part
+pub(super) struct TxTarget {
+    pub(super) format: Format,
+    pub(super) schema: Option<Schema>,
+    pub(super) max_record_size: Option<usize>,
+    // position of the collection's version counters among the views
+    pub(super) versions: Option<usize>,
+    // the collection's quota and where its usage count sits among the views
+    pub(super) usage: Option<(Quota, usize)>,
+}
+
+/// The set of collections taking part in a single `DBManager::transaction` call.
+pub struct Transaction<'a> {
+    // full tree names, `prefix` followed by the name the caller uses
+    prefix: &'a str,
+    names: &'a [&'a str],
+    targets: &'a [TxTarget],
+    // the index trees of those collections, their views follow the collection views
+    indexes: &'a [(String, IndexEntry)],
+    views: &'a [TransactionalTree],
+}
+
+impl<'a> Transaction<'a> {
+    pub(super) fn new(
+        prefix: &'a str,
+        names: &'a [&'a str],
+        targets: &'a [TxTarget],
+        indexes: &'a [(String, IndexEntry)],
+        views: &'a [TransactionalTree],
+    ) -> Self {
+        return Transaction {
+            prefix,
+            names,
+            targets,
+            indexes,
+            views,
+        };
+    }
+
+    /// The `T` record under `key`, in the collection `T` is routed to.
+    pub fn find<T: Model + DeserializeOwned>(&self, key: &str) -> TxResult<Option<T>> {
+        return self.collection::<T>(T::COLLECTION)?.get(key.to_string());
+    }
+
+    /// Stores `data` under its key in the collection `T` is routed to.
+    pub fn save<T: Model + Serialize + 'static>(&self, data: T) -> TxResult<()> {
+        return self.collection::<T>(T::COLLECTION)?.upsert(data.key(), data);
+    }
+
+    /// Deletes the `T` record under `key`, returning whether there was one.
+    pub fn remove<T: Model>(&self, key: &str) -> TxResult<bool> {
+        return self.collection::<T>(T::COLLECTION)?.delete(key.to_string());
+    }
+
+    pub fn collection<T>(&self, name: &str) -> TxResult<TxCollection<'_, T>> {
+        let name = format!("{}{}", self.prefix, name);
+        let position = match self.names.iter().position(|n| *n == name) {
+            None => {
+                return abort(DBError::new(DBErrorKind::NotFound(format!(
+                    "collection {} is not part of this transaction",
+                    name
+                ))))
+            }
+            Some(position) => position,
+        };
+
+        let target = &self.targets[position];
+        let indexes = self
+            .indexes
+            .iter()
+            .enumerate()
+            .filter(|(_, (collection, _))| *collection == name)
+            .map(|(i, (_, entry))| (entry, &self.views[self.names.len() + i]))
+            .collect();
+
+        return Ok(TxCollection {
+            name: self.names[position],
+            tree: &self.views[position],
+            indexes,
+            versions: target.versions.map(|i| &self.views[i]),
+            usage: target.usage.map(|(quota, at)| Usage::new(self.names[position], quota, &self.views[at])),
+            target,
+            _marker: PhantomData,
+        });
+    }
+}
+
+/// A typed view of one collection inside a transaction.
+pub struct TxCollection<'a, T> {
+    name: &'a str,
+    tree: &'a TransactionalTree,
+    indexes: Vec<(&'a IndexEntry, &'a TransactionalTree)>,
+    versions: Option<&'a TransactionalTree>,
+    usage: Option<Usage<'a>>,
+    target: &'a TxTarget,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> TxCollection<'_, T> {
+    pub fn insert(&self, data: T) -> TxResult<String>
+    where
+        T: Serialize + Id + 'static,
+    {
+        let id = data.gen_id();
+        self.upsert(id.clone(), data)?;
+        return Ok(id);
+    }
+
+    pub fn upsert(&self, id: String, data: T) -> TxResult<()>
+    where
+        T: Serialize + 'static,
+    {
+        let encoded = self.target.format.encode(&data).map_err(ConflictableTransactionError::Abort)?;
+        let serialized_data = schema::stamp(self.target.schema.as_ref(), encoded);
+        limit::check_size(self.name, &serialized_data, self.target.max_record_size).map_err(ConflictableTransactionError::Abort)?;
+        let keys: Vec<Vec<Vec<u8>>> = self.indexes.iter().map(|(entry, _)| entry.keys(&data)).collect();
+        self.write(&id, Some(serialized_data), &keys)?;
+        return Ok(());
+    }
+
+    pub fn get(&self, id: String) -> TxResult<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        match self.tree.get(id.as_str())? {
+            None => return Ok(None),
+            Some(bytes) => {
+                let data = schema::current(self.target.schema.as_ref(), id.as_bytes(), &bytes)
+                    .and_then(|bytes| self.target.format.decode(&bytes))
+                    .map_err(ConflictableTransactionError::Abort)?;
+                return Ok(Some(data));
+            }
+        }
+    }
+
+    pub fn delete(&self, id: String) -> TxResult<bool> {
+        let keys = vec![Vec::new(); self.indexes.len()];
+        return Ok(self.write(&id, None, &keys)?.is_some());
+    }
+
+    fn write(&self, id: &str, value: Option<Vec<u8>>, keys: &[Vec<Vec<u8>>]) -> TxResult<Option<sled::IVec>> {
+        let previous = write_entry(self.tree, &self.indexes, self.versions, id.as_bytes(), value.clone(), keys)?;
+        limit::track(self.usage.as_ref(), id.as_bytes(), previous.as_deref(), value.as_deref())?;
+        return Ok(previous);
+    }
+}
@@ -0,0 +1,109 @@
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sled::{IVec, Tree};
+
+use super::{DBError, DBManager};
+
+// `e` + expiry + id keeps entries in expiry order for sweeping,
+// `i` + id -> expiry finds a record's entry again when it is replaced or deleted
+const BY_EXPIRY: u8 = b'e';
+const BY_ID: u8 = b'i';
+
+pub(super) fn now_millis() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0);
+}
+
+fn expiry_key(at: u64, id: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(id.len() + 9);
+    key.push(BY_EXPIRY);
+    key.extend_from_slice(&at.to_be_bytes());
+    key.extend_from_slice(id);
+    return key;
+}
+
+fn id_key(id: &[u8]) -> Vec<u8> {
+    return [&[BY_ID], id].concat();
+}
+
+/// Makes `id` expire `ttl` from now, replacing any earlier expiry.
+pub(super) fn set(tree: &Tree, id: &[u8], ttl: Duration) -> Result<(), DBError> {
+    clear(tree, id)?;
+    let at = now_millis().saturating_add(ttl.as_millis() as u64);
+    tree.insert(expiry_key(at, id), &[])?;
+    tree.insert(id_key(id), &at.to_be_bytes())?;
+    return Ok(());
+}
+
+pub(super) fn clear(tree: &Tree, id: &[u8]) -> Result<(), DBError> {
+    if let Some(at) = tree.remove(id_key(id))? {
+        if let Ok(at) = at.as_ref().try_into() {
+            tree.remove(expiry_key(u64::from_be_bytes(at), id))?;
+        }
+    }
+    return Ok(());
+}
+
+/// Ids whose expiry is at or before `now`.
+pub(super) fn due(tree: &Tree, now: u64) -> Result<Vec<IVec>, DBError> {
+    let end = expiry_key(now.saturating_add(1), b"");
+    let mut ids = Vec::new();
+    for key in tree.range(vec![BY_EXPIRY]..end).keys() {
+        let key = key?;
+        ids.push(IVec::from(&key[9..]));
+    }
+    return Ok(ids);
+}
+
+/// How long until `id` expires, `None` when it has no expiry.
+pub(super) fn remaining(tree: &Tree, id: &[u8]) -> Result<Option<Duration>, DBError> {
+    let at = match tree.get(id_key(id))? {
+        None => return Ok(None),
+        Some(at) => match at.as_ref().try_into() {
+            Err(_) => return Ok(None),
+            Ok(at) => u64::from_be_bytes(at),
+        },
+    };
+    return Ok(Some(Duration::from_millis(at.saturating_sub(now_millis()))));
+}
+
+/// A background thread purging expired records every `interval`, stopped when dropped.
+pub struct Sweeper {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Sweeper {
+    pub(super) fn start(db: DBManager, interval: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = thread::Builder::new()
+            .name("rustpm-ttl-sweeper".to_string())
+            .spawn(move || loop {
+                match stopped.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => {
+                        // a failed sweep is simply retried on the next tick
+                        let _ = db.purge_expired();
+                    }
+                    _ => return,
+                }
+            })
+            .expect("failed to spawn ttl sweeper");
+        return Sweeper {
+            stop: Some(stop),
+            handle: Some(handle),
+        };
+    }
+}
+
+impl Drop for Sweeper {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
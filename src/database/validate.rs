@@ -0,0 +1,81 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use super::{DBError, DBErrorKind};
+
+/// Rules a record type checks itself against before it is written, once registered
+/// with `Collection::register_validation`. A failed check stops the write with
+/// `DBErrorKind::Validation`, carrying every problem found.
+pub trait Validate {
+    fn validate(&self) -> Result<(), Vec<ValidationError>>;
+}
+
+/// One field of a record that failed `Validate::validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    pub field: String,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        return ValidationError {
+            field: field.into(),
+            message: message.into(),
+        };
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}: {}", self.field, self.message);
+    }
+}
+
+#[derive(Clone)]
+struct Entry {
+    type_id: TypeId,
+    // a `fn(&T) -> Result<(), Vec<ValidationError>>` for the registered type
+    validate: Arc<dyn Any + Send + Sync>,
+}
+
+/// The record types each collection validates its writes as.
+#[derive(Clone, Default)]
+pub(super) struct ValidatorRegistry {
+    inner: Arc<RwLock<HashMap<String, Entry>>>,
+}
+
+impl fmt::Debug for ValidatorRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str("ValidatorRegistry");
+    }
+}
+
+type Validator<T> = fn(&T) -> Result<(), Vec<ValidationError>>;
+
+impl ValidatorRegistry {
+    pub(super) fn register<T: Validate + 'static>(&self, collection: &str) {
+        let validate: Validator<T> = T::validate;
+        let entry = Entry {
+            type_id: TypeId::of::<T>(),
+            validate: Arc::new(validate),
+        };
+        self.inner.write().unwrap().insert(collection.to_string(), entry);
+    }
+
+    pub(super) fn forget(&self, prefix: &str) {
+        self.inner.write().unwrap().retain(|collection, _| !collection.starts_with(prefix));
+    }
+
+    /// Checks `data` when `collection` validates records of its type.
+    pub(super) fn check<T: 'static>(&self, collection: &str, data: &T) -> Result<(), DBError> {
+        let entry = match self.inner.read().unwrap().get(collection) {
+            Some(entry) if entry.type_id == TypeId::of::<T>() => entry.clone(),
+            _ => return Ok(()),
+        };
+        let validate = entry.validate.downcast_ref::<Validator<T>>().unwrap();
+        return validate(data).map_err(|errors| DBError::new(DBErrorKind::Validation(errors)));
+    }
+}
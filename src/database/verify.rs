@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use sled::{Db, Tree};
+
+use super::DBError;
+
+type Check = Arc<dyn Fn(&[u8], &[u8]) -> Result<(), DBError> + Send + Sync>;
+
+/// The record decoders `DBManager::verify` checks each collection with, see
+/// `Collection::register_type`.
+#[derive(Clone, Default)]
+pub(super) struct CheckRegistry {
+    inner: Arc<RwLock<HashMap<String, Check>>>,
+}
+
+impl fmt::Debug for CheckRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str("CheckRegistry");
+    }
+}
+
+impl CheckRegistry {
+    pub(super) fn register<F>(&self, collection: &str, check: F)
+    where
+        F: Fn(&[u8], &[u8]) -> Result<(), DBError> + Send + Sync + 'static,
+    {
+        self.inner.write().unwrap().insert(collection.to_string(), Arc::new(check));
+    }
+
+    pub(super) fn forget(&self, prefix: &str) {
+        self.inner.write().unwrap().retain(|collection, _| !collection.starts_with(prefix));
+    }
+
+    fn get(&self, collection: &str) -> Option<Check> {
+        return self.inner.read().unwrap().get(collection).cloned();
+    }
+}
+
+/// One bad entry found by `DBManager::verify`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub tree: String,
+    pub key: String,
+    pub reason: String,
+}
+
+/// What `DBManager::verify` found.
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// Records walked across every collection.
+    pub records: usize,
+    /// Collections without a registered type, whose records could only be read, not decoded.
+    pub unchecked: Vec<String>,
+    /// Records that failed to read or decode.
+    pub corrupt: Vec<Problem>,
+    /// Index, expiry and version entries left behind for records that no longer exist.
+    pub orphaned: Vec<Problem>,
+    /// sled's crc32 over every key and value, comparable against an earlier run.
+    pub checksum: u32,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        return self.corrupt.is_empty() && self.orphaned.is_empty();
+    }
+}
+
+fn problem(tree: &str, key: &[u8], reason: impl Into<String>) -> Problem {
+    return Problem {
+        tree: tree.to_string(),
+        key: String::from_utf8_lossy(key).into_owned(),
+        reason: reason.into(),
+    };
+}
+
+// which side tree a name belongs to, and the collection it serves
+fn side_tree(name: &str) -> Option<(&'static str, &str)> {
+    if let Some(rest) = name.strip_prefix("__index/") {
+        return rest.rsplit_once('/').map(|(collection, _)| ("index", collection));
+    }
+    for (kind, prefix) in [("ttl", "__ttl/"), ("version", "__version/")] {
+        if let Some(collection) = name.strip_prefix(prefix) {
+            return Some((kind, collection));
+        }
+    }
+    return None;
+}
+
+// the record ids a side tree entry points at; index entries carry them in the value,
+// expiry entries by id and reverse index entries after a one byte tag
+fn referenced_ids(kind: &str, key: &[u8], value: &[u8]) -> Vec<Vec<u8>> {
+    match (kind, key.first()) {
+        ("index", Some(b'f')) => return vec![value.to_vec()],
+        ("index", Some(b'r')) | ("ttl", Some(b'i')) => return vec![key[1..].to_vec()],
+        ("version", _) => return vec![key.to_vec()],
+        _ => return Vec::new(),
+    }
+}
+
+fn check_records(name: &str, tree: &Tree, check: Option<&Check>, report: &mut VerifyReport) {
+    for entry in tree.iter() {
+        let (key, value) = match entry {
+            Err(err) => {
+                report.corrupt.push(problem(name, b"", err.to_string()));
+                return;
+            }
+            Ok(entry) => entry,
+        };
+        report.records += 1;
+        if let Some(Err(err)) = check.map(|check| check(&key, &value)) {
+            report.corrupt.push(problem(name, &key, err.to_string()));
+        }
+    }
+}
+
+fn check_side_tree(conn: &Db, name: &str, kind: &str, collection: &str, report: &mut VerifyReport) -> Result<(), DBError> {
+    let records = conn.open_tree(collection)?;
+    for entry in conn.open_tree(name)?.iter() {
+        let (key, value) = match entry {
+            Err(err) => {
+                report.corrupt.push(problem(name, b"", err.to_string()));
+                return Ok(());
+            }
+            Ok(entry) => entry,
+        };
+        for id in referenced_ids(kind, &key, &value) {
+            if !records.contains_key(&id)? {
+                let reason = format!("{} entry for missing record {}", kind, String::from_utf8_lossy(&id));
+                report.orphaned.push(problem(name, &key, reason));
+            }
+        }
+    }
+    return Ok(());
+}
+
+pub(super) fn run(conn: &Db, checks: &CheckRegistry) -> Result<VerifyReport, DBError> {
+    let mut report = VerifyReport::default();
+    for name in conn.tree_names() {
+        let name = String::from_utf8_lossy(&name).into_owned();
+        if let Some((kind, collection)) = side_tree(&name) {
+            check_side_tree(conn, &name, kind, collection, &mut report)?;
+            continue;
+        }
+        if name.starts_with("__") {
+            continue;
+        }
+
+        let check = checks.get(&name);
+        if check.is_none() {
+            report.unchecked.push(name.clone());
+        }
+        check_records(&name, &conn.open_tree(&name)?, check.as_ref(), &mut report);
+    }
+    report.checksum = conn.checksum()?;
+    return Ok(report);
+}
@@ -0,0 +1,33 @@
+use sled::transaction::TransactionalTree;
+use sled::IVec;
+
+use super::transaction::TxResult;
+use super::{DBError, DBErrorKind};
+
+// records written before tracking started have no counter yet and count as version 0
+pub(super) fn parse(bytes: Option<IVec>) -> u64 {
+    return bytes
+        .and_then(|bytes| bytes.as_ref().try_into().ok())
+        .map(u64::from_be_bytes)
+        .unwrap_or(0);
+}
+
+/// Moves the counter of `id` along with a write, or drops it with the record.
+pub(super) fn bump(versions: &TransactionalTree, id: &[u8], deleting: bool) -> TxResult<u64> {
+    if deleting {
+        versions.remove(id)?;
+        return Ok(0);
+    }
+    let next = parse(versions.get(id)?) + 1;
+    versions.insert(id, &next.to_be_bytes())?;
+    return Ok(next);
+}
+
+pub(super) fn conflict(id: &[u8], expected: u64, found: u64) -> DBError {
+    return DBError::new(DBErrorKind::Conflict(format!(
+        "{} is at version {}, expected {}",
+        String::from_utf8_lossy(id),
+        found,
+        expected
+    )));
+}
@@ -0,0 +1,1108 @@
+//! A small, dependency free JSON implementation on top of serde.
+//!
+//! It covers what the crate needs for storing and exporting records: a `Value`
+//! tree, conversion between `Value` and any serde type, and a parser/printer.
+//! The serde data model is mapped the same way serde_json maps it.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(map) => return map.get(key),
+            _ => return None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => return Some(s),
+            _ => return None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(n) => return Some(*n as f64),
+            Value::UInt(n) => return Some(*n as f64),
+            Value::Float(n) => return Some(*n),
+            _ => return None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        return matches!(self, Value::Null);
+    }
+
+    /// Applies `patch` as a JSON merge patch (RFC 7396): object fields are merged
+    /// recursively, `null` removes a field and anything else replaces the value.
+    pub fn merge(&mut self, patch: Value) {
+        let fields = match patch {
+            Value::Object(fields) => fields,
+            patch => {
+                *self = patch;
+                return;
+            }
+        };
+        if !matches!(self, Value::Object(_)) {
+            *self = Value::Object(BTreeMap::new());
+        }
+        if let Value::Object(target) = self {
+            for (key, value) in fields {
+                if value.is_null() {
+                    target.remove(&key);
+                } else {
+                    target.entry(key).or_insert(Value::Null).merge(value);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str(&self.0);
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        return Error(msg.to_string());
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        return Error(msg.to_string());
+    }
+}
+
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<Value, Error> {
+    return value.serialize(ValueSerializer);
+}
+
+pub fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, Error> {
+    return T::deserialize(value);
+}
+
+pub fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String, Error> {
+    let mut out = String::new();
+    write_value(&mut out, &to_value(value)?, None, 0);
+    return Ok(out);
+}
+
+pub fn to_string_pretty<T: Serialize + ?Sized>(value: &T) -> Result<String, Error> {
+    let mut out = String::new();
+    write_value(&mut out, &to_value(value)?, Some(2), 0);
+    return Ok(out);
+}
+
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+    return Ok(to_string(value)?.into_bytes());
+}
+
+pub fn from_str<T: DeserializeOwned>(input: &str) -> Result<T, Error> {
+    return from_value(parse(input)?);
+}
+
+pub fn from_slice<T: DeserializeOwned>(input: &[u8]) -> Result<T, Error> {
+    match std::str::from_utf8(input) {
+        Err(err) => return Err(Error(format!("invalid utf-8: {}", err))),
+        Ok(input) => return from_str(input),
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = String::new();
+        write_value(&mut out, self, if f.alternate() { Some(2) } else { None }, 0);
+        return f.write_str(&out);
+    }
+}
+
+// ---- printing ----
+
+fn write_value(out: &mut String, value: &Value, indent: Option<usize>, depth: usize) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Int(n) => write!(out, "{}", n).unwrap(),
+        Value::UInt(n) => write!(out, "{}", n).unwrap(),
+        Value::Float(n) => {
+            if !n.is_finite() {
+                out.push_str("null");
+            } else {
+                let start = out.len();
+                write!(out, "{}", n).unwrap();
+                if !out[start..].contains(['.', 'e', 'E']) {
+                    out.push_str(".0");
+                }
+            }
+        }
+        Value::String(s) => write_string(out, s),
+        Value::Array(items) => {
+            if items.is_empty() {
+                out.push_str("[]");
+                return;
+            }
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, indent, depth + 1);
+                write_value(out, item, indent, depth + 1);
+            }
+            newline(out, indent, depth);
+            out.push(']');
+        }
+        Value::Object(map) => {
+            if map.is_empty() {
+                out.push_str("{}");
+                return;
+            }
+            out.push('{');
+            for (i, (key, item)) in map.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, indent, depth + 1);
+                write_string(out, key);
+                out.push(':');
+                if indent.is_some() {
+                    out.push(' ');
+                }
+                write_value(out, item, indent, depth + 1);
+            }
+            newline(out, indent, depth);
+            out.push('}');
+        }
+    }
+}
+
+fn newline(out: &mut String, indent: Option<usize>, depth: usize) {
+    if let Some(width) = indent {
+        out.push('\n');
+        for _ in 0..width * depth {
+            out.push(' ');
+        }
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{08}' => out.push_str("\\b"),
+            '\u{0c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// ---- parsing ----
+
+const MAX_DEPTH: usize = 128;
+
+pub fn parse(input: &str) -> Result<Value, Error> {
+    let mut parser = Parser {
+        bytes: input.as_bytes(),
+        pos: 0,
+    };
+    let value = parser.value(0)?;
+    parser.whitespace();
+    if parser.pos != parser.bytes.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    return Ok(value);
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, msg: &str) -> Error {
+        return Error(format!("{} at byte {}", msg, self.pos));
+    }
+
+    fn whitespace(&mut self) {
+        while let Some(b' ' | b'\n' | b'\r' | b'\t') = self.bytes.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        return self.bytes.get(self.pos).copied();
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), Error> {
+        if self.peek() != Some(byte) {
+            return Err(self.error(&format!("expected '{}'", byte as char)));
+        }
+        self.pos += 1;
+        return Ok(());
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, Error> {
+        if !self.bytes[self.pos..].starts_with(word.as_bytes()) {
+            return Err(self.error("unexpected token"));
+        }
+        self.pos += word.len();
+        return Ok(value);
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, Error> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        self.whitespace();
+        match self.peek() {
+            None => return Err(self.error("unexpected end of input")),
+            Some(b'n') => return self.literal("null", Value::Null),
+            Some(b't') => return self.literal("true", Value::Bool(true)),
+            Some(b'f') => return self.literal("false", Value::Bool(false)),
+            Some(b'"') => return Ok(Value::String(self.string()?)),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Value::Array(items));
+                        }
+                        _ => return Err(self.error("expected ',' or ']'")),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut map = BTreeMap::new();
+                self.whitespace();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Value::Object(map));
+                }
+                loop {
+                    self.whitespace();
+                    let key = self.string()?;
+                    self.whitespace();
+                    self.expect(b':')?;
+                    let item = self.value(depth + 1)?;
+                    map.insert(key, item);
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Value::Object(map));
+                        }
+                        _ => return Err(self.error("expected ',' or '}'")),
+                    }
+                }
+            }
+            Some(b'-' | b'0'..=b'9') => return self.number(),
+            Some(_) => return Err(self.error("unexpected character")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, Error> {
+        let start = self.pos;
+        let mut float = false;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        while let Some(byte) = self.peek() {
+            match byte {
+                b'0'..=b'9' => {}
+                b'.' | b'e' | b'E' | b'+' | b'-' => float = true,
+                _ => break,
+            }
+            self.pos += 1;
+        }
+
+        let text = std::str::from_utf8(&self.bytes[start..self.pos]).unwrap();
+        if !float {
+            if let Ok(n) = text.parse::<u64>() {
+                return Ok(Value::UInt(n));
+            }
+            if let Ok(n) = text.parse::<i64>() {
+                return Ok(Value::Int(n));
+            }
+        }
+        match text.parse::<f64>() {
+            Err(_) => return Err(Error(format!("invalid number {} at byte {}", text, start))),
+            Ok(n) => return Ok(Value::Float(n)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.expect(b'"')?;
+        let mut out = String::new();
+        loop {
+            let start = self.pos;
+            while let Some(byte) = self.peek() {
+                if byte == b'"' || byte == b'\\' || byte < 0x20 {
+                    break;
+                }
+                self.pos += 1;
+            }
+            // the input is a &str and we only stop on ascii, so this slice is valid utf-8
+            out.push_str(std::str::from_utf8(&self.bytes[start..self.pos]).unwrap());
+
+            match self.peek() {
+                None => return Err(self.error("unterminated string")),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(out);
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = match self.peek() {
+                        None => return Err(self.error("unterminated string")),
+                        Some(byte) => byte,
+                    };
+                    self.pos += 1;
+                    match escaped {
+                        b'"' => out.push('"'),
+                        b'\\' => out.push('\\'),
+                        b'/' => out.push('/'),
+                        b'b' => out.push('\u{08}'),
+                        b'f' => out.push('\u{0c}'),
+                        b'n' => out.push('\n'),
+                        b'r' => out.push('\r'),
+                        b't' => out.push('\t'),
+                        b'u' => out.push(self.unicode_escape()?),
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                }
+                Some(_) => return Err(self.error("control character in string")),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, Error> {
+        let digits = match self.bytes.get(self.pos..self.pos + 4) {
+            None => return Err(self.error("truncated unicode escape")),
+            Some(digits) => digits,
+        };
+        let text = std::str::from_utf8(digits).map_err(|_| self.error("invalid unicode escape"))?;
+        let code = u32::from_str_radix(text, 16).map_err(|_| self.error("invalid unicode escape"))?;
+        self.pos += 4;
+        return Ok(code);
+    }
+
+    fn unicode_escape(&mut self) -> Result<char, Error> {
+        let first = self.hex4()?;
+        if (0xd800..0xdc00).contains(&first) {
+            // a high surrogate has to be followed by an escaped low surrogate
+            if !self.bytes[self.pos..].starts_with(b"\\u") {
+                return Err(self.error("unpaired surrogate"));
+            }
+            self.pos += 2;
+            let second = self.hex4()?;
+            if !(0xdc00..0xe000).contains(&second) {
+                return Err(self.error("unpaired surrogate"));
+            }
+            let code = 0x10000 + ((first - 0xd800) << 10) + (second - 0xdc00);
+            return char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"));
+        }
+        return char::from_u32(first).ok_or_else(|| self.error("invalid unicode escape"));
+    }
+}
+
+// ---- serializing into a Value ----
+
+struct ValueSerializer;
+
+pub struct SerializeVec {
+    items: Vec<Value>,
+    variant: Option<&'static str>,
+}
+
+pub struct SerializeMap {
+    map: BTreeMap<String, Value>,
+    next_key: Option<String>,
+    variant: Option<&'static str>,
+}
+
+fn tagged(variant: &'static str, value: Value) -> Value {
+    let mut map = BTreeMap::new();
+    map.insert(variant.to_string(), value);
+    return Value::Object(map);
+}
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = Error;
+    type SerializeSeq = SerializeVec;
+    type SerializeTuple = SerializeVec;
+    type SerializeTupleStruct = SerializeVec;
+    type SerializeTupleVariant = SerializeVec;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeMap;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, Error> {
+        return Ok(Value::Bool(v));
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, Error> {
+        return Ok(Value::Int(v as i64));
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, Error> {
+        return Ok(Value::Int(v as i64));
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, Error> {
+        return Ok(Value::Int(v as i64));
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, Error> {
+        return Ok(Value::Int(v));
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, Error> {
+        return Ok(Value::UInt(v as u64));
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, Error> {
+        return Ok(Value::UInt(v as u64));
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, Error> {
+        return Ok(Value::UInt(v as u64));
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, Error> {
+        return Ok(Value::UInt(v));
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, Error> {
+        return Ok(Value::Float(v as f64));
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, Error> {
+        return Ok(Value::Float(v));
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, Error> {
+        return Ok(Value::String(v.to_string()));
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, Error> {
+        return Ok(Value::String(v.to_string()));
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, Error> {
+        return Ok(Value::Array(v.iter().map(|b| Value::UInt(*b as u64)).collect()));
+    }
+
+    fn serialize_none(self) -> Result<Value, Error> {
+        return Ok(Value::Null);
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value, Error> {
+        return value.serialize(self);
+    }
+
+    fn serialize_unit(self) -> Result<Value, Error> {
+        return Ok(Value::Null);
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, Error> {
+        return Ok(Value::Null);
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<Value, Error> {
+        return Ok(Value::String(variant.to_string()));
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<Value, Error> {
+        return value.serialize(self);
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value, Error> {
+        return Ok(tagged(variant, value.serialize(ValueSerializer)?));
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeVec, Error> {
+        return Ok(SerializeVec {
+            items: Vec::with_capacity(len.unwrap_or(0)),
+            variant: None,
+        });
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeVec, Error> {
+        return self.serialize_seq(Some(len));
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerializeVec, Error> {
+        return self.serialize_seq(Some(len));
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVec, Error> {
+        return Ok(SerializeVec {
+            items: Vec::with_capacity(len),
+            variant: Some(variant),
+        });
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeMap, Error> {
+        return Ok(SerializeMap {
+            map: BTreeMap::new(),
+            next_key: None,
+            variant: None,
+        });
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeMap, Error> {
+        return self.serialize_map(Some(len));
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeMap, Error> {
+        return Ok(SerializeMap {
+            map: BTreeMap::new(),
+            next_key: None,
+            variant: Some(variant),
+        });
+    }
+}
+
+impl SerializeVec {
+    fn finish(self) -> Value {
+        let array = Value::Array(self.items);
+        match self.variant {
+            None => return array,
+            Some(variant) => return tagged(variant, array),
+        }
+    }
+}
+
+impl ser::SerializeSeq for SerializeVec {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.items.push(value.serialize(ValueSerializer)?);
+        return Ok(());
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        return Ok(self.finish());
+    }
+}
+
+impl ser::SerializeTuple for SerializeVec {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        return ser::SerializeSeq::serialize_element(self, value);
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        return Ok(self.finish());
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        return ser::SerializeSeq::serialize_element(self, value);
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        return Ok(self.finish());
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVec {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        return ser::SerializeSeq::serialize_element(self, value);
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        return Ok(self.finish());
+    }
+}
+
+impl SerializeMap {
+    fn finish(self) -> Value {
+        let object = Value::Object(self.map);
+        match self.variant {
+            None => return object,
+            Some(variant) => return tagged(variant, object),
+        }
+    }
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        // json keys are strings, scalars are stringified like serde_json does
+        let key = match key.serialize(ValueSerializer)? {
+            Value::String(s) => s,
+            Value::Int(n) => n.to_string(),
+            Value::UInt(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            _ => return Err(Error("map keys must be strings or numbers".to_string())),
+        };
+        self.next_key = Some(key);
+        return Ok(());
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = match self.next_key.take() {
+            None => return Err(Error("map value without a key".to_string())),
+            Some(key) => key,
+        };
+        self.map.insert(key, value.serialize(ValueSerializer)?);
+        return Ok(());
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        return Ok(self.finish());
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        self.map.insert(key.to_string(), value.serialize(ValueSerializer)?);
+        return Ok(());
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        return Ok(self.finish());
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeMap {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        return ser::SerializeStruct::serialize_field(self, key, value);
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        return Ok(self.finish());
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use ser::{SerializeMap as _, SerializeSeq as _};
+
+        match self {
+            Value::Null => return serializer.serialize_unit(),
+            Value::Bool(b) => return serializer.serialize_bool(*b),
+            Value::Int(n) => return serializer.serialize_i64(*n),
+            Value::UInt(n) => return serializer.serialize_u64(*n),
+            Value::Float(n) => return serializer.serialize_f64(*n),
+            Value::String(s) => return serializer.serialize_str(s),
+            Value::Array(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(item)?;
+                }
+                return seq.end();
+            }
+            Value::Object(map) => {
+                let mut out = serializer.serialize_map(Some(map.len()))?;
+                for (key, item) in map {
+                    out.serialize_entry(key, item)?;
+                }
+                return out.end();
+            }
+        }
+    }
+}
+
+// ---- deserializing out of a Value ----
+
+impl<'de> de::Deserialize<'de> for Value {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Value, D::Error> {
+        struct ValueVisitor;
+
+        impl<'de> Visitor<'de> for ValueVisitor {
+            type Value = Value;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                return f.write_str("any json value");
+            }
+
+            fn visit_bool<E>(self, v: bool) -> Result<Value, E> {
+                return Ok(Value::Bool(v));
+            }
+
+            fn visit_i64<E>(self, v: i64) -> Result<Value, E> {
+                return Ok(Value::Int(v));
+            }
+
+            fn visit_u64<E>(self, v: u64) -> Result<Value, E> {
+                return Ok(Value::UInt(v));
+            }
+
+            fn visit_f64<E>(self, v: f64) -> Result<Value, E> {
+                return Ok(Value::Float(v));
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Value, E> {
+                return Ok(Value::String(v.to_string()));
+            }
+
+            fn visit_string<E>(self, v: String) -> Result<Value, E> {
+                return Ok(Value::String(v));
+            }
+
+            fn visit_none<E>(self) -> Result<Value, E> {
+                return Ok(Value::Null);
+            }
+
+            fn visit_some<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+                return de::Deserialize::deserialize(deserializer);
+            }
+
+            fn visit_unit<E>(self) -> Result<Value, E> {
+                return Ok(Value::Null);
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+                let mut items = Vec::new();
+                while let Some(item) = seq.next_element()? {
+                    items.push(item);
+                }
+                return Ok(Value::Array(items));
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, mut access: A) -> Result<Value, A::Error> {
+                let mut map = BTreeMap::new();
+                while let Some((key, item)) = access.next_entry::<String, Value>()? {
+                    map.insert(key, item);
+                }
+                return Ok(Value::Object(map));
+            }
+        }
+
+        return deserializer.deserialize_any(ValueVisitor);
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Value;
+
+    fn into_deserializer(self) -> Value {
+        return self;
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Value::Null => return visitor.visit_unit(),
+            Value::Bool(b) => return visitor.visit_bool(b),
+            Value::Int(n) => return visitor.visit_i64(n),
+            Value::UInt(n) => return visitor.visit_u64(n),
+            Value::Float(n) => return visitor.visit_f64(n),
+            Value::String(s) => return visitor.visit_string(s),
+            Value::Array(items) => return visitor.visit_seq(de::value::SeqDeserializer::new(items.into_iter())),
+            Value::Object(map) => {
+                let entries = map.into_iter().map(|(key, item)| (Key(key), item));
+                return visitor.visit_map(de::value::MapDeserializer::new(entries));
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Value::Null => return visitor.visit_none(),
+            other => return visitor.visit_some(other),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        return visitor.visit_newtype_struct(self);
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self {
+            Value::String(variant) => return visitor.visit_enum(variant.into_deserializer()),
+            Value::Object(map) if map.len() == 1 => {
+                let (variant, value) = map.into_iter().next().unwrap();
+                return visitor.visit_enum(Enum { variant, value });
+            }
+            _ => return Err(Error("expected a string or single-key object for an enum".to_string())),
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Value::String(s) => return visitor.visit_byte_buf(s.into_bytes()),
+            other => return other.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        return self.deserialize_bytes(visitor);
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct Enum {
+    variant: String,
+    value: Value,
+}
+
+impl<'de> de::EnumAccess<'de> for Enum {
+    type Error = Error;
+    type Variant = Value;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Value), Error> {
+        let variant = seed.deserialize(Key(self.variant))?;
+        return Ok((variant, self.value));
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Value {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        match self {
+            Value::Null => return Ok(()),
+            _ => return Err(Error("expected a unit variant".to_string())),
+        }
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        return seed.deserialize(self);
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        return de::Deserializer::deserialize_seq(self, visitor);
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        return de::Deserializer::deserialize_map(self, visitor);
+    }
+}
+
+// object keys are always strings, but maps keyed by numbers need them parsed back
+struct Key(String);
+
+impl<'de> IntoDeserializer<'de, Error> for Key {
+    type Deserializer = Key;
+
+    fn into_deserializer(self) -> Key {
+        return self;
+    }
+}
+
+macro_rules! deserialize_parsed_key {
+    ($($method:ident => $visit:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                match self.0.parse() {
+                    Err(_) => return Err(Error(format!("invalid numeric key {}", self.0))),
+                    Ok(n) => return visitor.$visit(n),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Key {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        return visitor.visit_string(self.0);
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        return visitor.visit_newtype_struct(self);
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        return visitor.visit_enum(self.0.into_deserializer());
+    }
+
+    deserialize_parsed_key! {
+        deserialize_i8 => visit_i8, deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32, deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8, deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32, deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32, deserialize_f64 => visit_f64,
+        deserialize_bool => visit_bool
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf option unit unit_struct
+        seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_derive::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Status {
+        Open,
+        Closed { reason: String },
+        Moved(u32),
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Ticket {
+        id: u64,
+        title: String,
+        price: f64,
+        tags: Vec<String>,
+        owner: Option<String>,
+        status: Status,
+        votes: HashMap<u32, i8>,
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut votes = HashMap::new();
+        votes.insert(7, -1);
+        let ticket = Ticket {
+            id: 1,
+            title: "say \"hi\"\n\u{1F600}".to_string(),
+            price: 2.0,
+            tags: vec!["a".to_string(), "b".to_string()],
+            owner: None,
+            status: Status::Closed {
+                reason: "done".to_string(),
+            },
+            votes,
+        };
+
+        let text = to_string(&ticket).unwrap();
+        assert_eq!(from_str::<Ticket>(&text).unwrap(), ticket);
+        let pretty = to_string_pretty(&ticket).unwrap();
+        assert_eq!(from_str::<Ticket>(&pretty).unwrap(), ticket);
+
+        for status in [Status::Open, Status::Moved(3)] {
+            let text = to_string(&status).unwrap();
+            assert_eq!(from_str::<Status>(&text).unwrap(), status);
+        }
+    }
+
+    #[test]
+    fn test_parse_and_print() {
+        let value = parse(r#" {"b": [1, -2, 3.5e1, true, null], "a": "\u00e9\ud83d\ude00"} "#).unwrap();
+        assert_eq!(value.get("a").unwrap().as_str(), Some("é😀"));
+        assert_eq!(value.to_string(), r#"{"a":"é😀","b":[1,-2,35.0,true,null]}"#);
+
+        assert!(parse("{\"a\":1,}").is_err());
+        assert!(parse("[1 2]").is_err());
+        assert!(parse("\"\\ud800\"").is_err());
+        assert!(parse("1 x").is_err());
+    }
+
+    #[test]
+    fn test_fields_match_by_name() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Narrow {
+            title: String,
+            id: u64,
+        }
+
+        // field order and unknown fields do not matter
+        let narrow: Narrow = from_str(r#"{"extra": [1], "title": "x", "id": 9}"#).unwrap();
+        assert_eq!(
+            narrow,
+            Narrow {
+                title: "x".to_string(),
+                id: 9
+            }
+        );
+    }
+}